@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::parser::*;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum GenerateError {
+    /// There are no syllable patterns to generate words from
+    NoSyllables,
+    UnknownRef(String),
+    /// A class without any members to pick from
+    EmptyClass(String),
+    /// A repeater allowing more repetitions at least than at most
+    InvalidRepetitions(Repetitions),
+    /// The chain of classes leading back to a class that contains itself
+    CyclicRef(Vec<String>),
+    /// An element words can't be generated from, like a feature matrix
+    Unsupported(Element),
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GenerateError::NoSyllables => {
+                write!(f, "The rules don't declare any syllable patterns")
+            }
+            GenerateError::UnknownRef(name) => write!(f, "Unknown class `@{}`", name),
+            GenerateError::EmptyClass(name) => write!(f, "Class `@{}` has no members", name),
+            GenerateError::InvalidRepetitions(Repetitions { min, max }) => write!(
+                f,
+                "Repeater allows at least {} but at most {} repetitions",
+                min,
+                max.unwrap_or_default()
+            ),
+            GenerateError::CyclicRef(chain) => write!(f, "Cyclic class: @{}", chain.join(" -> @")),
+            GenerateError::Unsupported(e) => {
+                write!(
+                    f,
+                    "Can't generate words from syllable pattern element {:?}",
+                    e
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// How many times more than its minimum an unbounded repeater is repeated at most
+const MAX_EXTRA_REPETITIONS: usize = 2;
+
+/// Generates random words following the syllable patterns of a rule file
+#[derive(Debug)]
+pub struct WordGenerator {
+    classes: HashMap<String, Vec<Element>>,
+    patterns: Vec<SyllablePattern>,
+}
+
+impl WordGenerator {
+    /// Uses the last syllable declaration, checking every pattern can be generated from
+    pub fn from_ast(ast: &[Stmt]) -> Result<WordGenerator, GenerateError> {
+        let mut classes = HashMap::new();
+        let mut patterns = vec![];
+        for stmt in ast {
+            match stmt {
+                Stmt::ClassDecl(ClassDecl { name, elements }) => {
+                    classes.insert(name.clone(), elements.clone());
+                }
+                Stmt::SyllableDecl(SyllableDecl::Patterns(ps)) => patterns = ps.clone(),
+                Stmt::SyllableDecl(_) => patterns = vec![],
+                _ => {}
+            }
+        }
+        if patterns.is_empty() {
+            return Err(GenerateError::NoSyllables);
+        }
+
+        let gen = WordGenerator { classes, patterns };
+        for p in &gen.patterns {
+            match p {
+                SyllablePattern::Structured {
+                    onset,
+                    nucleus,
+                    coda,
+                } => {
+                    gen.check(onset, &mut vec![])?;
+                    gen.check(nucleus, &mut vec![])?;
+                    if let Some(coda) = coda {
+                        gen.check(coda, &mut vec![])?;
+                    }
+                }
+                SyllablePattern::Plain(e) => gen.check(e, &mut vec![])?,
+            }
+        }
+        Ok(gen)
+    }
+
+    fn check(&self, e: &Element, refs: &mut Vec<String>) -> Result<(), GenerateError> {
+        match e {
+            Element::Text(_) | Element::Empty => Ok(()),
+            Element::Ref(name) => {
+                if refs.contains(name) {
+                    refs.push(name.clone());
+                    return Err(GenerateError::CyclicRef(refs.clone()));
+                }
+                let class = self
+                    .classes
+                    .get(name)
+                    .ok_or_else(|| GenerateError::UnknownRef(name.clone()))?;
+                if class.is_empty() {
+                    return Err(GenerateError::EmptyClass(name.clone()));
+                }
+                refs.push(name.clone());
+                for e in class {
+                    self.check(e, refs)?;
+                }
+                refs.pop();
+                Ok(())
+            }
+            Element::Sequence(es) | Element::List(es) => {
+                es.iter().try_for_each(|e| self.check(e, refs))
+            }
+            Element::Repeater(_, reps) if reps.max.is_some_and(|max| max < reps.min) => {
+                Err(GenerateError::InvalidRepetitions(*reps))
+            }
+            Element::Group(e) | Element::Repeater(e, _) => self.check(e, refs),
+            _ => Err(GenerateError::Unsupported(e.clone())),
+        }
+    }
+
+    /// Generates a word of `syllables` syllables. `pick(n)` has to return a random number below `n`
+    pub fn generate(&self, syllables: usize, pick: &mut impl FnMut(usize) -> usize) -> String {
+        let mut word = String::new();
+        for _ in 0..syllables {
+            match &self.patterns[pick(self.patterns.len())] {
+                SyllablePattern::Structured {
+                    onset,
+                    nucleus,
+                    coda,
+                } => {
+                    self.element(onset, pick, &mut word);
+                    self.element(nucleus, pick, &mut word);
+                    if let Some(coda) = coda {
+                        self.element(coda, pick, &mut word);
+                    }
+                }
+                SyllablePattern::Plain(e) => self.element(e, pick, &mut word),
+            }
+        }
+        word
+    }
+
+    fn element(&self, e: &Element, pick: &mut impl FnMut(usize) -> usize, out: &mut String) {
+        match e {
+            Element::Text(t) => out.push_str(t),
+            Element::Ref(name) => {
+                let class = &self.classes[name];
+                self.element(&class[pick(class.len())], pick, out);
+            }
+            Element::Sequence(es) => {
+                for e in es {
+                    self.element(e, pick, out);
+                }
+            }
+            Element::List(es) => self.element(&es[pick(es.len())], pick, out),
+            Element::Group(e) => self.element(e, pick, out),
+            Element::Repeater(e, reps) => {
+                let max = reps.max.unwrap_or(reps.min + MAX_EXTRA_REPETITIONS);
+                let count = reps.min + pick(max - reps.min + 1);
+                for _ in 0..count {
+                    self.element(e, pick, out);
+                }
+            }
+            // Anything else is rejected by `check`
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(src: &str) -> Result<WordGenerator, GenerateError> {
+        WordGenerator::from_ast(&lsc::lsc_file(src).unwrap())
+    }
+
+    #[test]
+    fn test_generate() {
+        let gen = generator(
+            "Class cons {p, t, k}\nClass vowel {a, i}\n\
+             Syllables:\n    @cons? :: @vowel :: n?\n",
+        )
+        .unwrap();
+        assert_eq!(gen.generate(2, &mut |_| 0), "aa");
+        assert_eq!(gen.generate(2, &mut |n| n - 1), "kinkin");
+
+        // Pattern, onset count, onset, nucleus and coda count of each syllable
+        let mut choices = [0, 1, 1, 0, 1, 0, 0, 1, 0].into_iter();
+        let mut script = |n: usize| {
+            let c = choices.next().unwrap();
+            assert!(c < n);
+            c
+        };
+        assert_eq!(gen.generate(2, &mut script), "tani");
+    }
+
+    #[test]
+    fn test_generate_errors() {
+        assert_eq!(
+            generator("Feature +voice\n").unwrap_err(),
+            GenerateError::NoSyllables
+        );
+        assert_eq!(
+            generator("Syllables:\n    @cons :: a\n").unwrap_err(),
+            GenerateError::UnknownRef("cons".to_string())
+        );
+        assert_eq!(
+            generator("Class a {x, @b}\nClass b {@a}\nSyllables:\n    @a\n")
+                .unwrap_err()
+                .to_string(),
+            "Cyclic class: @a -> @b -> @a"
+        );
+        assert_eq!(
+            generator("Class x {}\nSyllables:\n    a @x\n").unwrap_err(),
+            GenerateError::EmptyClass("x".to_string())
+        );
+        assert_eq!(
+            generator("Syllables:\n    a*(3-1)\n").unwrap_err(),
+            GenerateError::InvalidRepetitions(Repetitions {
+                min: 3,
+                max: Some(1)
+            })
+        );
+        assert!(matches!(
+            generator("Syllables:\n    [+cons] :: a\n").unwrap_err(),
+            GenerateError::Unsupported(Element::Matrix(_))
+        ));
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::parser::*;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, peg::error::ParseError<peg::str::LineCol>),
+    /// The chain of files leading back to a file that's already being included
+    Cycle(Vec<PathBuf>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "Failed to read {}: {}", path.display(), err),
+            LoadError::Parse(path, err) => {
+                write!(f, "Failed to parse {}: {}", path.display(), err)
+            }
+            LoadError::Cycle(chain) => {
+                write!(f, "Cyclic include: ")?;
+                for (i, path) in chain.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Parses the rule file at `path`, replacing every `include` with the statements of the included
+/// file
+pub fn load_lsc(path: &Path) -> Result<Vec<Stmt>, LoadError> {
+    let mut stmts = vec![];
+    load_into(path, &mut vec![], &mut stmts)?;
+    Ok(stmts)
+}
+
+fn load_into(path: &Path, stack: &mut Vec<PathBuf>, out: &mut Vec<Stmt>) -> Result<(), LoadError> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| LoadError::Io(path.to_path_buf(), e))?;
+    if stack.contains(&path) {
+        let mut chain = stack.clone();
+        chain.push(path);
+        return Err(LoadError::Cycle(chain));
+    }
+    let src = fs::read_to_string(&path).map_err(|e| LoadError::Io(path.clone(), e))?;
+    let stmts = lsc::lsc_file(&src).map_err(|e| LoadError::Parse(path.clone(), e))?;
+
+    stack.push(path);
+    let base = stack.last().unwrap().parent().unwrap_or(Path::new("")).to_path_buf();
+    for stmt in stmts {
+        match stmt {
+            Stmt::Include(inc) => load_into(&base.join(inc), stack, out)?,
+            stmt => out.push(stmt),
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for a test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("sc-include-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_two_file_include() {
+        let dir = TempDir::new("two-file");
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        fs::write(dir.join("shared/common.lsc"), "Feature +voiced, +nasal\n").unwrap();
+        fs::write(dir.join("lang.lsc"), "include \"shared/common.lsc\"\nFeature +stop\n").unwrap();
+
+        let stmts = load_lsc(&dir.join("lang.lsc")).unwrap();
+        let plus = |name: &str| PlusFeature { syllable: false, plus: true, name: name.to_string() };
+        assert_eq!(stmts, vec![
+            Stmt::FeatureDecl(FeatureDecl::Plus(vec![plus("voiced"), plus("nasal")])),
+            Stmt::FeatureDecl(FeatureDecl::Plus(vec![plus("stop")])),
+        ]);
+    }
+
+    #[test]
+    fn test_cyclic_include() {
+        let dir = TempDir::new("cycle");
+        fs::write(dir.join("a.lsc"), "include \"b.lsc\"\n").unwrap();
+        fs::write(dir.join("b.lsc"), "include \"a.lsc\"\n").unwrap();
+
+        match load_lsc(&dir.join("a.lsc")) {
+            Err(LoadError::Cycle(chain)) => assert_eq!(chain.len(), 3),
+            r => panic!("expected an include cycle, got {:?}", r),
+        }
+    }
+}
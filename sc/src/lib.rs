@@ -1 +1,4 @@
-mod parser;
+pub mod parser;
+pub mod include;
+pub mod sc;
+pub mod generate;
@@ -1,52 +1,227 @@
 #[derive(Debug, PartialEq, Eq)]
-enum Stmt {
-    FeatureDecl,
+pub enum Stmt {
+    FeatureDecl(FeatureDecl),
     DiacriticDecl,
-    SymbolDecl,
-    ClassDecl,
+    /// `Symbol tʃ, dʒ` or `Symbol m [+nasal labial]`, the matrix being empty for the former
+    SymbolDecl(Vec<String>, Vec<MatrixValue>),
+    ClassDecl(ClassDecl),
     ElementDecl,
-    SyllableDecl,
-    Demonanizer,
-    InterRomanizer,
-    Romanizer,
-    ChangeRule,
-    StandardExpression,
+    SyllableDecl(SyllableDecl),
+    /// `Deromanizer:`, turning romanized input into phonetic forms before any rule applies. The
+    /// flag is set for `Deromanizer Literal:`
+    Deromanizer(bool, Block),
+    /// `Romanizer-name:`, romanizing the words as they are before the named rule
+    InterRomanizer(String, bool, Block),
+    /// `Romanizer:`, turning the final phonetic forms into romanized ones
+    Romanizer(bool, Block),
+    ChangeRule(ChangeRule),
+    StandardExpression(StandardExpression),
+    /// `include "path"`, resolved relative to the including file
+    Include(String),
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct ChangeRule {
-    name: String,
-    block: Block
+pub enum FeatureDecl {
+    /// `Feature +voiced, +nasal`
+    Plus(Vec<PlusFeature>),
+    /// `Feature height (*low, mid, high)`
+    Valued {
+        syllable: bool,
+        name: String,
+        /// The value used when the feature is absent, marked by `*`
+        null_alias: Option<String>,
+        values: Vec<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PlusFeature {
+    pub syllable: bool,
+    /// Declared with a `+`, making the feature binary (absent means `-`)
+    pub plus: bool,
+    pub name: String,
+}
+
+/// `Class vowel {a, e, i, @long}`
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClassDecl {
+    pub name: String,
+    /// Either `Element::Text` or `Element::Ref`
+    pub elements: Vec<Element>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyllableDecl {
+    Explicit,
+    Clear,
+    /// The changes and environments of the patterns aren't represented in the AST yet
+    Patterns(Vec<SyllablePattern>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SyllablePattern {
+    /// `onset :: nucleus :: coda`, the coda being optional
+    Structured {
+        onset: Element,
+        nucleus: Element,
+        coda: Option<Element>,
+    },
+    /// A pattern matching a whole syllable
+    Plain(Element),
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum Block {
+pub struct ChangeRule {
+    pub name: String,
+    pub modifiers: Vec<Modifier>,
+    pub block: Block
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Modifier {
+    Ltr,
+    Rtl,
+    /// Reapply the rule until the word stops changing
+    Propagate,
+    Defer,
+    Cleanup,
+    /// Filters and named modifiers, which aren't represented in the AST yet
+    Unsupported(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Block {
     BasicBlock {
-        expressions: Vec<Expr>
+        expressions: Vec<Expression>
     },
-    // ThenBlock {
-    //     first: Box<Block>,
-    //     then: Box<Block>,
-    // },
-    // ElseBlock {
-    //     first: Box<Block>,
-    //     else_: Box<Block>,
-    // }
+    /// `first` followed by `Then:`, applying `then` where `first` matched
+    ThenBlock {
+        first: Box<Block>,
+        then: Box<Block>,
+    },
+    /// `first` followed by `Else:`, applying `else_` where `first` didn't match
+    ElseBlock {
+        first: Box<Block>,
+        else_: Box<Block>,
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct Expr {
+pub enum Expression {
+    /// `unchanged`: explicitly leaves the input as is
+    Unchanged,
+    /// `off`: disables the rule
+    Off,
+    /// `:name`: applies the named rule
+    BlockRef(String),
+    Standard(StandardExpression),
+}
+
+/// `from => to / environment`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StandardExpression {
+    pub from: Element,
+    pub to: Element,
+    pub environment: CompoundEnvironment,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct CompoundEnvironment {
+    /// Any one of these must hold for the expression to apply
+    pub conditions: Vec<Environment>,
+    /// None of these may hold for the expression to apply
+    pub exclusions: Vec<Environment>,
+}
+
+/// `before _ after`
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Environment {
+    pub before: Option<Element>,
+    pub after: Option<Element>,
+}
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Element {
+    /// Literal graphemes
+    Text(String),
+    /// `[+voice -nasal]`
+    Matrix(Vec<FancyValue>),
+    /// `*`, matching nothing
+    Empty,
+    /// `.`
+    SyllableBoundary,
+    /// `$`
+    WordBoundary,
+    /// `$$`, between two words of a phrase
+    BetweenWords,
+    /// `<Syl>`, any one syllable
+    AnySyllable,
+    /// `@name`, a class or element declared elsewhere
+    Ref(String),
+    /// Whitespace separated elements, matched one after the other
+    Sequence(Vec<Element>),
+    /// `(a b)`
+    Group(Box<Element>),
+    /// `{p, t, k}`, matching any one of the elements
+    List(Vec<Element>),
+    /// `a+`, `a*`, `a?`, `a*2` or `a*(1-3)`
+    Repeater(Box<Element>, Repetitions),
+    /// `(@cons)$1`, binding what the element matched to a numbered slot. `~$1` matches the slot
+    /// inexactly, by the features of symbols declared with a matrix and ignoring the diacritics of
+    /// the rest
+    Capture { element: Box<Element>, index: u32, inexact: bool },
+    /// `$1`, whatever was bound to the slot
+    CaptureRef { index: u32, inexact: bool },
+    /// Syntax that isn't represented in the AST yet, kept as its source text
+    Unsupported(String),
 }
 
+/// How often a repeated element may match
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Repetitions {
+    pub min: usize,
+    /// `None` when unbounded
+    pub max: Option<usize>,
+}
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Sign {
+    Plus,
+    Minus,
+}
+
+/// `+voice`, `-voice` or a plain value like `labial`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MatrixValue {
+    pub sign: Option<Sign>,
+    pub name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FancyValue {
+    Value(MatrixValue),
+    /// `$place`, requiring the feature to have the same value everywhere it's used in a rule
+    Variable(String),
+    /// `!+voice` or `!labial`, matching anything without that value
+    Negated(MatrixValue),
+    /// Syntax that isn't represented in the AST yet, kept as its source text
+    Unsupported(String),
+}
 
 peg::parser!{
-  grammar lsc() for str {
+  pub grammar lsc() for str {
     // lscFile: (WHITESPACE | NEWLINE*) statement? (NEWLINE+ statement)* (WHITESPACE | NEWLINE*) EOF;
     pub rule lsc_file() -> Vec<Stmt>
       = _ r:statement()* { r }
 
+    // Not part of Lexurgy, for `parse_lsc_with_docs`: each statement along with where it starts
+    pub rule lsc_file_positions() -> Vec<(usize, Stmt)>
+      = _ r:(p:position!() s:statement() { (p, s) })* { r }
+
+    // Not part of Lexurgy, for `parse_statement`
+    pub rule single_statement() -> Stmt
+      = _ s:statement() _ { s }
+
     // fragment COMMENT_START: '#';
     // COMMENT: (WHITESPACE? COMMENT_START ~[\n\r]*) -> skip;
     rule comment() = quiet!{ whitespace()? "#" [^ '\n' | '\r']* }
@@ -55,15 +230,20 @@ peg::parser!{
     // NEWLINE: WHITESPACE? ('\r\n' | '\n') WHITESPACE?;
     rule newline() = quiet!{ ['\r' | '\n'] }
     rule _ = (comment()? whitespace())*
+    // Whitespace within a single line
+    rule ws() = quiet!{ [' ' | '\t']+ }
+    rule hs() = quiet!{ [' ' | '\t']* }
     // NUMBER: DIGIT+;
     rule number() = ['0'..='9']+
     // NAME: CHAR+;
     rule sname() = ['A'..='Z' | 'a'..='z' | '0'..='9']+
     // fragment ANY: ('\\' .) | ~[ \\,.=>()*[\]{}+?/\-_:!~$@#&\n\r];
-    rule any() = ("\\" [_]) / [^ '\\' | ',' | '.' | '=' | '>' | '(' | ')'
+    rule any() = ("\\" escaped()) / [^ '\\' | ',' | '.' | '=' | '>' | '(' | ')'
                                |  '*' | '[' | ']' | '{' | '}' | '+' | '?'
                                |  '/' | '-' | '_' | ':' | '!' | '~' | '$'
-                               |  '@' | '#' | '&' | '\n' | '\r' | ' ' ]
+                               |  '@' | '#' | '&' | '\n' | '\r' | ' ' | '\t' ]
+    // A backslash has to escape something on the same line, failing right after it otherwise
+    rule escaped() = quiet!{ [^ '\n' | '\r'] } / expected!("a character to escape after `\\`")
     // STR1: ANY;
     rule sstr1() = any()
     // STR: ANY+;
@@ -101,18 +281,24 @@ peg::parser!{
     // statement:
     //   featureDecl | diacriticDecl | symbolDecl | classDecl | elementDecl | syllableDecl |
     //   deromanizer | interRomanizer | romanizer | changeRule | standardExpression;
-    rule statement() -> Stmt = featureDecl() / diacriticDecl() / symbolDecl() / classDecl()
+    rule statement() -> Stmt = include() / featureDecl() / diacriticDecl() / symbolDecl() / classDecl()
                      / elementDecl() / syllableDecl() / deromanizer() / interRomanizer()
-                     / romanizer() / changeRule() / standardExpression()
+                     / romanizer() / changeRule() / e:standardExpression() _ { Stmt::StandardExpression(e) }
+
+    // Not part of Lexurgy, lets rule files share common declarations:
+    // include: INCLUDE WHITESPACE '"' ~["\r\n]* '"';
+    rule include() -> Stmt = ("Include" / "include") _ "\"" p:$([^ '"' | '\n' | '\r']*) "\"" _
+      { Stmt::Include(p.to_string()) }
 
     // elementDecl: ELEMENT_DECL WHITESPACE name WHITESPACE ruleElement;
     // ELEMENT_DECL: 'Element' | 'element';
     rule elementDecl() -> Stmt = ("Element" / "element") _ name() _ ruleElement() { Stmt::ElementDecl }
 
     // classDecl: CLASS_DECL WHITESPACE name WHITESPACE (CLASS_START | LIST_START) classElement ((CLASS_SEP | LIST_SEP) classElement)* CLASS_SEP? LIST_END;
-    rule classDecl() -> Stmt = ("Class" / "class") _ name() _ "{" _ classElement() ** ("," _) ","? "}" _ { Stmt::ClassDecl }
+    rule classDecl() -> Stmt = ("Class" / "class") _ name:featureValue() _ "{" _ elements:classElement() ** ("," _) ","? _ "}" _
+      { Stmt::ClassDecl(ClassDecl { name, elements }) }
     // classElement: elementRef | text;
-    rule classElement() = elementRef() / text()
+    rule classElement() -> Element = n:elementRef() { Element::Ref(n) } / t:text() { Element::Text(t) }
 
     // featureDecl:
     //     FEATURE_DECL WHITESPACE (
@@ -121,17 +307,20 @@ peg::parser!{
     //     );
     // featureModifier: SYLLABLE_FEATURE;
     rule featureDecl() -> Stmt =
-        ("Feature" / "feature") _ (
-            plusFeature() ** ("," _ )
-            / (featureModifier()? name() _ "(" _ (nullAlias() "," _)? featureValue() ++ ("," _) ")" _)
-        ) { Stmt::FeatureDecl }
+        ("Feature" / "feature") _ f:(
+            (syllable:featureModifier() name:featureValue() _ "(" _ null_alias:(a:nullAlias() "," _ { a })? values:featureValue() ++ ("," _) _ ")" _
+              { FeatureDecl::Valued { syllable, name, null_alias, values } })
+            / (fs:plusFeature() ++ ("," _) { FeatureDecl::Plus(fs) })
+        ) { Stmt::FeatureDecl(f) }
 
     // plusFeature: (featureModifier WHITESPACE)? AT_LEAST_ONE? name;
-    rule plusFeature() = featureModifier()? "+"? _ name() _
-    rule featureModifier() = "(Syllable)" / "(syllable)"
+    rule plusFeature() -> PlusFeature =
+        syllable:featureModifier() plus:"+"? _ name:featureValue() _
+        { PlusFeature { syllable, plus: plus.is_some(), name } }
+    rule featureModifier() -> bool = m:(("(Syllable)" / "(syllable)") _)? { m.is_some() }
 
     // nullAlias: NULL featureValue;
-    rule nullAlias() = "*" _ featureValue()
+    rule nullAlias() -> String = "*" _ v:featureValue() { v }
 
     // diacriticDecl:
     //     DIACRITIC_DECL WHITESPACE text WHITESPACE
@@ -141,166 +330,247 @@ peg::parser!{
     // diacriticModifier: DIA_BEFORE | DIA_FIRST | DIA_FLOATING;
     rule diacriticModifier() = ("(Before)" / "(before)" / "(First)" / "(first)" / "(Floating)" / "(floating)") _
     // symbolDecl: SYMBOL_DECL WHITESPACE symbolName ((LIST_SEP symbolName)* | WHITESPACE matrix);
-    rule symbolDecl() -> Stmt = ("Symbol" / "symbol") _ symbolName() _ (("," _ symbolName())* / matrix()) { Stmt::SymbolDecl }
+    rule symbolDecl() -> Stmt = ("Symbol" / "symbol") _ first:symbolName() _ rest:(
+          rest:("," _ s:symbolName() { s })+ { (rest, vec![]) }
+        / m:matrix() { (vec![], m) }
+        / { (vec![], vec![]) })
+      { Stmt::SymbolDecl(std::iter::once(first).chain(rest.0).collect(), rest.1) }
     // symbolName: text;
-    rule symbolName() = text() _
+    rule symbolName() -> String = t:text() _ { t }
 
     // syllableDecl:
     //     SYLLABLE_DECL RULE_START (NEWLINE+ (EXPLICIT_SYLLABLES | CLEAR_SYLLABLES) | (NEWLINE+ syllableExpression)+);
-    rule syllableDecl() -> Stmt = ("Syllable" / "syllable") _ ":" _ (("Explicit" / "explicit") _ / ("Clear" / "clear") _ / syllableExpression()+) { Stmt::SyllableDecl }
+    // (A pattern can't look like the start of the next statement)
+    rule syllableDecl() -> Stmt = ("Syllables" / "syllables" / "Syllable" / "syllable") hs() ":" _ d:(
+          ("Explicit" / "explicit") !nameChar() _ { SyllableDecl::Explicit }
+        / ("Clear" / "clear") !nameChar() _ { SyllableDecl::Clear }
+        / ps:(!declKeyword() !ruleHeader() p:syllableExpression() _ { p })+ { SyllableDecl::Patterns(ps) }
+      ) { Stmt::SyllableDecl(d) }
+    rule declKeyword() = ("Feature" / "feature" / "Diacritic" / "diacritic" / "Symbol" / "symbol"
+                         / "Class" / "class" / "Element" / "element" / "Syllables" / "syllables"
+                         / "Deromanizer" / "deromanizer" / "Romanizer" / "romanizer"
+                         / "Include" / "include") !nameChar()
+    rule ruleHeader() = ruleName() (hs() changeRuleModifier())* hs() ":" !":"
 
     // syllableExpression: syllablePattern (CHANGE matrix)? compoundEnvironment?;
-    rule syllableExpression() = syllablePattern() ("=>" _ matrix())? compoundEnvironment()?
+    rule syllableExpression() -> SyllablePattern = p:syllablePattern() (hs() "=>" _ matrix())? compoundEnvironment() { p }
 
     // syllablePattern: structuredPattern | ruleElement;
-    rule syllablePattern() = structuredPattern() / ruleElement()
+    rule syllablePattern() -> SyllablePattern = structuredPattern() / e:ruleElement() { SyllablePattern::Plain(e.0) }
 
     // structuredPattern:
     //     (reluctantOnset QMARK_COLON)?
     //     unconditionalRuleElement DOUBLE_COLON
     //     unconditionalRuleElement (DOUBLE_COLON unconditionalRuleElement)?;
-    rule structuredPattern() = (reluctantOnset() "?:" _)? unconditionalRuleElement() "::" _ unconditionalRuleElement() ("::" _ unconditionalRuleElement())?
+    rule structuredPattern() -> SyllablePattern =
+      (reluctantOnset() hs() "?:" hs())? onset:unconditionalRuleElement() hs() "::" hs() nucleus:unconditionalRuleElement()
+        coda:(hs() "::" hs() e:unconditionalRuleElement() { e })?
+      { SyllablePattern::Structured { onset, nucleus, coda } }
     // reluctantOnset: unconditionalRuleElement;
     rule reluctantOnset() = unconditionalRuleElement()
 
     // deromanizer: DEROMANIZER (WHITESPACE LITERAL)? RULE_START NEWLINE+ block;
-    rule deromanizer() -> Stmt = ("Deromanizer" / "deromanizer") _ ("Literal" / "literal") _ ":" _ block() { Stmt::Demonanizer }
+    rule deromanizer() -> Stmt = ("Deromanizer" / "deromanizer") l:literal() hs() ":" _ b:block() { Stmt::Deromanizer(l, b) }
+    rule literal() -> bool = l:(hs() ("Literal" / "literal") !nameChar())? { l.is_some() }
 
     // romanizer: ROMANIZER (WHITESPACE LITERAL)? RULE_START NEWLINE+ block;
-    rule romanizer() -> Stmt = ("Romanizer" / "romanizer") _ ("Literal" / "literal") _ ":" _ block() { Stmt::Romanizer }
+    rule romanizer() -> Stmt = ("Romanizer" / "romanizer") l:literal() hs() ":" _ b:block() { Stmt::Romanizer(l, b) }
     // interRomanizer: ROMANIZER HYPHEN ruleName (WHITESPACE LITERAL)? RULE_START NEWLINE+ block;
-    rule interRomanizer() -> Stmt = ("Romanizer-" / "romanizer-") ruleName() _ ("Literal" / "literal") _ ":" _ block() { Stmt::InterRomanizer }
+    rule interRomanizer() -> Stmt = ("Romanizer-" / "romanizer-") n:ruleName() l:literal() hs() ":" _ b:block() { Stmt::InterRomanizer(n, l, b) }
 
     // changeRule: ruleName (WHITESPACE changeRuleModifier)* RULE_START? NEWLINE+ block;
     rule changeRule() -> Stmt =
-      name:ruleName() _ (changeRuleModifier())* ":"? _ b:block()
-      { Stmt::ChangeRule(ChangeRule { name, block: b }) }
+      name:ruleName() modifiers:(hs() m:changeRuleModifier() { m })* hs() ":"? _ b:block()
+      { Stmt::ChangeRule(ChangeRule { name, modifiers, block: b }) }
 
     // filter: elementRef | fancyMatrix;
-    rule filter() = elementRef() / fancyMatrix()
+    rule filter() = elementRef() {} / fancyMatrix() {}
 
     // block: blockElement (NEWLINE+ blockType RULE_START (WHITESPACE | NEWLINE+) blockElement)*;
-    rule block() = blockElement() _ (blockType() ":" _ blockElement())*
+    // (Chained blocks group to the left)
+    rule block() -> Block = first:blockElement() _ rest:(t:blockType() ":" _ b:blockElement() _ { (t, b) })*
+      {
+        rest.into_iter().fold(first, |first, (then, b)| if then {
+            Block::ThenBlock { first: Box::new(first), then: Box::new(b) }
+        } else {
+            Block::ElseBlock { first: Box::new(first), else_: Box::new(b) }
+        })
+      }
 
     // blockElement: expressionList | O_PAREN NEWLINE* block NEWLINE* C_PAREN;
-    rule blockElement() = expressionList() / "(" _ block() ")" _
+    rule blockElement() -> Block = expressions:expressionList() { Block::BasicBlock { expressions } }
+      / "(" _ b:block() ")" { b }
 
     // blockType: (ALL_MATCHING | FIRST_MATCHING) (WHITESPACE changeRuleModifier)*;
-    rule blockType() = (("Then" / "then") / ("Else" / "else")) _ changeRuleModifier()*
+    // (Returns whether it's `Then`, block modifiers aren't represented in the AST yet)
+    rule blockType() -> bool = t:(("Then" / "then") { true } / ("Else" / "else") { false }) (hs() changeRuleModifier() {})* hs() { t }
 
     // changeRuleModifier: filter | keywordModifier;
-    rule changeRuleModifier() = filter() / keywordModifier()
+    rule changeRuleModifier() -> Modifier = s:$(filter()) { Modifier::Unsupported(s.to_string()) } / keywordModifier()
 
     // keywordModifier: LTR | RTL | PROPAGATE | BLOCK | CLEANUP | NAME;
-    rule keywordModifier() = (("ltr" / "Ltr") / ("Rtl" / "Rtl") / ("Propagate" / "propagate") / ("Defer" / "defer") / ("Cleanup" / "cleanup")) _ / name()
+    rule keywordModifier() -> Modifier = ("LTR" / "Ltr" / "ltr") !nameChar() { Modifier::Ltr }
+                                       / ("RTL" / "Rtl" / "rtl") !nameChar() { Modifier::Rtl }
+                                       / ("Propagate" / "propagate") !nameChar() { Modifier::Propagate }
+                                       / ("Defer" / "defer") !nameChar() { Modifier::Defer }
+                                       / ("Cleanup" / "cleanup") !nameChar() { Modifier::Cleanup }
+                                       / s:$(name()) { Modifier::Unsupported(s.to_string()) }
     // expressionList: expression (NEWLINE+ expression)*;
-    rule expressionList() = expression()*
+    rule expressionList() -> Vec<Expression> = expression() ++ _
     // ruleName: name (HYPHEN (name | NUMBER))*;
     rule ruleName() -> String = n:$(name() ("-" (name() / number()))*) { n.to_string() }
     // expression: keywordExpression | blockRef | standardExpression;
-    rule expression() = keywordExpression() / blockRef() / standardExpression()
+    rule expression() -> Expression = keywordExpression() / blockRef()
+                                    / e:standardExpression() { Expression::Standard(e) }
     // keywordExpression: UNCHANGED | OFF;
-    rule keywordExpression() = ("Unchanged" / "Unchanged") / ("Off" / "off") _
+    rule keywordExpression() -> Expression = ("Unchanged" / "unchanged") !nameChar() { Expression::Unchanged }
+                                           / ("Off" / "off") !nameChar() { Expression::Off }
+    rule nameChar() = ['A'..='Z' | 'a'..='z' | '0'..='9' | '-']
     // blockRef: RULE_START ruleName;
-    rule blockRef() = ":" ruleName()
+    rule blockRef() -> Expression = ":" n:ruleName() { Expression::BlockRef(n) }
     // standardExpression: from CHANGE to compoundEnvironment?;
     // from: ruleElement;
     // to: unconditionalRuleElement;
-    rule standardExpression() -> Stmt = ruleElement() "=>" _ unconditionalRuleElement() compoundEnvironment()? { Stmt::StandardExpression }
+    rule standardExpression() -> StandardExpression =
+      from:ruleElement() hs() "=>" _ to:unconditionalRuleElement() env:compoundEnvironment()
+      {
+        let (from, mut environment) = from;
+        environment.conditions.extend(env.conditions);
+        environment.exclusions.extend(env.exclusions);
+        StandardExpression { from, to, environment }
+      }
 
     // ruleElement: unconditionalRuleElement compoundEnvironment?;
-    rule ruleElement() = unconditionalRuleElement() compoundEnvironment()?
+    rule ruleElement() -> (Element, CompoundEnvironment) = e:unconditionalRuleElement() env:compoundEnvironment() { (e, env) }
     // unconditionalRuleElement: bounded | interfix | negated | postfix | simple | sequence;
-    rule unconditionalRuleElement() = bounded() / interfix() / negated() / postfix() / simple() / sequence()
+    rule unconditionalRuleElement() -> Element = sequence() / freeElement()
 
     // // "Bounded" elements have a clear start and end symbol
     // bounded: group | list;
     // group: O_PAREN ruleElement C_PAREN;
     // list: LIST_START ruleElement (LIST_SEP ruleElement)* LIST_END;
-    rule bounded() = "(" _ ruleElement() ")" _
-                   / "{" _ ruleElement() ++ ("," _) "}" _
+    // (Elements with their own environment aren't represented in the AST yet)
+    rule bounded() -> Element = "(" hs() e:unconditionalRuleElement() hs() ")" { Element::Group(Box::new(e)) }
+                              / "{" hs() es:unconditionalRuleElement() ++ ("," hs()) hs() "}" { Element::List(es) }
+                              / s:$("(" hs() ruleElement() hs() ")"
+                                    / "{" hs() ruleElement() ++ ("," hs()) hs() "}")
+                                { Element::Unsupported(s.to_string()) }
 
     // // "Free" elements have sub-elements floating free amid whitespace
     // sequence: freeElement (WHITESPACE freeElement)+;
     // freeElement: bounded | interfix | negated | postfix | simple;
-    rule sequence() = (bounded() / interfix() / negated() / postfix() / simple())+
+    rule sequence() -> Element = first:freeElement() rest:(ws() e:freeElement() { e })+
+      { Element::Sequence(std::iter::once(first).chain(rest).collect()) }
+    // Elements with an operator after a simpler element need to be tried before the simpler one
+    rule freeElement() -> Element = interfix() / postfix() / negated() / bounded() / simple()
 
     // compoundEnvironment: condition | exclusion | (condition exclusion);
-    rule compoundEnvironment() = condition() / exclusion() / (condition() exclusion())
+    rule compoundEnvironment() -> CompoundEnvironment = c:condition()? e:exclusion()?
+      { CompoundEnvironment { conditions: c.unwrap_or_default(), exclusions: e.unwrap_or_default() } }
 
     // condition: CONDITION (environment | environmentList);
-    rule condition() = "/" _ (environment() / environmentList())
+    rule condition() -> Vec<Environment> = hs() "/" !"/" hs() e:environments() { e }
     // exclusion: EXCLUSION (environment | environmentList);
-    rule exclusion() = "//" _ (environment() / environmentList())
+    rule exclusion() -> Vec<Environment> = hs() "//" hs() e:environments() { e }
+    rule environments() -> Vec<Environment> = environmentList() / e:environment() { vec![e] }
     // environmentList: LIST_START environment (LIST_SEP environment)* LIST_END;
-    rule environmentList() = "{" _ environment() ++ ("," _) "}" _
+    // (Not followed by an anchor, otherwise it's a list element before the anchor)
+    rule environmentList() -> Vec<Environment> = "{" hs() e:environment() ++ ("," hs()) hs() "}" !(hs() "_") { e }
     // environment:
     //     (environmentBefore WHITESPACE)? ANCHOR (WHITESPACE environmentAfter)?
     //     | environmentBefore?;
     // environmentBefore: unconditionalRuleElement;
     // environmentAfter: unconditionalRuleElement;
-    rule environment() = unconditionalRuleElement()? "_" _ unconditionalRuleElement()?
-                       / unconditionalRuleElement()
+    rule environment() -> Environment =
+        before:(e:unconditionalRuleElement() hs() { e })? "_" after:(hs() e:unconditionalRuleElement() { e })?
+          { Environment { before, after } }
+        / e:unconditionalRuleElement() { Environment { before: Some(e), after: None } }
 
     // // "Interfix" elements use a delimiter but no whitespace or boundary marker
     // interfix: interfixElement (interfixType interfixElement)+;
     // interfixType: INTERSECTION | INTERSECTION_NOT | TRANSFORMING;
     // interfixElement: bounded | negated | postfix | simple;
-    rule interfix() = interfixElement() (("&" / "!&" / ">") _ interfixElement())+
-    rule interfixElement() = bounded() / negated() / postfix() / simple()
+    rule interfix() -> Element = s:$(interfixElement() (("&!" / "&" / ">") interfixElement())+)
+      { Element::Unsupported(s.to_string()) }
+    rule interfixElement() -> Element = postfix() / negated() / bounded() / simple()
 
     // // "Prefix" elements use a prefix operator
     // negated: NEGATION (bounded | simple);
-    rule negated() = "!" (bounded() / simple())
+    rule negated() -> Element = s:$("!" (bounded() / simple())) { Element::Unsupported(s.to_string()) }
 
     // // "Postfix" elements use a postfix operator
     // postfix: capture | repeater;
-    rule postfix() = capture() / repeater()
+    rule postfix() -> Element = capture() / repeater()
     // capture: (bounded | negated | simple) captureRef;
-    rule capture() = (bounded() / negated() / simple()) captureRef()
+    rule capture() -> Element = e:(bounded() / negated() / simple()) r:captureRef()
+      { Element::Capture { element: Box::new(e), index: r.0, inexact: r.1 } }
+                             / s:$((bounded() / negated() / simple()) syllableCaptureRef())
+      { Element::Unsupported(s.to_string()) }
     // repeater: (bounded | simple) repeaterType;
-    rule repeater() = (bounded() / simple()) repeaterType()
+    rule repeater() -> Element = e:(bounded() / simple()) r:repeaterType()
+      { Element::Repeater(Box::new(e), r) }
 
     // // "Simple" elements can't have other elements inside them
     // simple: anySyllable | elementRef | captureRef | fancyMatrix | empty | sylBoundary | boundary | betweenWords | text;
     // anySyllable: ANY_SYLLABLE;
-    rule simple() = ("<Syl>" / "<syl>") _ / elementRef() / captureRef() / fancyMatrix() / empty()
-                  / ("." _) / ("$" _) / ("$$" _) / text()
+    rule simple() -> Element = ("<Syl>" / "<syl>") { Element::AnySyllable }
+                  / n:elementRef() { Element::Ref(n) }
+                  / r:captureRef() { Element::CaptureRef { index: r.0, inexact: r.1 } }
+                  / s:$(syllableCaptureRef()) { Element::Unsupported(s.to_string()) }
+                  / "$$" { Element::BetweenWords }
+                  / m:fancyMatrix() { Element::Matrix(m) }
+                  / empty() { Element::Empty }
+                  / "$" { Element::WordBoundary }
+                  / "." { Element::SyllableBoundary }
+                  / t:text() { Element::Text(t) }
     // elementRef: CLASSREF name;
-    rule elementRef() = "@" name()
+    rule elementRef() -> String = "@" n:featureValue() { n }
     // captureRef: INEXACT? WORD_BOUNDARY SYLLABLE_BOUNDARY? NUMBER;
-    rule captureRef() = "~"? "$" "."? number()
+    // (Syllable captures aren't represented in the AST yet)
+    rule captureRef() -> (u32, bool) = i:"~"? "$" n:$(number()) {? n.parse().map(|n| (n, i.is_some())).or(Err("capture number")) }
+    rule syllableCaptureRef() = "~"? "$" "." number()
 
     // fancyMatrix: MATRIX_START fancyValue? (WHITESPACE fancyValue)* MATRIX_END;
     // fancyValue: matrixValue | negatedValue | absentFeature | featureVariable;
-    rule fancyMatrix() = "[" _ (matrixValue() / negatedValue() / absentFeature() / featureVariable())* "]" _
+    rule fancyMatrix() -> Vec<FancyValue> = "[" hs() v:fancyValue() ** ws() hs() "]" { v }
+    rule fancyValue() -> FancyValue = v:matrixValue() { FancyValue::Value(v) }
+      / n:featureVariable() { FancyValue::Variable(n) }
+      / v:negatedValue() { FancyValue::Negated(v) }
+      / s:$(absentFeature()) { FancyValue::Unsupported(s.to_string()) }
     // negatedValue: NEGATION matrixValue;
-    rule negatedValue() = "!" matrixValue()
+    rule negatedValue() -> MatrixValue = "!" v:matrixValue() { v }
     // absentFeature: NULL name;
     rule absentFeature() = "*" name()
     // featureVariable: WORD_BOUNDARY name;
-    rule featureVariable() = "$" name()
+    rule featureVariable() -> String = "$" n:featureValue() { n }
 
     // empty: NULL;
-    rule empty() = "*" _
+    rule empty() = "*"
     // sylBoundary: SYLLABLE_BOUNDARY;
     // boundary: WORD_BOUNDARY;
     // betweenWords: BETWEEN_WORDS;
     // repeaterType: repeatRange | AT_LEAST_ONE | NULL | OPTIONAL;
-    rule repeaterType() = repeatRange() / "+" _ / "*" _ / "?" _
+    rule repeaterType() -> Repetitions = repeatRange()
+                                       / "+" { Repetitions { min: 1, max: None } }
+                                       / "*" { Repetitions { min: 0, max: None } }
+                                       / "?" { Repetitions { min: 0, max: Some(1) } }
     // repeatRange: NULL (NUMBER | (O_PAREN lowerBound? HYPHEN upperBound? C_PAREN));
     // lowerBound: NUMBER;
     // upperBound: NUMBER;
-    rule repeatRange() = "*" (number() / ("(" _ number()? "-" number()? ")" _))
+    rule repeatRange() -> Repetitions =
+        "*" n:count() { Repetitions { min: n, max: Some(n) } }
+      / "*(" hs() min:count()? "-" max:count()? hs() ")" { Repetitions { min: min.unwrap_or(0), max } }
+    rule count() -> usize = n:$(number()) {? n.parse().or(Err("number")) }
     // matrix: MATRIX_START matrixValue? (WHITESPACE matrixValue)* MATRIX_END;
-    rule matrix() = "[" _ matrixValue()* "]" _
+    rule matrix() -> Vec<MatrixValue> = "[" hs() v:matrixValue() ** ws() hs() "]" _ { v }
     // matrixValue: plusFeatureValue | featureValue;
     // plusFeatureValue: (AT_LEAST_ONE | HYPHEN) name;
     // featureValue: name;
-    rule matrixValue() = ("+" / "-")? name()
-    rule featureValue() = name()
+    rule matrixValue() -> MatrixValue = sign:("+" { Sign::Plus } / "-" { Sign::Minus })? name:featureValue()
+      { MatrixValue { sign, name } }
+    rule featureValue() -> String = n:$(name()) { n.to_string() }
     // text: (name | STR1 | STR) NEGATION?;
-    rule text() = name() / sstr() "!"?
+    rule text() -> String = t:$(sstr() / name()) "!"? { unescape(t) }
     // name:
     //     NAME |
     //     ELEMENT_DECL | CLASS_DECL | FEATURE_DECL | DIACRITIC_DECL | SYMBOL_DECL |
@@ -338,6 +608,55 @@ peg::parser!{
   }
 }
 
+/// Decodes the `\x` escapes of a text literal into the characters they stand for
+fn unescape(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => decoded.extend(chars.next()),
+            c => decoded.push(c),
+        }
+    }
+    decoded
+}
+
+/// Parses a single statement, like a line being typed into a rule file. Anything but whitespace
+/// and comments after the statement is an error
+pub fn parse_statement(input: &str) -> Result<Stmt, peg::error::ParseError<peg::str::LineCol>> {
+    lsc::single_statement(input)
+}
+
+/// Parses a rule file like `lsc::lsc_file`, attaching to each statement the comment lines right
+/// above it as its documentation, without their `#`. A blank line ends the documentation. Indented
+/// comments right after the body of the previous statement belong to that body, and are only
+/// documentation when a blank line separates them from it
+pub fn parse_lsc_with_docs(src: &str) -> Result<Vec<(Stmt, Vec<String>)>, peg::error::ParseError<peg::str::LineCol>> {
+    let stmts = lsc::lsc_file_positions(src)?;
+    let mut prev = 0;
+    let mut documented = vec![];
+    for (start, stmt) in stmts {
+        // Only the text since the previous statement started can document this one
+        let before = src[prev..start].trim_end_matches([' ', '\t']);
+        let lines = before.lines().collect::<Vec<_>>();
+        let comments = lines.iter().rev().take_while(|l| l.trim().starts_with('#')).count();
+        let (rest, mut comments) = lines.split_at(lines.len() - comments);
+        let separated = rest.last().is_none_or(|l| l.trim().is_empty());
+        if !separated {
+            let top_level = comments.iter().rev().take_while(|l| l.starts_with('#')).count();
+            comments = &comments[comments.len() - top_level..];
+        }
+        let docs = comments
+            .iter()
+            .map(|l| l.trim().strip_prefix('#').unwrap_or(l))
+            .map(|l| l.strip_prefix(' ').unwrap_or(l).trim_end().to_string())
+            .collect();
+        documented.push((stmt, docs));
+        prev = start;
+    }
+    Ok(documented)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -348,6 +667,340 @@ mod tests {
             "
 Feature soft
 "
-        ), Ok(vec![Stmt::FeatureDecl]));
+        ), Ok(vec![Stmt::FeatureDecl(FeatureDecl::Plus(vec![
+            PlusFeature { syllable: false, plus: false, name: "soft".to_string() },
+        ]))]));
+    }
+
+    #[test]
+    fn test_plus_features() {
+        assert_eq!(lsc::lsc_file("Feature +voiced, +nasal\n"), Ok(vec![
+            Stmt::FeatureDecl(FeatureDecl::Plus(vec![
+                PlusFeature { syllable: false, plus: true, name: "voiced".to_string() },
+                PlusFeature { syllable: false, plus: true, name: "nasal".to_string() },
+            ]))
+        ]));
+        assert_eq!(lsc::lsc_file("Feature (syllable) +stress\n"), Ok(vec![
+            Stmt::FeatureDecl(FeatureDecl::Plus(vec![
+                PlusFeature { syllable: true, plus: true, name: "stress".to_string() },
+            ]))
+        ]));
+    }
+
+    #[test]
+    fn test_valued_feature() {
+        assert_eq!(lsc::lsc_file("Feature height (*low, mid, high)\n"), Ok(vec![
+            Stmt::FeatureDecl(FeatureDecl::Valued {
+                syllable: false,
+                name: "height".to_string(),
+                null_alias: Some("low".to_string()),
+                values: vec!["mid".to_string(), "high".to_string()],
+            })
+        ]));
+        assert_eq!(lsc::lsc_file("Feature place (labial, coronal)\n"), Ok(vec![
+            Stmt::FeatureDecl(FeatureDecl::Valued {
+                syllable: false,
+                name: "place".to_string(),
+                null_alias: None,
+                values: vec!["labial".to_string(), "coronal".to_string()],
+            })
+        ]));
+    }
+
+    #[test]
+    fn test_keyword_expressions() {
+        assert_eq!(lsc::lsc_file("leave-alone:\n    unchanged\n"), Ok(vec![
+            Stmt::ChangeRule(ChangeRule {
+                name: "leave-alone".to_string(),
+                modifiers: vec![],
+                block: Block::BasicBlock { expressions: vec![Expression::Unchanged] },
+            })
+        ]));
+        assert_eq!(lsc::lsc_file("disabled:\n    Off\n"), Ok(vec![
+            Stmt::ChangeRule(ChangeRule {
+                name: "disabled".to_string(),
+                modifiers: vec![],
+                block: Block::BasicBlock { expressions: vec![Expression::Off] },
+            })
+        ]));
+    }
+
+    #[test]
+    fn test_include() {
+        assert_eq!(lsc::lsc_file("include \"common.lsc\"\nFeature +voiced\n"), Ok(vec![
+            Stmt::Include("common.lsc".to_string()),
+            Stmt::FeatureDecl(FeatureDecl::Plus(vec![
+                PlusFeature { syllable: false, plus: true, name: "voiced".to_string() },
+            ])),
+        ]));
+    }
+
+    #[test]
+    fn test_symbol_decl() {
+        assert_eq!(lsc::lsc_file("Symbol tʃ, dʒ\nSymbol ts [+affricate]\n"), Ok(vec![
+            Stmt::SymbolDecl(vec!["tʃ".to_string(), "dʒ".to_string()], vec![]),
+            Stmt::SymbolDecl(vec!["ts".to_string()], vec![
+                MatrixValue { sign: Some(Sign::Plus), name: "affricate".to_string() },
+            ]),
+        ]));
+    }
+
+    #[test]
+    fn test_docs() {
+        let src = "# Voicing\n#  is binary\nFeature +voice\n\n# Not attached\n\nSymbol ts\n\
+                   # Lenition, see\n# the notes\nlenition:\n    p => b # inline\n    # indented\n\
+                   Class stop {p, t}\n\n  # Spaced out\nClass vowel {a}\n";
+        let docs = parse_lsc_with_docs(src).unwrap();
+        let stmts = docs.iter().map(|(s, _)| s).collect::<Vec<_>>();
+        assert_eq!(stmts, lsc::lsc_file(src).unwrap().iter().collect::<Vec<_>>());
+        let docs = docs.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+        assert_eq!(docs, [
+            vec!["Voicing", " is binary"],
+            vec![],
+            vec!["Lenition, see", "the notes"],
+            // Part of the body of `lenition`
+            vec![],
+            vec!["Spaced out"],
+        ]);
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        let statements = [
+            "Feature +voice, +nasal",
+            "Feature height (*low, mid, high)",
+            "Diacritic ʰ [+aspirated]",
+            "Symbol tʃ, dʒ",
+            "Symbol m [+nasal labial]",
+            "Class stop {p, t, k}",
+            "Element cluster @stop @stop",
+            "Syllables:\n    @stop? :: a",
+            "Deromanizer:\n    c => k",
+            "Romanizer-mid:\n    unchanged",
+            "Romanizer:\n    k => c",
+            "lenition:\n    p => b / a _ a",
+            "include \"base.lsc\"",
+        ];
+        for src in statements {
+            let stmt = parse_statement(src).unwrap_or_else(|e| panic!("{}: {}", src, e));
+            assert_eq!(lsc::lsc_file(src), Ok(vec![stmt]), "{}", src);
+            assert_eq!(parse_statement(&format!("  {} \n\n# done\n", src)), parse_statement(src));
+        }
+        assert!(matches!(parse_statement("[+voice] => [-voice]"), Ok(Stmt::StandardExpression(_))));
+
+        assert!(parse_statement("").is_err());
+        assert!(parse_statement("Feature +voice )").is_err());
+        assert!(parse_statement("Feature +voice\nSymbol ts").is_err());
+    }
+
+    #[test]
+    fn test_escapes() {
+        let Expression::Standard(e) = expression("\\. => a") else {
+            panic!("Expected a standard expression");
+        };
+        assert_eq!(e.from, Element::Text(".".to_string()));
+
+        let expected = "a character to escape after `\\`";
+        for (src, line, column) in [("a:\n    k => c\\", 2, 12), ("a:\n    k\\\n    => c\n", 2, 7)] {
+            let err = lsc::lsc_file(src).unwrap_err();
+            assert_eq!((err.location.line, err.location.column), (line, column), "{:?}", src);
+            assert_eq!(err.expected.tokens().collect::<Vec<_>>(), [expected], "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn test_escaped_text() {
+        assert_eq!(lsc::lsc_file("Symbol t\\>, \\/s\n"), Ok(vec![
+            Stmt::SymbolDecl(vec!["t>".to_string(), "/s".to_string()], vec![]),
+        ]));
+        let Expression::Standard(e) = expression("a\\/b => a\\>b / _ c") else {
+            panic!("Expected a standard expression");
+        };
+        assert_eq!(e.from, Element::Text("a/b".to_string()));
+        assert_eq!(e.to, Element::Text("a>b".to_string()));
+        assert_eq!(lsc::lsc_file("Class odd {\\\\, \\,}\n"), Ok(vec![
+            Stmt::ClassDecl(ClassDecl {
+                name: "odd".to_string(),
+                elements: vec![Element::Text("\\".to_string()), Element::Text(",".to_string())],
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_feature_variable() {
+        assert_eq!(expression("[+nasal] => [$place] / _ [$place -nasal]"), Expression::Standard(StandardExpression {
+            from: Element::Matrix(vec![FancyValue::Value(MatrixValue { sign: Some(Sign::Plus), name: "nasal".to_string() })]),
+            to: Element::Matrix(vec![FancyValue::Variable("place".to_string())]),
+            environment: CompoundEnvironment {
+                conditions: vec![Environment {
+                    before: None,
+                    after: Some(Element::Matrix(vec![
+                        FancyValue::Variable("place".to_string()),
+                        FancyValue::Value(MatrixValue { sign: Some(Sign::Minus), name: "nasal".to_string() }),
+                    ])),
+                }],
+                exclusions: vec![],
+            },
+        }));
+        assert_eq!(expression("[+nasal !+voice !labial *long] => *"), Expression::Standard(StandardExpression {
+            from: Element::Matrix(vec![
+                FancyValue::Value(MatrixValue { sign: Some(Sign::Plus), name: "nasal".to_string() }),
+                FancyValue::Negated(MatrixValue { sign: Some(Sign::Plus), name: "voice".to_string() }),
+                FancyValue::Negated(MatrixValue { sign: None, name: "labial".to_string() }),
+                FancyValue::Unsupported("*long".to_string()),
+            ]),
+            to: Element::Empty,
+            environment: CompoundEnvironment::default(),
+        }));
+    }
+
+    #[test]
+    fn test_any_syllable() {
+        assert_eq!(lsc::lsc_file("stress:\n    <Syl> => [+stress] / $ _\n"), Ok(vec![
+            Stmt::ChangeRule(ChangeRule {
+                name: "stress".to_string(),
+                modifiers: vec![],
+                block: Block::BasicBlock { expressions: vec![Expression::Standard(StandardExpression {
+                    from: Element::AnySyllable,
+                    to: Element::Matrix(vec![FancyValue::Value(MatrixValue {
+                        sign: Some(Sign::Plus),
+                        name: "stress".to_string(),
+                    })]),
+                    environment: CompoundEnvironment {
+                        conditions: vec![Environment { before: Some(Element::WordBoundary), after: None }],
+                        exclusions: vec![],
+                    },
+                })] },
+            })
+        ]));
+    }
+
+    fn expression(rule: &str) -> Expression {
+        let Ok(mut stmts) = lsc::lsc_file(&format!("test:\n    {}\n", rule)) else {
+            panic!("Failed to parse {:?}", rule);
+        };
+        let Some(Stmt::ChangeRule(ChangeRule { block: Block::BasicBlock { mut expressions }, .. })) = stmts.pop() else {
+            panic!("Expected a change rule");
+        };
+        expressions.remove(0)
+    }
+
+    fn text(t: &str) -> Element {
+        Element::Text(t.to_string())
+    }
+
+    #[test]
+    fn test_bounded() {
+        assert_eq!(expression("{p, t, k} => s"), Expression::Standard(StandardExpression {
+            from: Element::List(vec![text("p"), text("t"), text("k")]),
+            to: text("s"),
+            environment: CompoundEnvironment::default(),
+        }));
+        assert_eq!(expression("(a b)? => x"), Expression::Standard(StandardExpression {
+            from: Element::Repeater(
+                Box::new(Element::Group(Box::new(Element::Sequence(vec![text("a"), text("b")])))),
+                Repetitions { min: 0, max: Some(1) },
+            ),
+            to: text("x"),
+            environment: CompoundEnvironment::default(),
+        }));
+        let Expression::Standard(e) = expression("a*(2-) => b") else { unreachable!() };
+        assert_eq!(e.from, Element::Repeater(Box::new(text("a")), Repetitions { min: 2, max: None }));
+    }
+
+    #[test]
+    fn test_captures() {
+        assert_eq!(expression("(@C)$1 => $1 $1"), Expression::Standard(StandardExpression {
+            from: Element::Capture {
+                element: Box::new(Element::Group(Box::new(Element::Ref("C".to_string())))),
+                index: 1,
+                inexact: false,
+            },
+            to: Element::Sequence(vec![
+                Element::CaptureRef { index: 1, inexact: false },
+                Element::CaptureRef { index: 1, inexact: false },
+            ]),
+            environment: CompoundEnvironment::default(),
+        }));
+        let Expression::Standard(e) = expression("a~$2 => * / _ ~$2") else { unreachable!() };
+        assert_eq!(e.from, Element::Capture { element: Box::new(text("a")), index: 2, inexact: true });
+        assert_eq!(e.environment.conditions[0].after, Some(Element::CaptureRef { index: 2, inexact: true }));
+        let Expression::Standard(e) = expression("n => m / _ $$ $1") else { unreachable!() };
+        assert_eq!(e.environment.conditions[0].after, Some(Element::Sequence(vec![
+            Element::BetweenWords,
+            Element::CaptureRef { index: 1, inexact: false },
+        ])));
+        let Expression::Standard(e) = expression("<Syl>$.1 => $.1") else { unreachable!() };
+        assert_eq!(e.from, Element::Unsupported("<Syl>$.1".to_string()));
+    }
+
+    #[test]
+    fn test_then_else() {
+        let basic = |e: &str| Box::new(Block::BasicBlock { expressions: vec![expression(e)] });
+        let stmts = lsc::lsc_file("rule:\n    a => e\nThen:\n    b => c\nElse:\n    d => f\n").unwrap();
+        let [Stmt::ChangeRule(rule)] = &stmts[..] else { panic!("{:?}", stmts) };
+        assert_eq!(rule.block, Block::ElseBlock {
+            first: Box::new(Block::ThenBlock { first: basic("a => e"), then: basic("b => c") }),
+            else_: basic("d => f"),
+        });
+
+        let stmts = lsc::lsc_file("rule:\n    a => e\nelse:\n    (b => c\n    then: d => f)\n").unwrap();
+        let [Stmt::ChangeRule(rule)] = &stmts[..] else { panic!("{:?}", stmts) };
+        assert_eq!(rule.block, Block::ElseBlock {
+            first: basic("a => e"),
+            else_: Box::new(Block::ThenBlock { first: basic("b => c"), then: basic("d => f") }),
+        });
+    }
+
+    #[test]
+    fn test_romanizers() {
+        let basic = |e: &str| Block::BasicBlock { expressions: vec![expression(e)] };
+        let src = "Deromanizer:\n    c => k\nRomanizer-late literal:\n    k => q\nlate:\n    a => e\nRomanizer:\n    k => c\n";
+        let stmts = lsc::lsc_file(src).unwrap();
+        assert_eq!(stmts[0], Stmt::Deromanizer(false, basic("c => k")));
+        assert_eq!(stmts[1], Stmt::InterRomanizer("late".to_string(), true, basic("k => q")));
+        assert!(matches!(stmts[2], Stmt::ChangeRule(_)));
+        assert_eq!(stmts[3], Stmt::Romanizer(false, basic("k => c")));
+        for (src, literal) in [
+            ("Deromanizer:\n    c => k\n", false),
+            ("Deromanizer Literal:\n    c => k\n", true),
+            ("deromanizer literal :\n    c => k\n", true),
+        ] {
+            assert_eq!(lsc::lsc_file(src), Ok(vec![Stmt::Deromanizer(literal, basic("c => k"))]), "{:?}", src);
+        }
+        assert_eq!(lsc::lsc_file("Romanizer Literal:\n    k => c\n"), Ok(vec![Stmt::Romanizer(true, basic("k => c"))]));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let stmts = lsc::lsc_file("harmony propagate ltr:\n    e => i / i _\n").unwrap();
+        let [Stmt::ChangeRule(rule)] = &stmts[..] else { panic!("{:?}", stmts) };
+        assert_eq!(rule.name, "harmony");
+        assert_eq!(rule.modifiers, vec![Modifier::Propagate, Modifier::Ltr]);
+    }
+
+    #[test]
+    fn test_syllables() {
+        let src = "Class cons {p, t, k}\nClass vowel {a, i}\n\
+                   Syllables:\n    @cons? :: @vowel :: n?\n    @vowel\n\
+                   rule:\n    a => e\n";
+        let stmts = lsc::lsc_file(src).unwrap();
+        assert_eq!(stmts[0], Stmt::ClassDecl(ClassDecl {
+            name: "cons".to_string(),
+            elements: vec![text("p"), text("t"), text("k")],
+        }));
+        let maybe = |e: Element| Element::Repeater(Box::new(e), Repetitions { min: 0, max: Some(1) });
+        assert_eq!(stmts[2], Stmt::SyllableDecl(SyllableDecl::Patterns(vec![
+            SyllablePattern::Structured {
+                onset: maybe(Element::Ref("cons".to_string())),
+                nucleus: Element::Ref("vowel".to_string()),
+                coda: Some(maybe(text("n"))),
+            },
+            SyllablePattern::Plain(Element::Ref("vowel".to_string())),
+        ])));
+        assert!(matches!(stmts[3], Stmt::ChangeRule(_)));
+        assert_eq!(stmts.len(), 4);
+
+        assert_eq!(lsc::lsc_file("Syllables: explicit\n"), Ok(vec![Stmt::SyllableDecl(SyllableDecl::Explicit)]));
     }
 }
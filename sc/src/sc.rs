@@ -1,86 +1,1640 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::ops;
+
 use super::parser::*;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
-struct Symbol {
+pub struct Symbol {
     symbol: String,
 }
 
+impl Symbol {
+    pub fn new(symbol: &str) -> Symbol {
+        Symbol {
+            symbol: symbol.to_string(),
+        }
+    }
+
+    /// The symbol without its diacritics and modifier letters, like `t` for `tʰ`
+    fn base(&self) -> String {
+        self.symbol
+            .chars()
+            .filter(|c| !matches!(c, '\u{2b0}'..='\u{36f}' | '\u{1dc0}'..='\u{1dff}'))
+            .collect()
+    }
+}
+
+/// A symbol's value of each feature, `None` where it has none
+type FeatureValues = BTreeMap<String, Option<String>>;
+
+/// The multi-character symbols a language declares, which need to be kept together when splitting
+/// a word into symbols, the classes it declares and the features of its symbols
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    /// Sorted longest first, so the first match is the longest one
+    symbols: Vec<String>,
+    /// With the classes they refer to already substituted
+    classes: HashMap<String, Vec<Element>>,
+    /// The feature each value belongs to, `+nasal` and `-nasal` both belonging to `nasal`
+    values: HashMap<String, String>,
+    /// The value of each feature for symbols that don't give one, like `-nasal`
+    defaults: FeatureValues,
+    /// The symbols declared with a matrix, in declaration order
+    matrices: Vec<(Symbol, FeatureValues)>,
+}
+
+impl SymbolTable {
+    pub fn new(symbols: impl IntoIterator<Item = String>) -> SymbolTable {
+        let mut symbols = symbols.into_iter().collect::<Vec<_>>();
+        symbols.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        symbols.dedup();
+        SymbolTable {
+            symbols,
+            ..SymbolTable::default()
+        }
+    }
+
+    /// Syllable features are only ever set on syllables, so they're left out
+    fn declare_feature(&mut self, decl: &FeatureDecl) {
+        match decl {
+            FeatureDecl::Plus(fs) => {
+                for f in fs.iter().filter(|f| !f.syllable) {
+                    for sign in ["+", "-"] {
+                        self.values
+                            .insert(format!("{}{}", sign, f.name), f.name.clone());
+                    }
+                    // Only binary features are absent as `-`
+                    let default = f.plus.then(|| format!("-{}", f.name));
+                    self.defaults.insert(f.name.clone(), default);
+                }
+            }
+            FeatureDecl::Valued {
+                syllable: false,
+                name,
+                null_alias,
+                values,
+            } => {
+                for v in null_alias.iter().chain(values) {
+                    self.values.insert(v.clone(), name.clone());
+                }
+                self.defaults.insert(name.clone(), null_alias.clone());
+            }
+            FeatureDecl::Valued { .. } => {}
+        }
+    }
+
+    /// Values of features that weren't declared are ignored
+    fn declare_matrix(&mut self, symbol: &str, matrix: &[MatrixValue]) {
+        let mut features = self.defaults.clone();
+        for v in matrix {
+            let value = matrix_value(v);
+            if let Some(feature) = self.values.get(&value) {
+                features.insert(feature.clone(), Some(value));
+            }
+        }
+        self.matrices.push((Symbol::new(symbol), features));
+    }
+
+    /// The symbols declared with a matrix, with the defaults of the features declared after them
+    /// filled in
+    fn matrices(&self) -> Vec<(Symbol, FeatureValues)> {
+        let mut matrices = self.matrices.clone();
+        for (_, features) in &mut matrices {
+            for (feature, default) in &self.defaults {
+                features
+                    .entry(feature.clone())
+                    .or_insert_with(|| default.clone());
+            }
+        }
+        matrices
+    }
+
+    /// The feature a value of a matrix belongs to, and the value as it's stored
+    fn feature_of(&self, v: &MatrixValue) -> Option<(String, String)> {
+        let value = matrix_value(v);
+        Some((self.values.get(&value)?.clone(), value))
+    }
+
+    fn is_feature(&self, name: &str) -> bool {
+        self.defaults.contains_key(name)
+    }
+
+    /// Classes can only refer to the ones declared before them, so substituting those right away
+    /// means a class can never end up containing itself
+    fn declare_class(&mut self, name: &str, elements: &[Element]) {
+        let elements = elements.iter().map(|e| self.substitute(e)).collect();
+        self.classes.insert(name.to_string(), elements);
+    }
+
+    fn substitute(&self, e: &Element) -> Element {
+        match e {
+            Element::Ref(name) => match self.classes.get(name) {
+                Some(class) => Element::List(class.clone()),
+                None => e.clone(),
+            },
+            Element::Sequence(es) => {
+                Element::Sequence(es.iter().map(|e| self.substitute(e)).collect())
+            }
+            Element::List(es) => Element::List(es.iter().map(|e| self.substitute(e)).collect()),
+            Element::Group(e) => Element::Group(Box::new(self.substitute(e))),
+            Element::Repeater(e, reps) => Element::Repeater(Box::new(self.substitute(e)), *reps),
+            Element::Capture {
+                element,
+                index,
+                inexact,
+            } => Element::Capture {
+                element: Box::new(self.substitute(element)),
+                index: *index,
+                inexact: *inexact,
+            },
+            _ => e.clone(),
+        }
+    }
+
+    /// Finds the longest declared symbol at the start of `s`, falling back to its first character
+    fn longest_prefix<'s>(&self, s: &'s str) -> &'s str {
+        if let Some(sym) = self.symbols.iter().find(|sym| s.starts_with(&sym[..])) {
+            return &s[..sym.len()];
+        }
+        let c = s.chars().next().map_or(0, char::len_utf8);
+        &s[..c]
+    }
+
+    fn split(&self, s: &str) -> Vec<Symbol> {
+        let mut symbols = vec![];
+        let mut rest = s;
+        while !rest.is_empty() {
+            let sym = self.longest_prefix(rest);
+            symbols.push(Symbol::new(sym));
+            rest = &rest[sym.len()..];
+        }
+        symbols
+    }
+}
+
+/// `+nasal`, `-nasal` or `labial`, the way feature values are stored
+fn matrix_value(v: &MatrixValue) -> String {
+    match v.sign {
+        Some(Sign::Plus) => format!("+{}", v.name),
+        Some(Sign::Minus) => format!("-{}", v.name),
+        None => v.name.clone(),
+    }
+}
+
+/// One value of a compiled feature matrix
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum FeatureItem {
+    /// The feature and its value
+    Value(String, String),
+    /// The feature and the value it mustn't have
+    Negated(String, String),
+    /// A feature variable
+    Variable(String),
+}
+
+impl FeatureItem {
+    fn compile(v: &FancyValue, table: &SymbolTable) -> Option<FeatureItem> {
+        match v {
+            FancyValue::Value(v) => {
+                let (feature, value) = table.feature_of(v)?;
+                Some(FeatureItem::Value(feature, value))
+            }
+            FancyValue::Negated(v) => {
+                let (feature, value) = table.feature_of(v)?;
+                Some(FeatureItem::Negated(feature, value))
+            }
+            FancyValue::Variable(f) if table.is_feature(f) => {
+                Some(FeatureItem::Variable(f.clone()))
+            }
+            FancyValue::Variable(_) | FancyValue::Unsupported(_) => None,
+        }
+    }
+}
+
+/// A compiled pattern
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Matcher {
+    Symbol(Symbol),
+    /// Consumes every symbol of the syllable starting here
+    AnySyllable,
+    SyllableBoundary,
+    /// Also matches at the edges of the words within a phrase
+    WordBoundary,
+    BetweenWords,
+    Sequence(Vec<Matcher>),
+    /// Matches any one of the alternatives
+    List(Vec<Matcher>),
+    Repeat(Box<Matcher>, Repetitions),
+    /// Binds the symbols the inner matcher consumed to a slot
+    Capture(Box<Matcher>, u32, bool),
+    /// Matches the symbols bound to a slot again. Inexact references compare the features of the
+    /// symbols declared with a matrix, and the rest without their diacritics
+    CaptureRef(u32, bool, Vec<(Symbol, FeatureValues)>),
+    /// The declared symbols a feature matrix matches, with their values of the features the
+    /// matrix uses as variables
+    Matrix(Vec<(Symbol, FeatureValues)>),
+}
+
+/// What a match bound on its way
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+struct Captures {
+    /// The symbols bound to each slot, and whether they're referred to inexactly
+    slots: BTreeMap<u32, (Vec<Symbol>, bool)>,
+    /// The value each feature variable was bound to by its first use
+    variables: FeatureValues,
+}
+
+impl Matcher {
+    /// Returns everywhere a match starting at `pos` can end, the preferred ones first, along with
+    /// what got captured on the way there
+    fn ends(&self, w: &Word, pos: usize, caps: &Captures) -> Vec<(usize, Captures)> {
+        let at = |end: Option<usize>| end.map(|end| (end, caps.clone())).into_iter().collect();
+        match self {
+            Matcher::Symbol(s) => at((w.symbols.get(pos) == Some(s)).then_some(pos + 1)),
+            Matcher::AnySyllable => at(w.syllable_end(pos)),
+            Matcher::SyllableBoundary => {
+                at((pos != 0 && w.syllable_index(pos).is_some()).then_some(pos))
+            }
+            Matcher::WordBoundary => {
+                at((pos == 0 || pos == w.symbols.len() || w.word_starts_at(pos)).then_some(pos))
+            }
+            Matcher::BetweenWords => {
+                at((pos != 0 && pos != w.symbols.len() && w.word_starts_at(pos)).then_some(pos))
+            }
+            Matcher::Sequence(ms) => {
+                let mut ends = vec![(pos, caps.clone())];
+                for m in ms {
+                    let mut next = vec![];
+                    for (end, caps) in ends {
+                        push_new(&mut next, m.ends(w, end, &caps));
+                    }
+                    ends = next;
+                }
+                ends
+            }
+            Matcher::List(ms) => {
+                let mut ends = vec![];
+                for m in ms {
+                    push_new(&mut ends, m.ends(w, pos, caps));
+                }
+                ends
+            }
+            Matcher::Repeat(m, reps) => {
+                // Greedy, so the ends reached through more repetitions come first
+                let mut by_count = vec![vec![(pos, caps.clone())]];
+                let mut seen = by_count[0].clone();
+                while reps.max.is_none_or(|max| by_count.len() <= max) {
+                    let mut next = vec![];
+                    for (end, caps) in by_count.last().unwrap() {
+                        let new = m
+                            .ends(w, *end, caps)
+                            .into_iter()
+                            .filter(|e| !seen.contains(e));
+                        push_new(&mut next, new.collect());
+                    }
+                    if next.is_empty() {
+                        break;
+                    }
+                    seen.extend(next.iter().cloned());
+                    by_count.push(next);
+                }
+                by_count
+                    .into_iter()
+                    .skip(reps.min)
+                    .rev()
+                    .flatten()
+                    .collect()
+            }
+            Matcher::Capture(m, index, inexact) => m
+                .ends(w, pos, caps)
+                .into_iter()
+                .map(|(end, mut caps)| {
+                    caps.slots
+                        .insert(*index, (w.symbols[pos..end].to_vec(), *inexact));
+                    (end, caps)
+                })
+                .collect(),
+            Matcher::CaptureRef(index, inexact, matrices) => {
+                let Some((captured, inexact_capture)) = caps.slots.get(index) else {
+                    return vec![];
+                };
+                let end = pos + captured.len();
+                let Some(symbols) = w.symbols.get(pos..end) else {
+                    return vec![];
+                };
+                let features = |s: &Symbol| matrices.iter().find(|(m, _)| m == s).map(|(_, f)| f);
+                let matched = if *inexact || *inexact_capture {
+                    symbols
+                        .iter()
+                        .zip(captured)
+                        .all(|(a, b)| match (features(a), features(b)) {
+                            (Some(a), Some(b)) => a == b,
+                            _ => a.base() == b.base(),
+                        })
+                } else {
+                    symbols == captured
+                };
+                at(matched.then_some(end))
+            }
+            Matcher::Matrix(candidates) => {
+                let Some((_, values)) = candidates
+                    .iter()
+                    .find(|(s, _)| w.symbols.get(pos) == Some(s))
+                else {
+                    return vec![];
+                };
+                let mut caps = caps.clone();
+                for (feature, value) in values {
+                    match caps.variables.get(feature) {
+                        Some(bound) if bound != value => return vec![],
+                        Some(_) => {}
+                        None => {
+                            caps.variables.insert(feature.clone(), value.clone());
+                        }
+                    }
+                }
+                vec![(pos + 1, caps)]
+            }
+        }
+    }
+
+    fn compile(e: &Element, table: &SymbolTable) -> Option<Matcher> {
+        let compile_all = |es: &[Element]| {
+            es.iter()
+                .map(|e| Matcher::compile(e, table))
+                .collect::<Option<Vec<_>>>()
+        };
+        Some(match e {
+            Element::Text(t) => {
+                let mut symbols = table
+                    .split(t)
+                    .into_iter()
+                    .map(Matcher::Symbol)
+                    .collect::<Vec<_>>();
+                if symbols.len() == 1 {
+                    symbols.remove(0)
+                } else {
+                    Matcher::Sequence(symbols)
+                }
+            }
+            Element::Empty => Matcher::Sequence(vec![]),
+            Element::SyllableBoundary => Matcher::SyllableBoundary,
+            Element::WordBoundary => Matcher::WordBoundary,
+            Element::BetweenWords => Matcher::BetweenWords,
+            Element::AnySyllable => Matcher::AnySyllable,
+            Element::Sequence(es) => Matcher::Sequence(compile_all(es)?),
+            Element::Group(e) => Matcher::compile(e, table)?,
+            Element::List(es) => Matcher::List(compile_all(es)?),
+            Element::Repeater(e, reps) => {
+                Matcher::Repeat(Box::new(Matcher::compile(e, table)?), *reps)
+            }
+            Element::Ref(name) => Matcher::List(compile_all(table.classes.get(name)?)?),
+            Element::Capture {
+                element,
+                index,
+                inexact,
+            } => Matcher::Capture(
+                Box::new(Matcher::compile(element, table)?),
+                *index,
+                *inexact,
+            ),
+            Element::CaptureRef { index, inexact } => {
+                Matcher::CaptureRef(*index, *inexact, table.matrices())
+            }
+            Element::Matrix(values) => {
+                let items = values
+                    .iter()
+                    .map(|v| FeatureItem::compile(v, table))
+                    .collect::<Option<Vec<_>>>()?;
+                let matrices = table.matrices();
+                let candidates = matrices.iter().filter(|(_, features)| {
+                    items.iter().all(|item| match item {
+                        FeatureItem::Value(f, v) => features.get(f) == Some(&Some(v.clone())),
+                        FeatureItem::Negated(f, v) => features.get(f) != Some(&Some(v.clone())),
+                        FeatureItem::Variable(_) => true,
+                    })
+                });
+                let candidates = candidates.map(|(symbol, features)| {
+                    let variables = items.iter().filter_map(|item| match item {
+                        FeatureItem::Variable(f) => {
+                            Some((f.clone(), features.get(f).cloned().flatten()))
+                        }
+                        FeatureItem::Value(..) | FeatureItem::Negated(..) => None,
+                    });
+                    (symbol.clone(), variables.collect())
+                });
+                Matcher::Matrix(candidates.collect())
+            }
+            Element::Unsupported(_) => return None,
+        })
+    }
+
+    /// What this would produce as a result, if it's a fixed sequence of symbols and captures
+    fn output(&self) -> Option<Vec<Output>> {
+        match self {
+            Matcher::Symbol(s) => Some(vec![Output::Symbol(s.clone())]),
+            Matcher::CaptureRef(index, ..) => Some(vec![Output::Capture(*index)]),
+            Matcher::Sequence(ms) => {
+                let mut output = vec![];
+                for m in ms {
+                    output.extend(m.output()?);
+                }
+                Some(output)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A piece of the result of a change
+#[derive(Debug, Clone)]
+enum Output {
+    Symbol(Symbol),
+    /// The symbols bound to a slot, exactly as they were matched
+    Capture(u32),
+}
+
+/// Appends the ends not in `ends` yet, keeping their order
+fn push_new<T: PartialEq>(ends: &mut Vec<T>, new: Vec<T>) {
+    for e in new {
+        if !ends.contains(&e) {
+            ends.push(e);
+        }
+    }
+}
+
+/// A compiled `before _ after` environment
+#[derive(Debug)]
+struct Context {
+    before: Matcher,
+    after: Matcher,
+}
+
+impl Context {
+    fn compile(env: &Environment, table: &SymbolTable) -> Option<Context> {
+        let compile = |e: &Option<Element>| match e {
+            Some(e) => Matcher::compile(e, table),
+            None => Some(Matcher::Sequence(vec![])),
+        };
+        Some(Context {
+            before: compile(&env.before)?,
+            after: compile(&env.after)?,
+        })
+    }
+
+    /// Returns what got bound if the environment holds, so the result can use the values the
+    /// environment's feature variables agreed on
+    fn holds(&self, w: &Word, start: usize, end: usize, caps: &Captures) -> Option<Captures> {
+        (0..=start).find_map(|s| {
+            self.before
+                .ends(w, s, caps)
+                .into_iter()
+                .filter(|(e, _)| *e == start)
+                .find_map(|(_, caps)| self.after.ends(w, end, &caps).into_iter().next())
+                .map(|(_, caps)| caps)
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Replacement {
+    /// Slots the pattern didn't bind produce nothing
+    Symbols(Vec<Output>),
+    /// Sets features on the matched syllable, like `<Syl> => [+stress]`
+    SyllableFeatures(Vec<MatrixValue>),
+    /// Changes the features of each matched symbol, like `[+nasal] => [$place]`, turning it into
+    /// the declared symbol with the resulting features. Symbols there's none for are left as is
+    SymbolFeatures {
+        items: Vec<FeatureItem>,
+        matrices: Vec<(Symbol, FeatureValues)>,
+    },
+}
+
+/// A single compiled `from => to / environment` expression
+#[derive(Debug)]
+struct Change {
+    pattern: Matcher,
+    replacement: Replacement,
+    conditions: Vec<Context>,
+    exclusions: Vec<Context>,
+}
+
+impl Change {
+    /// Returns `None` if the expression uses syntax the engine doesn't support yet. Lists on both
+    /// sides, like `{p, t} => {b, d}`, turn into a change per pair of elements
+    fn compile(e: &StandardExpression, table: &SymbolTable) -> Option<Vec<Change>> {
+        if let (Element::List(from), Element::List(to)) = (&e.from, &e.to) {
+            if from.len() != to.len() {
+                return None;
+            }
+            let mut changes = vec![];
+            for (from, to) in from.iter().zip(to) {
+                let e = StandardExpression {
+                    from: from.clone(),
+                    to: to.clone(),
+                    environment: e.environment.clone(),
+                };
+                changes.extend(Change::compile(&e, table)?);
+            }
+            return Some(changes);
+        }
+
+        let pattern = Matcher::compile(&e.from, table)?;
+        let replacement = match &e.to {
+            Element::Matrix(values) if pattern == Matcher::AnySyllable => {
+                let values = values.iter().map(|v| match v {
+                    FancyValue::Value(v) => Some(v.clone()),
+                    FancyValue::Variable(_)
+                    | FancyValue::Negated(_)
+                    | FancyValue::Unsupported(_) => None,
+                });
+                Replacement::SyllableFeatures(values.collect::<Option<_>>()?)
+            }
+            // A negated value doesn't say what to change the feature to
+            Element::Matrix(values)
+                if values.iter().any(|v| matches!(v, FancyValue::Negated(_))) =>
+            {
+                return None
+            }
+            Element::Matrix(values) => Replacement::SymbolFeatures {
+                items: values
+                    .iter()
+                    .map(|v| FeatureItem::compile(v, table))
+                    .collect::<Option<_>>()?,
+                matrices: table.matrices(),
+            },
+            to => Replacement::Symbols(Matcher::compile(to, table)?.output()?),
+        };
+        let compile = |envs: &[Environment]| {
+            envs.iter()
+                .map(|env| Context::compile(env, table))
+                .collect::<Option<Vec<_>>>()
+        };
+        Some(vec![Change {
+            pattern,
+            replacement,
+            conditions: compile(&e.environment.conditions)?,
+            exclusions: compile(&e.environment.exclusions)?,
+        }])
+    }
+
+    /// Returns where the match ends and what it captured if the change applies at `pos`
+    fn matches(&self, w: &Word, pos: usize) -> Option<(usize, Captures)> {
+        self.pattern
+            .ends(w, pos, &Captures::default())
+            .into_iter()
+            .find_map(|(end, caps)| {
+                if self
+                    .exclusions
+                    .iter()
+                    .any(|c| c.holds(w, pos, end, &caps).is_some())
+                {
+                    return None;
+                }
+                if self.conditions.is_empty() {
+                    return Some((end, caps));
+                }
+                let caps = self
+                    .conditions
+                    .iter()
+                    .find_map(|c| c.holds(w, pos, end, &caps))?;
+                Some((end, caps))
+            })
+    }
+
+    fn replace(&self, w: &mut Word, start: usize, end: usize, caps: &Captures) {
+        match &self.replacement {
+            Replacement::Symbols(output) => {
+                let mut new = vec![];
+                for o in output {
+                    match o {
+                        Output::Symbol(s) => new.push(s.clone()),
+                        Output::Capture(index) => {
+                            if let Some((symbols, _)) = caps.slots.get(index) {
+                                new.extend(symbols.iter().cloned());
+                            }
+                        }
+                    }
+                }
+                w.splice(start, end, new)
+            }
+            Replacement::SyllableFeatures(values) => {
+                if let Some(i) = w.syllable_index(start) {
+                    let features = &mut w.syllables[i].features;
+                    for v in values {
+                        if v.sign == Some(Sign::Minus) {
+                            features.remove(&v.name);
+                        } else {
+                            features.insert(v.name.clone());
+                        }
+                    }
+                }
+            }
+            Replacement::SymbolFeatures { items, matrices } => {
+                for symbol in &mut w.symbols[start..end] {
+                    let Some((_, features)) = matrices.iter().find(|(s, _)| s == symbol) else {
+                        continue;
+                    };
+                    let mut features = features.clone();
+                    for item in items {
+                        match item {
+                            FeatureItem::Value(f, v) => {
+                                features.insert(f.clone(), Some(v.clone()));
+                            }
+                            FeatureItem::Variable(f) => {
+                                if let Some(v) = caps.variables.get(f) {
+                                    features.insert(f.clone(), v.clone());
+                                }
+                            }
+                            // Rejected when compiling the change
+                            FeatureItem::Negated(..) => {}
+                        }
+                    }
+                    if let Some((new, _)) = matrices.iter().find(|(_, fs)| *fs == features) {
+                        *symbol = new.clone();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A compiled block of a rule
+#[derive(Debug)]
+enum RuleBlock {
+    /// Tried in order at each position, the first one that matches is applied
+    Changes(Vec<Change>),
+    /// Applies the second block only to words the first one matched
+    Then(Box<RuleBlock>, Box<RuleBlock>),
+    /// Applies the second block only to words the first one didn't match
+    Else(Box<RuleBlock>, Box<RuleBlock>),
+}
+
+impl RuleBlock {
+    /// Leaves out the expressions the engine doesn't support, clearing `complete` if there are any
+    fn compile(block: &Block, table: &SymbolTable, complete: &mut bool) -> RuleBlock {
+        let mut compile = |b: &Block| Box::new(RuleBlock::compile(b, table, complete));
+        match block {
+            Block::BasicBlock { expressions } => {
+                let mut changes = vec![];
+                for e in expressions {
+                    match e {
+                        Expression::Standard(e) => match Change::compile(e, table) {
+                            Some(cs) => changes.extend(cs),
+                            None => *complete = false,
+                        },
+                        Expression::Unchanged | Expression::Off => {}
+                        Expression::BlockRef(_) => *complete = false,
+                    }
+                }
+                RuleBlock::Changes(changes)
+            }
+            Block::ThenBlock { first, then } => RuleBlock::Then(compile(first), compile(then)),
+            Block::ElseBlock { first, else_ } => RuleBlock::Else(compile(first), compile(else_)),
+        }
+    }
+
+    /// Returns whether anything matched. A chain of blocks matches if its first block does.
+    /// `steps` is increased by the number of changes made
+    fn apply(&self, w: &mut Word, steps: &mut usize) -> bool {
+        match self {
+            RuleBlock::Changes(changes) => apply_changes(changes, w, steps),
+            RuleBlock::Then(first, then) => {
+                let matched = first.apply(w, steps);
+                if matched {
+                    then.apply(w, steps);
+                }
+                matched
+            }
+            RuleBlock::Else(first, else_) => first.apply(w, steps) || else_.apply(w, steps),
+        }
+    }
+}
+
+/// Returns whether any of the changes matched
+fn apply_changes(changes: &[Change], w: &mut Word, steps: &mut usize) -> bool {
+    // Every non-overlapping match is found left to right on the word as it was before the
+    // changes, so a change can't create the environment for another one
+    let mut hits = vec![];
+    let mut i = 0;
+    while i <= w.symbols.len() {
+        let hit = changes
+            .iter()
+            .find_map(|c| c.matches(w, i).map(|end| (c, end)));
+        match hit {
+            Some((c, (end, caps))) => {
+                hits.push((c, i, end, caps));
+                // Step past an insertion so it isn't matched again right after itself
+                i = end.max(i + 1);
+            }
+            None => i += 1,
+        }
+    }
+    let matched = !hits.is_empty();
+    *steps += hits.len();
+    // Replacing from the back keeps the positions of the earlier matches valid
+    for (c, start, end, caps) in hits.into_iter().rev() {
+        c.replace(w, start, end, &caps);
+    }
+    matched
+}
+
 #[derive(Debug)]
 struct Rule {
     name: String,
-    pattern: Vec<Symbol>,
-    result: Vec<Symbol>
+    block: RuleBlock,
+    /// Reapply the rule until the word stops changing, from the `propagate` modifier
+    iterate: bool,
+    /// Also apply the rule after every rule following it, from the `cleanup` modifier
+    cleanup: bool,
 }
 
+/// How often a propagating rule is reapplied at most before giving up on it settling
+const MAX_ITERATIONS: usize = 100;
+
+/// How many changes the rules may make to a single word at most, so rules that keep growing it
+/// fail instead of running away
+const MAX_STEPS: usize = 10_000;
+
 impl Rule {
-    fn apply(&self, w: &mut Word) {
-        let mut matches = Vec::new();
-        let mut new = Vec::new();
-        // Find all matches
-        'outer: for i in 0..(w.symbols.len() - self.patten.len())  {
-            for j in 0..self.pattern.len() {
-                if self.pattern[j] != w.symbols[i + j] { break 'outer }
+    /// Counts the changes made in `steps`, failing once there are more than `MAX_STEPS`, or if a
+    /// propagating rule doesn't settle. Errors name the word as it was before the rule
+    fn apply(&self, w: &mut Word, steps: &mut usize) -> Result<(), ApplyError> {
+        let mut seen = vec![w.clone()];
+        let too_many = |seen: &[Word]| ApplyError::TooManySteps {
+            rule: self.name.clone(),
+            word: seen[0].to_string(),
+        };
+        if !self.iterate {
+            self.block.apply(w, steps);
+            return if *steps > MAX_STEPS {
+                Err(too_many(&seen))
+            } else {
+                Ok(())
+            };
+        }
+        for _ in 0..MAX_ITERATIONS {
+            self.block.apply(w, steps);
+            if seen.last() == Some(w) {
+                return Ok(());
+            }
+            if seen.contains(w) {
+                return Err(ApplyError::Cycle {
+                    rule: self.name.clone(),
+                    word: seen[0].to_string(),
+                });
             }
-            matches.push((i, i + self.pattern.len(), &self.result[..]));
+            if *steps > MAX_STEPS {
+                return Err(too_many(&seen));
+            }
+            seen.push(w.clone());
+        }
+        Err(too_many(&seen))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+struct Syllable {
+    /// Index of the first symbol of the syllable
+    start: usize,
+    /// Syllable features set by rules, like `stress`
+    features: BTreeSet<String>,
+    /// Whether a word of a phrase starts here
+    word_start: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Word {
+    symbols: Vec<Symbol>,
+    /// Sorted by start, the first one always starting at 0. A word without explicit syllable
+    /// breaks is a single syllable
+    syllables: Vec<Syllable>,
+}
+
+impl Word {
+    /// Splits `s` into symbols, always preferring the longest declared symbol. `.` separates
+    /// syllables
+    pub fn from_str(s: &str, table: &SymbolTable) -> Word {
+        let mut symbols = vec![];
+        let mut syllables = vec![];
+        for syl in s.split('.').filter(|syl| !syl.is_empty()) {
+            syllables.push(Syllable {
+                start: symbols.len(),
+                ..Syllable::default()
+            });
+            symbols.extend(table.split(syl));
+        }
+        Word { symbols, syllables }
+    }
+
+    /// The symbols the word is made of, without the syllable breaks
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.symbols.iter().map(|s| &s.symbol[..])
+    }
+
+    /// Joins the words into a single phrase, marking where each one starts
+    fn join(ws: &[Word]) -> Word {
+        let mut phrase = Word {
+            symbols: vec![],
+            syllables: vec![],
+        };
+        for w in ws {
+            let offset = phrase.symbols.len();
+            phrase.symbols.extend(w.symbols.iter().cloned());
+            phrase
+                .syllables
+                .extend(w.syllables.iter().enumerate().map(|(i, s)| Syllable {
+                    start: s.start + offset,
+                    features: s.features.clone(),
+                    word_start: i == 0,
+                }));
         }
+        phrase
+    }
 
-        // Filter overlapping matches
-        let last_end = 0;
-        matches.retain(|(start, end, _)| {
-            if last_end > start {
-                return false
+    /// Splits a phrase back into its words
+    fn split(self) -> Vec<Word> {
+        let mut words: Vec<Word> = vec![];
+        let mut syllables = self.syllables.into_iter().peekable();
+        while let Some(first) = syllables.next() {
+            let offset = first.start;
+            let mut word = vec![first];
+            while let Some(s) = syllables.next_if(|s| !s.word_start) {
+                word.push(s);
             }
-            last_end = end;
-        });
+            let end = syllables.peek().map_or(self.symbols.len(), |s| s.start);
+            for s in &mut word {
+                s.start -= offset;
+                s.word_start = false;
+            }
+            words.push(Word {
+                symbols: self.symbols[offset..end].to_vec(),
+                syllables: word,
+            });
+        }
+        words
+    }
 
-        // Build a new word from the remaining matches
-        let n = w.symbols.len();
-        let mut head = 0;
-        let mut symbols = w.symbols.drain();
-        for (start, end, content) in matches.into_iter() {
-            if head < start {
-                for _ in 0..(start - head) {
-                    new.push(symbols.next().unwrap());
-                    head += 1;
-                }
+    fn word_starts_at(&self, pos: usize) -> bool {
+        self.syllables
+            .iter()
+            .any(|s| s.start == pos && s.word_start)
+    }
+
+    fn syllable_index(&self, pos: usize) -> Option<usize> {
+        self.syllables.iter().position(|s| s.start == pos)
+    }
+
+    /// Returns the end of the syllable starting at `pos`
+    fn syllable_end(&self, pos: usize) -> Option<usize> {
+        let i = self.syllable_index(pos)?;
+        Some(
+            self.syllables
+                .get(i + 1)
+                .map_or(self.symbols.len(), |s| s.start),
+        )
+    }
+
+    /// Replaces the symbols in `start..end`, dropping the syllables that started inside it
+    fn splice(&mut self, start: usize, end: usize, new: impl IntoIterator<Item = Symbol>) {
+        let old_len = self.symbols.len();
+        self.symbols.splice(start..end, new);
+        let len = self.symbols.len();
+        self.syllables
+            .retain(|s| s.start <= start || s.start >= end);
+        for s in &mut self.syllables {
+            if s.start > start && s.start >= end {
+                s.start = s.start + len - old_len;
             }
-            assert!(head == start);
-            new.append(content);
-            head = end;
         }
-        w.symbols = new;
+        // A syllable that got moved onto the start of another one still starts a word if either did
+        self.syllables.dedup_by(|s, prev| {
+            let same = s.start == prev.start;
+            if same {
+                prev.word_start |= s.word_start;
+            }
+            same
+        });
+        self.syllables.retain(|s| s.start < len);
     }
 }
 
-#[derive(Debug, Clone)]
-struct Word {
-    symbols: Vec<Symbol>,
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, s) in self.symbols.iter().enumerate() {
+            if i != 0 && self.syllable_index(i).is_some() {
+                f.write_str(".")?;
+            }
+            f.write_str(&s.symbol)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
-struct Lexurgy {
+pub struct Lexurgy {
+    symbols: SymbolTable,
+    deromanizer: Option<Rule>,
     rules: Vec<Rule>,
+    romanizer: Option<Rule>,
+    /// The rules that had expressions left out
+    incomplete: Vec<String>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApplyError {
+    UnknownRule(String),
+    /// A propagating rule brought the word back to a form it already had, so it would never
+    /// settle. `word` is the word the rule was applied to
+    Cycle {
+        rule: String,
+        word: String,
+    },
+    /// A rule kept changing the word past the step budget, or a propagating one past its
+    /// iteration limit
+    TooManySteps {
+        rule: String,
+        word: String,
+    },
+}
+
+impl fmt::Display for ApplyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApplyError::UnknownRule(name) => write!(f, "No rule named `{}`", name),
+            ApplyError::Cycle { rule, word } => write!(
+                f,
+                "The rule `{}` loops on `{}`, changing it back to an earlier form",
+                rule, word
+            ),
+            ApplyError::TooManySteps { rule, word } => {
+                write!(f, "The rule `{}` never stops changing `{}`", rule, word)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
 impl Lexurgy {
-    fn from_ast(ast: Vec<Stmt>) -> Lexurgy {
+    /// Expressions using syntax the engine doesn't support yet are left out of their rule.
+    /// Intermediate romanizers are ignored
+    pub fn from_ast(ast: Vec<Stmt>) -> Lexurgy {
+        let mut symbols = vec![];
+        for stmt in &ast {
+            if let Stmt::SymbolDecl(syms, _) = stmt {
+                symbols.extend(syms.iter().cloned());
+            }
+        }
+        let symbols = SymbolTable::new(symbols);
+
+        let mut lexurgy = Lexurgy {
+            symbols,
+            deromanizer: None,
+            rules: vec![],
+            romanizer: None,
+            incomplete: vec![],
+        };
+        for stmt in ast {
+            match stmt {
+                Stmt::ClassDecl(ClassDecl { name, elements }) => {
+                    lexurgy.symbols.declare_class(&name, &elements);
+                }
+                Stmt::FeatureDecl(decl) => lexurgy.symbols.declare_feature(&decl),
+                Stmt::SymbolDecl(syms, matrix) if !matrix.is_empty() => {
+                    for sym in syms {
+                        lexurgy.symbols.declare_matrix(&sym, &matrix);
+                    }
+                }
+                Stmt::Deromanizer(_, block) => {
+                    lexurgy.deromanizer = Some(lexurgy.compile("deromanizer", &block, false));
+                }
+                Stmt::Romanizer(_, block) => {
+                    lexurgy.romanizer = Some(lexurgy.compile("romanizer", &block, false));
+                }
+                Stmt::ChangeRule(ChangeRule {
+                    name,
+                    modifiers,
+                    block,
+                }) => {
+                    let mut rule =
+                        lexurgy.compile(&name, &block, modifiers.contains(&Modifier::Propagate));
+                    rule.cleanup = modifiers.contains(&Modifier::Cleanup);
+                    lexurgy.rules.push(rule);
+                }
+                _ => {}
+            }
+        }
+        lexurgy
+    }
+
+    fn compile(&mut self, name: &str, block: &Block, iterate: bool) -> Rule {
+        let mut complete = true;
+        let block = RuleBlock::compile(block, &self.symbols, &mut complete);
+        if !complete {
+            self.incomplete.push(name.to_string());
+        }
+        Rule {
+            name: name.to_string(),
+            block,
+            iterate,
+            cleanup: false,
+        }
     }
 
-    fn apply(&self, ws: &mut Vec<Word>) {
-        for rule in self.rules {
+    /// The names of the rules that use syntax the engine doesn't support, and so don't do
+    /// everything they should
+    pub fn incomplete_rules(&self) -> &[String] {
+        &self.incomplete
+    }
+
+    pub fn word(&self, s: &str) -> Word {
+        Word::from_str(s, &self.symbols)
+    }
+
+    pub fn apply(&self, ws: &mut [Word]) -> Result<(), ApplyError> {
+        for word in ws.iter_mut() {
+            self.apply_rules(0..self.rules.len(), word)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the rules in `range` in order. After each one the cleanup rules declared before it
+    /// are applied again, even the ones before the start of the range
+    fn apply_rules(&self, range: ops::Range<usize>, w: &mut Word) -> Result<(), ApplyError> {
+        let mut steps = 0;
+        for i in range {
+            let rule = &self.rules[i];
+            rule.apply(w, &mut steps)?;
+            if rule.cleanup {
+                continue;
+            }
+            for cleanup in self.rules[..i].iter().filter(|r| r.cleanup) {
+                cleanup.apply(w, &mut steps)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the rules from `start_at` up to the one before `stop_before`, from the first and up
+    /// to the last one if not given
+    pub fn apply_range(
+        &self,
+        ws: &mut [Word],
+        start_at: Option<&str>,
+        stop_before: Option<&str>,
+    ) -> Result<(), ApplyError> {
+        let index = |name: &str| {
+            self.rules
+                .iter()
+                .position(|r| r.name == name)
+                .ok_or_else(|| ApplyError::UnknownRule(name.to_string()))
+        };
+        let start = start_at.map(index).transpose()?.unwrap_or(0);
+        let end = stop_before
+            .map(index)
+            .transpose()?
+            .unwrap_or(self.rules.len());
+        if start < end {
             for word in ws.iter_mut() {
-                rule.apply(&mut w);
+                self.apply_rules(start..end, word)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns romanized words into phonetic ones, leaving them as they are without a deromanizer
+    pub fn deromanize(&self, ws: &mut [Word]) -> Result<(), ApplyError> {
+        if let Some(rule) = &self.deromanizer {
+            for w in ws.iter_mut() {
+                rule.apply(w, &mut 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns phonetic words into romanized ones, leaving them as they are without a romanizer
+    pub fn romanize(&self, ws: &mut [Word]) -> Result<(), ApplyError> {
+        if let Some(rule) = &self.romanizer {
+            for w in ws.iter_mut() {
+                rule.apply(w, &mut 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the rules to the words as a phrase, so rules can see the edges of the neighbouring
+    /// words through `$$`. Words the rules delete entirely drop out of the phrase
+    pub fn apply_phrase(&self, ws: &mut Vec<Word>) -> Result<(), ApplyError> {
+        let mut phrase = Word::join(ws);
+        self.apply_rules(0..self.rules.len(), &mut phrase)?;
+        *ws = phrase.split();
+        Ok(())
+    }
+}
+
+/// The graphemes a rule file accounts for in romanized input: its declared symbols, the members of
+/// its classes and whatever its deromanizer matches. Anything else passes through the
+/// deromanizer untouched
+#[derive(Debug)]
+pub struct Inventory {
+    symbols: SymbolTable,
+    graphemes: BTreeSet<String>,
+}
+
+impl Inventory {
+    pub fn from_ast(ast: &[Stmt]) -> Inventory {
+        let mut declared = vec![];
+        let mut texts = vec![];
+        for stmt in ast {
+            match stmt {
+                Stmt::SymbolDecl(syms, _) => declared.extend(syms.iter().cloned()),
+                Stmt::ClassDecl(ClassDecl { elements, .. }) => {
+                    elements.iter().for_each(|e| collect_texts(e, &mut texts));
+                }
+                Stmt::Deromanizer(_, block) => collect_block_texts(block, &mut texts),
+                _ => {}
+            }
+        }
+        let symbols = SymbolTable::new(declared.iter().cloned());
+        let mut graphemes = declared.into_iter().collect::<BTreeSet<_>>();
+        for text in texts {
+            graphemes.extend(symbols.split(text).into_iter().map(|s| s.symbol));
+        }
+        Inventory { symbols, graphemes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.graphemes.is_empty()
+    }
+
+    /// The graphemes of the romanized word `s` that aren't in the inventory, in the order they
+    /// first appear. Whitespace and syllable breaks are ignored
+    pub fn unknown(&self, s: &str) -> Vec<String> {
+        let mut unknown: Vec<String> = vec![];
+        for part in s.split(|c: char| c.is_whitespace() || c == '.') {
+            for sym in self.symbols.split(part) {
+                if !self.graphemes.contains(&sym.symbol) && !unknown.contains(&sym.symbol) {
+                    unknown.push(sym.symbol);
+                }
+            }
+        }
+        unknown
+    }
+}
+
+/// The input side of every expression of the block
+fn collect_block_texts<'a>(block: &'a Block, texts: &mut Vec<&'a str>) {
+    match block {
+        Block::BasicBlock { expressions } => {
+            for e in expressions {
+                if let Expression::Standard(e) = e {
+                    collect_texts(&e.from, texts);
+                }
             }
         }
+        Block::ThenBlock { first, then: other }
+        | Block::ElseBlock {
+            first,
+            else_: other,
+        } => {
+            collect_block_texts(first, texts);
+            collect_block_texts(other, texts);
+        }
     }
 }
 
+fn collect_texts<'a>(e: &'a Element, texts: &mut Vec<&'a str>) {
+    match e {
+        Element::Text(t) => texts.push(t),
+        Element::Sequence(es) | Element::List(es) => {
+            es.iter().for_each(|e| collect_texts(e, texts));
+        }
+        Element::Group(e) | Element::Repeater(e, _) => collect_texts(e, texts),
+        Element::Capture { element, .. } => collect_texts(element, texts),
+        _ => {}
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn symbols(syms: &[&str]) -> Vec<Symbol> {
+        syms.iter().map(|s| Symbol::new(s)).collect()
+    }
+
     #[test]
     fn test_basic_rules() {
+        let table = SymbolTable::default();
+        let rule = Rule {
+            name: "k-to-tʃ".to_string(),
+            block: RuleBlock::Changes(vec![Change {
+                pattern: Matcher::Sequence(
+                    symbols(&["k", "i"])
+                        .into_iter()
+                        .map(Matcher::Symbol)
+                        .collect(),
+                ),
+                replacement: Replacement::Symbols(
+                    symbols(&["tʃ", "i"])
+                        .into_iter()
+                        .map(Output::Symbol)
+                        .collect(),
+                ),
+                conditions: vec![],
+                exclusions: vec![],
+            }]),
+            iterate: false,
+            cleanup: false,
+        };
+        let mut w = Word::from_str("kiki", &table);
+        rule.apply(&mut w, &mut 0).unwrap();
+        assert_eq!(w.to_string(), "tʃitʃi");
+        assert_eq!(w.symbols, symbols(&["tʃ", "i", "tʃ", "i"]));
+    }
+
+    #[test]
+    fn test_word_round_trip() {
+        let lexurgy = Lexurgy::from_ast(lsc::lsc_file("Symbol tʃ, ts, t\n").unwrap());
+        let w = lexurgy.word("tʃatsta");
+        assert_eq!(w.symbols, symbols(&["tʃ", "a", "ts", "t", "a"]));
+        assert_eq!(w.to_string(), "tʃatsta");
+        assert_eq!(lexurgy.word(&w.to_string()), w);
+
+        let w = Word {
+            symbols: symbols(&["ts", "a", "tʃ"]),
+            syllables: vec![Syllable::default()],
+        };
+        assert_eq!(lexurgy.word(&w.to_string()), w);
+
+        let w = lexurgy.word("tʃa.tsta");
+        assert_eq!(w.syllables.len(), 2);
+        assert_eq!(w.to_string(), "tʃa.tsta");
+    }
+
+    #[test]
+    fn test_any_syllable() {
+        let lexurgy = Lexurgy::from_ast(
+            lsc::lsc_file(
+                "Feature (syllable) +stress\n\
+                 initial-stress:\n    <Syl> => [+stress] / $ _\n",
+            )
+            .unwrap(),
+        );
+        let mut ws = vec![lexurgy.word("ka.ta.na"), lexurgy.word("ta")];
+        lexurgy.apply(&mut ws).unwrap();
+        let stressed = |w: &Word| {
+            w.syllables
+                .iter()
+                .map(|s| s.features.contains("stress"))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stressed(&ws[0]), [true, false, false]);
+        assert_eq!(stressed(&ws[1]), [true]);
+        assert_eq!(ws[0].to_string(), "ka.ta.na");
+
+        // Consumes exactly one syllable, whatever its length
+        let lexurgy =
+            Lexurgy::from_ast(lsc::lsc_file("drop-final:\n    <Syl> => * / . _ $\n").unwrap());
+        let mut ws = vec![lexurgy.word("kan.tra.sta"), lexurgy.word("sta")];
+        lexurgy.apply(&mut ws).unwrap();
+        assert_eq!(ws[0].to_string(), "kan.tra");
+        assert_eq!(ws[1].to_string(), "sta");
+    }
+
+    fn evolve(rules: &str, words: &[&str]) -> Vec<String> {
+        let lexurgy = Lexurgy::from_ast(lsc::lsc_file(rules).unwrap());
+        let mut ws = words.iter().map(|w| lexurgy.word(w)).collect::<Vec<_>>();
+        lexurgy.apply(&mut ws).unwrap();
+        ws.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_bounded() {
+        assert_eq!(
+            evolve("lenition:\n    {p, t, k} => s / a _\n", &["apakta", "pak"]),
+            ["asasta", "pas"]
+        );
+        assert_eq!(
+            evolve("voicing:\n    {p, t, k} => {b, d, g}\n", &["pataka"]),
+            ["badaga"]
+        );
+        assert_eq!(
+            evolve("merge:\n    (a b)? c => x\n", &["abc", "ac", "c", "ababc"]),
+            ["x", "ax", "x", "abx"]
+        );
+        assert_eq!(
+            evolve("shorten:\n    a+ => a / _ $\n", &["baaa", "aba"]),
+            ["ba", "aba"]
+        );
+    }
+
+    #[test]
+    fn test_propagate() {
+        // Without propagating, only the first vowel after the `i` sees it
+        assert_eq!(
+            evolve("harmony:\n    e => i / i {k, t}* _\n", &["tieke"]),
+            ["tiike"]
+        );
+        assert_eq!(
+            evolve(
+                "harmony propagate:\n    e => i / i {k, t}* _\n",
+                &["tieke", "teke"]
+            ),
+            ["tiiki", "teke"]
+        );
+    }
+
+    #[test]
+    fn test_runaway_rules() {
+        let apply = |rules: &str, word: &str| {
+            let lexurgy = Lexurgy::from_ast(lsc::lsc_file(rules).unwrap());
+            lexurgy.apply(&mut [lexurgy.word(word)])
+        };
+        // Flips back and forth without ever settling
+        let err = apply("flip propagate:\n    {a, b} => {b, a}\n", "ab").unwrap_err();
+        assert_eq!(
+            err,
+            ApplyError::Cycle {
+                rule: "flip".to_string(),
+                word: "ab".to_string()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "The rule `flip` loops on `ab`, changing it back to an earlier form"
+        );
+        // Grows the word on every pass, never returning to an earlier form
+        assert_eq!(
+            apply("grow propagate:\n    a => a a\n", "a")
+                .unwrap_err()
+                .to_string(),
+            "The rule `grow` never stops changing `a`"
+        );
+        assert!(matches!(
+            apply("append propagate:\n    * => a / _ $\n", "a"),
+            Err(ApplyError::TooManySteps { rule, .. }) if rule == "append"
+        ));
+        // Propagating rules that settle are fine
+        assert_eq!(apply("shrink propagate:\n    a a => a\n", "aaaa"), Ok(()));
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let rules = "lenition:\n    g => k\n\
+                     spirantization:\n    k => x / _ k\n";
+        assert_eq!(evolve(rules, &["akga"]), ["axka"]);
+        // Normalizes the geminate `lenition` creates before `spirantization` can see it, and
+        // keeps doing so after later rules
+        let cleanup = "degeminate cleanup:\n    k k => k\n";
+        assert_eq!(
+            evolve(&format!("{}{}", cleanup, rules), &["akga", "akka"]),
+            ["aka", "aka"]
+        );
+        // Only the rules after it are cleaned up after
+        assert_eq!(
+            evolve(&format!("{}{}", rules, cleanup), &["akga"]),
+            ["axka"]
+        );
+        assert_eq!(
+            evolve(&format!("{}voicing:\n    x => k\n", cleanup), &["axk"]),
+            ["ak"]
+        );
+    }
+
+    #[test]
+    fn test_captures() {
+        let classes = "Symbol tʰ\nClass C {p, t, k, tʰ}\n";
+        assert_eq!(
+            evolve(
+                &format!("{}reduplicate:\n    (@C)$1 => $1 $1 / $ _\n", classes),
+                &["pa", "ta", "a"]
+            ),
+            ["ppa", "tta", "a"]
+        );
+        assert_eq!(
+            evolve(
+                &format!("{}copy:\n    (@C)$1 a => $1 a $1 a / $ _\n", classes),
+                &["kana", "tʰa"]
+            ),
+            ["kakana", "tʰatʰa"]
+        );
+        // Referring to a capture in the environment, degeminating
+        assert_eq!(
+            evolve(
+                &format!("{}degeminate:\n    (@C)$1 => * / _ $1\n", classes),
+                &["atta", "atka"]
+            ),
+            ["ata", "atka"]
+        );
+        assert_eq!(
+            evolve(
+                &format!("{}degeminate:\n    (@C)$1 => * / _ $1\n", classes),
+                &["attʰa"]
+            ),
+            ["attʰa"]
+        );
+        // Inexact references ignore diacritics, but restore what was captured
+        assert_eq!(
+            evolve(
+                &format!("{}degeminate:\n    (@C)$1 => * / _ ~$1\n", classes),
+                &["attʰa"]
+            ),
+            ["atʰa"]
+        );
+        assert_eq!(
+            evolve(
+                &format!("{}swap:\n    (@C)$1 a ~$1 => $1 i $1\n", classes),
+                &["tʰat"]
+            ),
+            ["tʰitʰ"]
+        );
+        // Symbols declared with a matrix are compared by their features instead
+        let decls = "Feature +aspirated\nFeature +trill\n\
+                     Symbol t [-aspirated]\nSymbol tʰ [+aspirated]\n\
+                     Symbol r [+trill]\nSymbol ʀ [+trill]\nClass C {t, tʰ, r, ʀ}\n";
+        let degeminate = format!("{}degeminate:\n    (@C)$1 => * / _ ~$1\n", decls);
+        assert_eq!(evolve(&degeminate, &["attʰa", "arʀa"]), ["attʰa", "aʀa"]);
+    }
+
+    #[test]
+    fn test_then_else() {
+        let words = ["pak", "pat"];
+        let stress = "stress:\n    a => á / _ k\n";
+        assert_eq!(
+            evolve(&format!("{}Then:\n    p => b\n", stress), &words),
+            ["bák", "pat"]
+        );
+        assert_eq!(
+            evolve(&format!("{}Else:\n    p => b\n", stress), &words),
+            ["pák", "bat"]
+        );
+        // Whichever matched, a word ends up with exactly one of the two effects
+        assert_eq!(
+            evolve(
+                &format!("{}Then:\n    p => b\nElse:\n    t => d\n", stress),
+                &words
+            ),
+            ["bák", "pad"]
+        );
+    }
+
+    #[test]
+    fn test_feature_variables() {
+        let decls = "Feature +nasal\nFeature place (labial, alveolar, velar)\n\
+                     Symbol m [+nasal labial]\nSymbol n [+nasal alveolar]\nSymbol ŋ [+nasal velar]\n\
+                     Symbol p [labial]\nSymbol t [alveolar]\nSymbol k [velar]\n";
+        assert_eq!(
+            evolve(
+                &format!(
+                    "{}assimilation:\n    [+nasal] => [$place] / _ [$place]\n",
+                    decls
+                ),
+                &["anpa", "amka", "anta", "ana", "anm"]
+            ),
+            ["ampa", "aŋka", "anta", "ana", "amm"]
+        );
+        // A variable used twice has to agree with itself
+        assert_eq!(
+            evolve(
+                &format!("{}cluster:\n    [$place] => * / _ [$place]\n", decls),
+                &["ampa", "anpa", "akka"]
+            ),
+            ["apa", "anpa", "aka"]
+        );
+        // Symbols without a matrix never match one
+        assert_eq!(
+            evolve(
+                &format!("{}denasal:\n    [+nasal] => [-nasal]\n", decls),
+                &["man"]
+            ),
+            ["pat"]
+        );
+        // Symbols declared before a feature have its default value
+        let decls = "Feature +nasal\nSymbol m [+nasal]\nFeature place (labial, velar)\n\
+                     Symbol ŋ [+nasal velar]\nSymbol p [labial]\n";
+        assert_eq!(
+            evolve(&format!("{}x:\n    [$place] => a\n", decls), &["mpŋ"]),
+            ["aaa"]
+        );
+        assert_eq!(
+            evolve(&format!("{}y:\n    [+nasal] => [velar]\n", decls), &["mp"]),
+            ["ŋp"]
+        );
+    }
+
+    #[test]
+    fn test_negated_values() {
+        let decls = "Feature +nasal\nFeature +voice\nFeature place (labial, alveolar)\n\
+                     Symbol m [+nasal +voice labial]\nSymbol m̥ [+nasal labial]\n\
+                     Symbol n [+nasal +voice alveolar]\nSymbol p [labial]\n\
+                     Symbol b [+voice labial]\nSymbol t [alveolar]\n";
+        assert_eq!(
+            evolve(
+                &format!("{}debuccalize:\n    [+nasal !+voice] => h\n", decls),
+                &["am̥a", "ama", "apa"]
+            ),
+            ["aha", "ama", "apa"]
+        );
+        // Symbols without a matrix don't match a negated value either
+        assert_eq!(
+            evolve(&format!("{}mark:\n    [!+voice] => x\n", decls), &["pam̥bn"]),
+            ["xaxbn"]
+        );
+        assert_eq!(
+            evolve(
+                &format!("{}mark:\n    [!labial !+nasal] => x\n", decls),
+                &["tpnb"]
+            ),
+            ["xpnb"]
+        );
+        // There's nothing to change a feature to when it's negated
+        let lexurgy = Lexurgy::from_ast(
+            lsc::lsc_file(&format!("{}devoice:\n    [+nasal] => [!+voice]\n", decls)).unwrap(),
+        );
+        assert_eq!(lexurgy.incomplete_rules(), ["devoice"]);
+    }
+
+    fn evolve_phrase(rules: &str, words: &[&str]) -> Vec<String> {
+        let lexurgy = Lexurgy::from_ast(lsc::lsc_file(rules).unwrap());
+        let mut ws = words.iter().map(|w| lexurgy.word(w)).collect::<Vec<_>>();
+        lexurgy.apply_phrase(&mut ws).unwrap();
+        ws.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_between_words() {
+        let sandhi = "sandhi:\n    n => m / _ $$ {p, b}\n";
+        assert_eq!(
+            evolve_phrase(sandhi, &["kan", "pa", "tan", "ka", "sin"]),
+            ["kam", "pa", "tan", "ka", "sin"]
+        );
+        // Words on their own are never followed by another word
+        assert_eq!(evolve(sandhi, &["kan", "pa"]), ["kan", "pa"]);
+
+        // Word boundaries still apply to each word of the phrase
+        assert_eq!(
+            evolve_phrase("final:\n    a => e / _ $\n", &["ka.ta", "na"]),
+            ["ka.te", "ne"]
+        );
+        assert_eq!(
+            evolve_phrase("initial:\n    k => g / $ _\n", &["kak", "ka"]),
+            ["gak", "ga"]
+        );
+        let lexurgy = Lexurgy::from_ast(vec![]);
+        let ws = vec![lexurgy.word("ka.ta"), lexurgy.word("na")];
+        assert_eq!(Word::join(&ws).split(), ws);
+    }
+
+    #[test]
+    fn test_inventory() {
+        let ast = lsc::lsc_file(
+            "Symbol tʃ\nClass vowel {a, i}\n\
+             Deromanizer:\n    ch => tʃ\n    {y, w} => {j, ʋ}\n",
+        )
+        .unwrap();
+        let inventory = Inventory::from_ast(&ast);
+        assert!(!inventory.is_empty());
+        assert!(inventory.unknown("chai.ya").is_empty());
+        assert!(inventory.unknown("tʃa wi").is_empty());
+        assert_eq!(inventory.unknown("kachiq"), ["k", "q"]);
+        assert_eq!(inventory.unknown("qéq"), ["q", "é"]);
+
+        assert!(Inventory::from_ast(&lsc::lsc_file("Feature +voice\n").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_romanizers_and_ranges() {
+        let lexurgy = Lexurgy::from_ast(
+            lsc::lsc_file(
+                "Deromanizer:\n    c => k\n\
+                 init:\n    unchanged\n\
+                 voicing:\n    k => g / a _ a\n\
+                 raising:\n    a => e / _ $\n\
+                 skipped:\n    [+voice] => [-voice]\n    :voicing\n\
+                 Romanizer:\n    g => gh\n",
+            )
+            .unwrap(),
+        );
+        assert_eq!(lexurgy.incomplete_rules(), ["skipped"]);
+
+        let run = |start_at, stop_before| {
+            let mut ws = vec![lexurgy.word("caca")];
+            lexurgy.deromanize(&mut ws)?;
+            lexurgy.apply_range(&mut ws, start_at, stop_before)?;
+            let phon = ws[0].to_string();
+            lexurgy.romanize(&mut ws)?;
+            Ok((phon, ws[0].to_string()))
+        };
+        let s = |p: &str, r: &str| Ok((p.to_string(), r.to_string()));
+        assert_eq!(run(None, None), s("kage", "kaghe"));
+        assert_eq!(run(None, Some("init")), s("kaka", "kaka"));
+        assert_eq!(run(Some("raising"), None), s("kake", "kake"));
+        assert_eq!(
+            run(None, Some("nope")),
+            Err(ApplyError::UnknownRule("nope".to_string()))
+        );
     }
 }
@@ -1,39 +1,253 @@
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::*;
 
 use anyhow::{anyhow, bail, Context, Result};
-use clap::{Args, Parser, Subcommand};
-use rusqlite::{params, Connection, Row};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rusqlite::{params, Connection, OptionalExtension, Row};
 use serde::{Deserialize, Serialize};
 use tinytemplate::TinyTemplate;
+use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Deserialize)]
 struct Settings {
+    /// The only required setting, see `parse_settings`
     db_file: String,
-    db_dev_file: String,
+    /// The database used in debug mode, see `Settings::dev_db_file`
+    db_dev_file: Option<String>,
+    #[serde(default = "default_word_list_folder")]
     word_list_folder: String,
+    #[serde(default = "default_rule_list_folder")]
     rule_list_folder: String,
+    #[serde(default = "default_dictionary_file_template")]
     dictionary_file_template: String,
+    #[serde(default = "default_dictionary_template")]
     dictionary_template: String,
+    reverse_dictionary_file_template: Option<String>,
+    reverse_dictionary_template: Option<String>,
+    /// How the dictionary files are encoded
+    #[serde(default)]
+    dictionary_encoding: Encoding,
+    #[serde(default)]
     auto_dump: bool,
+    /// The parts-of-speech words can belong to
+    #[serde(default = "default_kinds")]
+    kinds: Vec<String>,
+    /// The order of the kinds when dumping with `--group-by kind`, the rest come after
+    #[serde(default)]
+    kind_order: Vec<String>,
+    /// What the gloss abbreviations used in meanings and notes stand for, like `PST = "past"`
+    #[serde(default)]
+    glosses: collections::BTreeMap<String, String>,
+    /// What runs the sound changes
+    #[serde(default)]
+    engine: Engine,
+    /// Reject new words with an empty meaning
+    #[serde(default)]
+    require_meaning: bool,
+    /// Reject new words with an empty kind
+    #[serde(default)]
+    require_kind: bool,
+    /// How the word list files passed to and from Lexurgy are named, see `Config::word_list_file`
+    #[serde(default)]
+    word_lists: WordListNames,
+    /// How the IPA of the words is delimited in the dictionary files
+    #[serde(default)]
+    ipa_delimiter: IpaDelimiter,
+    /// Look words up by their romanization ignoring case, for languages where capitalization
+    /// isn't phonemic
+    #[serde(default)]
+    case_insensitive_lookup: bool,
+}
+
+/// The names of Lexurgy's word list files, in case a version of Lexurgy names them differently
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+struct WordListNames {
+    /// The extension of plain word lists
+    extension: String,
+    /// The extension of the word lists with every stage of a word, for `LexurgyOutput::Both`
+    comparison_extension: String,
+    /// The folder in the word list folder Lexurgy is told to write its output to
+    output_folder: String,
+    /// What Lexurgy adds to the name of the input word list to name its output
+    output_suffix: String,
+}
+
+impl Default for WordListNames {
+    fn default() -> Self {
+        WordListNames {
+            extension: "wli".to_string(),
+            comparison_extension: "wlm".to_string(),
+            output_folder: "out".to_string(),
+            output_suffix: "_ev".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum Engine {
+    /// The external `lexurgy` program
+    #[default]
+    Lexurgy,
+    /// The built-in engine from the `sc` crate, see `NativeEngine`
+    Native,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum IpaDelimiter {
+    /// Bare, leaving any delimiters to the template
+    #[default]
+    None,
+    /// `/.../`
+    Slashes,
+    /// `[...]`
+    Brackets,
+}
+
+impl IpaDelimiter {
+    fn render(self, ipa: &str) -> String {
+        let ipa = normalize_ipa(ipa);
+        match self {
+            IpaDelimiter::None => ipa.to_string(),
+            IpaDelimiter::Slashes => format!("/{}/", ipa),
+            IpaDelimiter::Brackets => format!("[{}]", ipa),
+        }
+    }
+}
+
+fn default_word_list_folder() -> String {
+    "words".to_string()
+}
+
+fn default_rule_list_folder() -> String {
+    "rules".to_string()
+}
+
+fn default_dictionary_file_template() -> String {
+    "{lang.name}.md".to_string()
+}
+
+fn default_dictionary_template() -> String {
+    "# {lang.name}\n\n{{ for w in words }}- **{w.romanization}** ({w.kind}): {w.meaning}\n{{ endfor }}"
+        .to_string()
+}
+
+fn default_kinds() -> Vec<String> {
+    ["v", "n", "adv", "adj", "inj", "conj", "adp"]
+        .iter()
+        .map(|k| k.to_string())
+        .collect()
+}
+
+impl Settings {
+    /// `db_dev_file`, defaulting to `db_file` with `_dev` added to its name
+    fn dev_db_file(&self) -> String {
+        if let Some(dev) = &self.db_dev_file {
+            return dev.clone();
+        }
+        // Only the file name is changed, dots in the folders aren't extensions
+        let path = Path::new(&self.db_file);
+        let Some(stem) = path.file_stem() else {
+            return format!("{}_dev", self.db_file);
+        };
+        let mut name = stem.to_os_string();
+        name.push("_dev");
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        path.with_file_name(name).to_string_lossy().into_owned()
+    }
+
+    /// Rejects parts-of-speech not listed in `kinds`, unless `force` is set
+    fn validate_kind(&self, kind: &str, force: bool) -> Result<()> {
+        if force || self.kinds.iter().any(|k| k == kind) {
+            return Ok(());
+        }
+        bail!(
+            "Unknown kind `{}`, expected one of: {}\nIf you want to use it anyway, use the --force-kind flag.",
+            kind,
+            self.kinds.join(", ")
+        )
+    }
+
+    /// Rejects the meaning or kind of a new word for being empty, if `require_meaning` or
+    /// `require_kind` is set
+    fn validate_required(&self, meaning: &str, kind: &str) -> Result<()> {
+        if self.require_meaning && meaning.trim().is_empty() {
+            bail!("Words need a meaning, `require_meaning` is set in Wdb.toml");
+        }
+        if self.require_kind && kind.trim().is_empty() {
+            bail!("Words need a kind, `require_kind` is set in Wdb.toml");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum Encoding {
+    #[default]
+    Utf8,
+    /// UTF-8 starting with a byte order mark
+    Utf8Bom,
+    Utf16le,
+}
+
+impl Encoding {
+    fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Utf8Bom => [&[0xef, 0xbb, 0xbf], text.as_bytes()].concat(),
+            Encoding::Utf16le => iter::once(0xfeff)
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+        }
+    }
 }
 
 struct Config {
     root: PathBuf,
     debug_mode: bool,
+    keep_temps: bool,
+    /// Overrides both `db_file` and `db_dev_file` when set
+    db_file: Option<PathBuf>,
+    /// Run sound changes with the native engine instead of Lexurgy
+    native: bool,
+    /// Ignore the case of romanizations when looking words up. Like SQLite's `NOCASE` this only
+    /// folds ASCII letters
+    ignore_case: bool,
     settings: Settings,
-    word_list_folder: cell::OnceCell<PathBuf>,
-    rule_list_folder: cell::OnceCell<PathBuf>,
+    word_list_folder: sync::OnceLock<PathBuf>,
+    rule_list_folder: sync::OnceLock<PathBuf>,
 }
 
 impl Config {
     fn new(root: PathBuf, settings: Settings, debug_mode: bool) -> Config {
         Config {
             root,
+            native: settings.engine == Engine::Native,
+            ignore_case: settings.case_insensitive_lookup,
             settings,
             debug_mode,
-            word_list_folder: cell::OnceCell::new(),
-            rule_list_folder: cell::OnceCell::new(),
+            keep_temps: debug_mode,
+            db_file: None,
+            word_list_folder: sync::OnceLock::new(),
+            rule_list_folder: sync::OnceLock::new(),
+        }
+    }
+
+    fn db_path(&self) -> PathBuf {
+        if let Some(db_file) = &self.db_file {
+            db_file.clone()
+        } else if self.debug_mode {
+            self.root.join(self.settings.dev_db_file())
+        } else {
+            self.root.join(&self.settings.db_file)
         }
     }
 
@@ -47,6 +261,39 @@ impl Config {
         p.as_path()
     }
 
+    /// The word list file wdb writes the words it passes to Lexurgy to
+    fn word_list_file(&self, name: &str) -> PathBuf {
+        let mut path = self.word_list_folder().join(name);
+        path.set_extension(&self.settings.word_lists.extension);
+        path
+    }
+
+    /// The folder Lexurgy is told to write its output to
+    fn lexurgy_output_folder(&self) -> PathBuf {
+        self.word_list_folder()
+            .join(&self.settings.word_lists.output_folder)
+    }
+
+    /// Where Lexurgy writes the output for the word list `name`
+    fn lexurgy_output_file(&self, name: &str, format: LexurgyOutput) -> PathBuf {
+        let names = &self.settings.word_lists;
+        let mut path = self
+            .lexurgy_output_folder()
+            .join(format!("{}{}", name, names.output_suffix));
+        path.set_extension(if format == LexurgyOutput::Both {
+            &names.comparison_extension
+        } else {
+            &names.extension
+        });
+        path
+    }
+
+    fn rule_file(&self, lang: &LangEntry) -> PathBuf {
+        let mut lsc = self.rule_list_folder().join(&lang.rule);
+        lsc.set_extension("lsc");
+        lsc
+    }
+
     fn rule_list_folder(&self) -> &Path {
         let p = self.rule_list_folder.get_or_init(|| {
             let mut b = PathBuf::new();
@@ -66,6 +313,27 @@ struct Cli {
     disable_checks: bool,
     #[arg(long)]
     debug_mode: bool,
+    /// Keep the word list files passed to and from Lexurgy (implied by `--debug-mode`)
+    #[arg(long)]
+    keep_temps: bool,
+    /// Use this database file instead of the one configured in `Wdb.toml` (even with
+    /// `--debug-mode`)
+    #[arg(long)]
+    db: Option<PathBuf>,
+    /// Run sound changes with the built-in engine instead of Lexurgy (like `engine = "native"` in
+    /// `Wdb.toml`). It doesn't support everything Lexurgy does yet
+    #[arg(long)]
+    native: bool,
+    /// Ignore case when looking words up by their romanization (like `case_insensitive_lookup =
+    /// true` in `Wdb.toml`)
+    #[arg(long, global = true)]
+    ignore_case: bool,
+    /// Report a failure as a JSON object on STDERR, with `error` and the `causes` leading to it
+    #[arg(long)]
+    json: bool,
+    /// List the language ids, one per line, for completion scripts
+    #[arg(long, hide = true)]
+    complete_langs: bool,
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -74,1079 +342,8214 @@ struct Cli {
 enum Command {
     /// Read mutliple comamnds from STDIN
     Interactive,
+    /// Set the working language, used by commands whose language is left out
+    Use(UseArgs),
     /// Add a new word
     Add(AddArgs),
     /// Edit a word
     Edit(EditArgs),
+    /// Swap the meaning, kind, note and origin of two homophones
+    Swap(SwapArgs),
+    /// Add the words listed in a CSV file
+    Import(ImportArgs),
     /// Inherit a word from the language's predecessor
     Inherit(InheritArgs),
+    /// Evolve the inherited words of a language again, after its rules changed
+    Rebuild(RebuildArgs),
     /// Delete a word
     Del(DelArgs),
+    /// Delete words identical to an older one in spelling, meaning and kind
+    Dedupe(DedupeArgs),
+    /// Delete languages without any words or descendants
+    Prune(PruneArgs),
+    /// Show the changes made to a word over time
+    History(HistoryArgs),
     /// Evolve a sentence
     Evolve(EvolveArgs),
     /// Dump a language's lexical inventory
     Dump(DumpArgs),
+    /// Dump a language again whenever the database changes, until interrupted
+    Watch(WatchArgs),
+    /// Export a language's words as JSON or as an Anki deck
+    Export(ExportArgs),
+    /// Export every language and their words as JSON
+    ExportAll(ExportAllArgs),
     /// List all languages
-    List,
+    List(ListArgs),
+    /// Show the language family as a tree
+    Tree,
+    /// Export the language family as a Graphviz DOT graph
+    FamilyDot(FamilyDotArgs),
+    /// Check that every language's rule file exists and can be parsed
+    Check,
+    /// Check that every word's romanization only uses graphemes its language's rule file declares
+    Validate,
+    /// Print a completion script for the given shell
+    Completions(CompletionsArgs),
+    /// Show a language's details along with its parent and children
+    LangInfo(LangInfoArgs),
+    /// Count how often each sound appears in a language's words
+    Phonology(PhonologyArgs),
+    /// Count how often each letter appears in a language's romanizations
+    Freq(FreqArgs),
+    /// Search for words by their romanization or meaning
+    Search(SearchArgs),
     /// Generate phonetic annotations for words based on thier romanization
     Phon(PhonArgs),
+    /// Manage alternative pronunciations of a word (like dialectal ones)
+    Ipa(IpaArgs),
+    /// Manage the example sentences of a word
+    Example(ExampleArgs),
+    /// Generate random new words following a language's syllable patterns
+    Random(RandomArgs),
+    /// Run words through a language's sound changes, without touching the database
+    Sc(ScArgs),
+    /// Show how words would be pronounced, without adding them
+    Pron(PronArgs),
+    /// List the words spelled similarly to the given one
+    Suggest(SuggestArgs),
+    /// Show the word coming alphabetically after the given one
+    Next(NeighbourArgs),
+    /// Show the word coming alphabetically before the given one
+    Prev(NeighbourArgs),
 }
 
 #[derive(Args)]
 struct DumpArgs {
     /// ID of the target language
     language: String,
+    /// Dump the reverse dictionary (keyed by meaning) instead
+    #[arg(short, long)]
+    reverse: bool,
+    /// Leave out words with any of these tags
+    #[arg(long)]
+    exclude_tag: Vec<String>,
+    /// Only include words with at least one of these tags
+    #[arg(long)]
+    include_tag: Vec<String>,
+    /// Don't pass the words' flags on to the template
+    #[arg(long)]
+    strip_flags: bool,
+    /// Also pass the words to the template as `groups`, split up by the given field
+    #[arg(long, conflicts_with = "reverse")]
+    group_by: Option<GroupBy>,
+    /// Print the dictionary to STDOUT instead of writing it to the file named by
+    /// `dictionary_file_template`, which isn't rendered at all then
+    #[arg(long)]
+    dictionary_only: bool,
 }
 
-#[derive(Args, Debug)]
-struct AddArgs {
-    /// The language to add the word to
-    language: String,
-    /// The romanized spelling of the word
-    word: String,
-    /// The meaning of the word
-    meaning: String,
-    /// The part-of-speech the word belond to (v, n, adv, adj, inj, conj, adp)
-    kind: String,
-    /// Where the word comes from (unspecified means it's a neoglism)
-    #[arg(short, long)]
-    origin: Option<String>,
-    /// Attach a note to the word (arbitrary text)
-    #[arg(short, long)]
-    note: Option<String>,
-    /// The phonetic transcription of the word
-    #[arg(short, long)]
-    ipa: Option<String>,
-    /// Disable auto-deromanization
-    #[arg(short = 'D', long)]
-    disable_autorom: bool,
-    /// Allow definining the word to be a homophone of any existing words
-    #[arg(short = 'H', long)]
-    homophone: bool,
+/// A dumped dictionary, along with the file it's written to unless it's only printed
+struct RenderedDictionary {
+    file: Option<PathBuf>,
+    text: String,
 }
 
-#[derive(Args, Debug)]
-struct EditArgs {
-    /// The language to add the word to
-    language: String,
-    /// The romanized spelling of the word
-    word: String,
-    /// The meaning of the word
-    #[arg(short, long)]
-    meaning: Option<String>,
-    /// The part-of-speech the word belond to (v, n, adv, adj, inj, conj, adp)
-    #[arg(short, long)]
-    kind: Option<String>,
-    /// Where the word comes from (unspecified means it's a neoglism)
-    #[arg(short, long)]
-    origin: Option<String>,
-    /// Attach a note to the word (arbitrary text)
-    #[arg(short, long)]
-    note: Option<String>,
-    /// The phonetic transcription of the word
-    #[arg(short, long)]
-    ipa: Option<String>,
-    /// Disable auto-deromanization
-    #[arg(short = 'D', long)]
-    disable_autorom: bool,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupBy {
+    /// Ordered by the `kind_order` in `Wdb.toml`, then alphabetically
+    Kind,
+    /// The first letter of the romanization, lowercased
+    Letter,
+    /// A word is in the group of every tag it has, untagged words are in a last group named ""
+    Tag,
 }
 
-#[derive(Args, Debug)]
-struct InheritArgs {
-    /// The language to add the word to
-    language: String,
-    /// The romanized spelling of the word
-    word: String,
-    /// The meaning of the word
-    #[arg(short, long)]
-    meaning: Option<String>,
-    /// The part-of-speech the word belond to (v, n, adv, adj, inj, conj, adp)
-    #[arg(short, long)]
-    kind: Option<String>,
-    /// Attach a note to the word (arbitrary text)
-    #[arg(short, long)]
-    note: Option<String>,
+/// The words of a dictionary sharing a kind, letter or tag, see `DumpArgs::group_by`
+#[derive(Debug, Serialize)]
+struct WordGroup<'a> {
+    name: String,
+    words: Vec<&'a WordEntry>,
 }
 
-#[derive(Args, Debug)]
-struct DelArgs {
-    /// The language to add the word to
-    language: String,
-    /// The romanized spelling of the word
-    word: String,
+/// Splits up `words` into groups, keeping the words of every group in order. A word is added to
+/// every group `names` gives for it
+fn group_words<'a>(
+    words: &'a [WordEntry],
+    names: impl Fn(&WordEntry) -> Vec<String>,
+) -> Vec<WordGroup<'a>> {
+    let mut groups: Vec<WordGroup> = vec![];
+    for w in words {
+        for name in names(w) {
+            match groups.iter_mut().find(|g| g.name == name) {
+                Some(g) => g.words.push(w),
+                None => groups.push(WordGroup {
+                    name,
+                    words: vec![w],
+                }),
+            }
+        }
+    }
+    groups
 }
 
-#[derive(Args, Debug)]
-struct EvolveArgs {
-    /// The source language
-    from_lang: String,
-    /// The target language
-    to_lang: String,
-    /// The sentence to evolve
-    sentence: Vec<String>,
-    #[arg(short = 'b')]
-    stop_before: Option<String>,
-    #[arg(short = 'a')]
-    start_at: Option<String>,
-    #[arg(short = 'p')]
-    show_phonetic: bool,
-    /// Show intermediate versions
-    #[arg(short = 'i', long)]
-    show_intermediate: bool,
+/// Groups `words` by kind, putting the kinds listed in `order` first in that order
+fn group_by_kind<'a>(words: &'a [WordEntry], order: &[String]) -> Vec<WordGroup<'a>> {
+    let mut groups = group_words(words, |w| vec![w.kind.clone()]);
+    groups.sort_by_cached_key(|g| {
+        let rank = order.iter().position(|k| *k == g.name);
+        (rank.unwrap_or(order.len()), g.name.clone())
+    });
+    groups
 }
 
-#[derive(Args, Debug)]
-struct PhonArgs {
-    #[arg(short, long = "lang")]
-    language: Option<String>,
-    /// Regenerate ALL phonetic annotation, not just the missing ones
-    #[arg(short, long)]
-    force: bool,
+/// Groups `words` by the lowercased first letter of their romanization, alphabetically
+fn group_by_letter(words: &[WordEntry]) -> Vec<WordGroup<'_>> {
+    let mut groups = group_words(words, |w| {
+        let first = w.romanization.graphemes(true).next().unwrap_or("");
+        vec![first.to_lowercase()]
+    });
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+    groups
 }
 
-/*
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
-enum OriginKind {
-    Inherited {
-        from_lang: String,
-        romanization: String,
-        word_id: u32,
-    },
-    Compound {
+/// Groups `words` by their tags alphabetically, with the untagged ones last
+fn group_by_tag(words: &[WordEntry]) -> Vec<WordGroup<'_>> {
+    let mut groups = group_words(words, |w| {
+        if w.flags.is_empty() {
+            vec![String::new()]
+        } else {
+            w.flags.iter().map(String::from).collect()
+        }
+    });
+    groups.sort_by_cached_key(|g| (g.name.is_empty(), g.name.clone()));
+    groups
+}
+
+impl DumpArgs {
+    fn new(language: String) -> DumpArgs {
+        DumpArgs {
+            language,
+            reverse: false,
+            exclude_tag: vec![],
+            include_tag: vec![],
+            strip_flags: false,
+            group_by: None,
+            dictionary_only: false,
+        }
+    }
 
+    /// Drops the words the tag filters exclude, and strips the flags of the rest if requested
+    fn filter(&self, words: &mut Vec<WordEntry>) {
+        words.retain(|w| {
+            let included =
+                self.include_tag.is_empty() || self.include_tag.iter().any(|t| w.flags.contains(t));
+            included && !self.exclude_tag.iter().any(|t| w.flags.contains(t))
+        });
+        if self.strip_flags {
+            for w in words {
+                w.flags = Flags::default();
+            }
+        }
     }
 }
-*/
 
-#[derive(Debug, Serialize)]
-struct WordEntry {
-    id: u32,
-    lang: String,
-    romanization: String,
-    ipa: Option<String>,
+/// A gloss abbreviation used by the dumped words, for the dictionary's legend
+#[derive(Debug, Serialize, PartialEq)]
+struct Gloss {
+    abbreviation: String,
     meaning: String,
-    kind: String,
-    origin: Option<String>,
-    flags: Option<String>,
-    note: Option<String>,
 }
 
-impl WordEntry {
-    fn from_row(row: &Row) -> rusqlite::Result<WordEntry> {
-        Ok(WordEntry {
-            id: row.get(0)?,
-            lang: row.get(1)?,
-            romanization: row.get(2)?,
-            ipa: row.get(3)?,
-            meaning: row.get(4)?,
-            kind: row.get(5)?,
-            origin: row.get(6)?,
-            flags: row.get(7)?,
-            note: row.get(8)?,
+/// Finds the abbreviations from `glosses` used in the meanings and notes of `words`
+fn gloss_legend(
+    glosses: &collections::BTreeMap<String, String>,
+    words: &[WordEntry],
+) -> Vec<Gloss> {
+    let mut used = collections::BTreeSet::new();
+    for w in words {
+        let text = [Some(&w.meaning), w.note.as_ref()];
+        for token in text
+            .into_iter()
+            .flatten()
+            .flat_map(|t| t.split(|c: char| !c.is_alphanumeric()))
+        {
+            if glosses.contains_key(token) {
+                used.insert(token);
+            }
+        }
+    }
+    used.into_iter()
+        .map(|abbr| Gloss {
+            abbreviation: abbr.to_string(),
+            meaning: glosses[abbr].clone(),
         })
+        .collect()
+}
+
+/// The tags in a word's `flags` column, separated by commas or whitespace there. They're written
+/// back sorted and separated by `, `, whatever form they were read in
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Flags(collections::BTreeSet<String>);
+
+impl Flags {
+    fn parse(flags: &str) -> Flags {
+        Flags(
+            flags
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    fn contains(&self, tag: &str) -> bool {
+        self.0.contains(tag)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
     }
 }
 
-#[derive(Debug, Serialize)]
-struct LangEntry {
-    id: String,
-    name: String,
-    origin: Option<String>,
-    rule: String,
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.iter().collect::<Vec<_>>().join(", "))
+    }
 }
 
-impl std::fmt::Display for LangEntry {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{} ({})", self.name, self.id)
+impl rusqlite::types::FromSql for Flags {
+    fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Flags> {
+        Ok(Option::<String>::column_result(value)?
+            .map(|f| Flags::parse(&f))
+            .unwrap_or_default())
     }
 }
 
-impl LangEntry {
-    fn from_row(row: &Row) -> rusqlite::Result<LangEntry> {
-        Ok(LangEntry {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            origin: row.get(2)?,
-            rule: row.get(3)?,
-        })
+impl rusqlite::ToSql for Flags {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
     }
 }
 
-fn normalize_text(s: &str) -> String {
-    s.trim().to_string()
+impl Serialize for Flags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
-struct Wdb {
-    db: Connection,
-    cfg: Config,
+#[derive(Args, Debug)]
+struct ExportArgs {
+    /// ID of the target language
+    language: String,
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Json)]
+    format: ExportFormat,
+    /// The fields shown on the front of the Anki cards
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "romanization,ipa"
+    )]
+    front: Vec<AnkiField>,
+    /// The fields shown on the back of the Anki cards
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "meaning")]
+    back: Vec<AnkiField>,
+    /// Only append the words added since the last `--append` export of the language to the
+    /// `--out` file, instead of rewriting it. Only for CSV
+    #[arg(long, requires = "out")]
+    append: bool,
+    /// With `--append`, rewrite the whole file and track new words from there on
+    #[arg(long, requires = "append")]
+    full: bool,
+    /// The document class of a LaTeX export
+    #[arg(long, default_value = "article")]
+    documentclass: String,
+    /// A file with extra LaTeX to put in the preamble of a LaTeX export, like font settings
+    #[arg(long)]
+    preamble: Option<PathBuf>,
+    #[command(flatten)]
+    output: ExportOutputArgs,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum LexurgyInput {
-    Romanized,
-    Phonetic,
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ExportFormat {
+    Json,
+    /// Tab separated front and back of a flashcard per word, importable by Anki
+    Anki,
+    /// A row per word with the columns of `CSV_HEADER`, importable with `import --header
+    /// --ipa-col 4`
+    Csv,
+    /// A LaTeX document with a `longtable` of the words, to be compiled with XeLaTeX or LuaLaTeX
+    /// for the IPA to show up
+    Latex,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum LexurgyOutput {
-    Romanized,
-    Phonetic,
-    Both,
-}
+/// The columns of a CSV export
+const CSV_HEADER: &str = "romanization,meaning,kind,ipa,origin,note";
 
-struct LexurgyCmd<'a> {
-    target_lang: &'a LangEntry,
-    output: Option<String>,
-    evolve: bool,
-    input_format: LexurgyInput,
-    output_format: LexurgyOutput,
-    stop_before: Option<String>,
-    start_at: Option<String>,
+/// Quotes the field if it contains a comma, quote or line break, doubling its quotes
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
 }
 
-enum WordOutput {
-    Phon(String),
-    Rom(String),
-    PhonRom(String, String),
+/// A CSV row per word, without the header
+fn csv_rows(words: &[WordEntry]) -> String {
+    let mut csv = String::new();
+    for word in words {
+        let fields = [
+            Some(&word.romanization[..]),
+            Some(&word.meaning[..]),
+            Some(&word.kind[..]),
+            word.ipa.as_deref(),
+            word.origin.as_deref(),
+            word.note.as_deref(),
+        ];
+        let fields = fields
+            .iter()
+            .map(|f| csv_field(f.unwrap_or("")))
+            .collect::<Vec<_>>();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
 }
 
-impl WordOutput {
-    fn get_value_ref(&self) -> Result<&str> {
-        match self {
-            WordOutput::Phon(x) => Ok(x),
-            WordOutput::Rom(x) => Ok(x),
-            WordOutput::PhonRom(_, _) => {
-                bail!("Expected a single value, but this word output has two")
+/// Escapes the characters LaTeX treats specially, and turns line breaks into spaces
+fn latex_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
             }
+            '\r' | '\n' => escaped.push(' '),
+            c => escaped.push(c),
         }
     }
+    escaped
+}
 
-    fn get_rom_ref(&self) -> Result<&str> {
-        match self {
-            WordOutput::Phon(_) => bail!("Expected romanized word, got phonetic word"),
-            WordOutput::Rom(x) => Ok(x),
-            WordOutput::PhonRom(_, x) => Ok(x),
-        }
+/// A standalone LaTeX document listing the words with their IPA, kind and meaning
+fn latex_document(export: &LangExport, class: &str, preamble: &str) -> String {
+    let mut doc = format!(
+        "\\documentclass{{{}}}\n\\usepackage{{fontspec}}\n\\usepackage{{longtable}}\n",
+        class
+    );
+    doc.push_str(preamble);
+    if !preamble.is_empty() && !preamble.ends_with('\n') {
+        doc.push('\n');
     }
-
-    fn get_phon_ref(&self) -> Result<&str> {
-        match self {
-            WordOutput::Phon(x) => Ok(x),
-            WordOutput::Rom(_) => bail!("Expected phonetic word, got romanized word"),
-            WordOutput::PhonRom(x, _) => Ok(x),
-        }
+    doc.push_str("\\begin{document}\n");
+    doc.push_str(&format!(
+        "\\section*{{{}}}\n",
+        latex_escape(&export.lang.name)
+    ));
+    doc.push_str("\\begin{longtable}{lllp{0.5\\textwidth}}\n");
+    for word in &export.words {
+        let ipa = word
+            .ipa
+            .as_deref()
+            .map(|ipa| IpaDelimiter::Brackets.render(ipa))
+            .unwrap_or_default();
+        doc.push_str(&format!(
+            "\\textbf{{{}}} & {} & \\textit{{{}}} & {} \\\\\n",
+            latex_escape(&word.romanization),
+            latex_escape(&ipa),
+            latex_escape(&word.kind),
+            latex_escape(&word.meaning)
+        ));
     }
+    doc.push_str("\\end{longtable}\n\\end{document}\n");
+    doc
+}
 
-    fn get_phon_rom_ref(&self) -> Result<(&str, &str)> {
-        match self {
-            WordOutput::PhonRom(p, r) => Ok((p, r)),
-            _ => bail!("Expected both phonetic and romanized versions of the word"),
-        }
-    }
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum AnkiField {
+    Romanization,
+    /// Shown in brackets
+    Ipa,
+    Meaning,
+    /// Shown in parentheses
+    Kind,
+    Note,
+}
 
-    fn get_value(self) -> Result<String> {
-        match self {
-            WordOutput::Phon(x) => Ok(x),
-            WordOutput::Rom(x) => Ok(x),
-            WordOutput::PhonRom(_, _) => {
-                bail!("Expected a single value, but this word output has two")
-            }
-        }
+impl AnkiField {
+    fn render(self, word: &WordEntry) -> Option<String> {
+        let value = match self {
+            AnkiField::Romanization => Some(word.romanization.clone()),
+            AnkiField::Ipa => word
+                .ipa
+                .as_deref()
+                .map(|ipa| IpaDelimiter::Brackets.render(ipa)),
+            AnkiField::Meaning => Some(word.meaning.clone()),
+            AnkiField::Kind => Some(format!("({})", word.kind)),
+            AnkiField::Note => word.note.clone(),
+        };
+        value.filter(|v| !v.is_empty())
     }
+}
 
-    fn get_rom(self) -> Result<String> {
-        match self {
-            WordOutput::Phon(_) => bail!("Expected romanized word, got phonetic word"),
-            WordOutput::Rom(x) => Ok(x),
-            WordOutput::PhonRom(_, x) => Ok(x),
-        }
-    }
+/// Renders a side of an Anki card, the fields are separated by spaces
+fn anki_side(word: &WordEntry, fields: &[AnkiField]) -> String {
+    let text = fields
+        .iter()
+        .filter_map(|f| f.render(word))
+        .collect::<Vec<_>>()
+        .join(" ");
+    // Anki reads the fields as HTML, and tabs and newlines would break up the TSV
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\t', " ")
+        .replace("\r\n", "<br>")
+        .replace('\n', "<br>")
+}
 
-    fn get_phon(self) -> Result<String> {
-        match self {
-            WordOutput::Phon(x) => Ok(x),
-            WordOutput::Rom(_) => bail!("Expected phonetic word, got romanized word"),
-            WordOutput::PhonRom(x, _) => Ok(x),
-        }
+fn anki_deck(words: &[WordEntry], front: &[AnkiField], back: &[AnkiField]) -> String {
+    let mut deck = String::new();
+    for word in words {
+        deck.push_str(&anki_side(word, front));
+        deck.push('\t');
+        deck.push_str(&anki_side(word, back));
+        deck.push('\n');
     }
+    deck
+}
 
-    fn get_phon_rom(self) -> Result<(String, String)> {
-        match self {
-            WordOutput::PhonRom(p, r) => Ok((p, r)),
-            _ => bail!("Expected both phonetic and romanized versions of the word"),
-        }
-    }
+#[derive(Args, Debug)]
+struct ExportAllArgs {
+    #[command(flatten)]
+    output: ExportOutputArgs,
 }
 
-impl<'a> LexurgyCmd<'a> {
-    fn evolve(to: &'a LangEntry, inp: LexurgyInput, out: LexurgyOutput) -> LexurgyCmd<'a> {
-        LexurgyCmd {
-            target_lang: to,
-            output: None,
-            evolve: true,
-            input_format: inp,
-            output_format: out,
-            stop_before: None,
-            start_at: None,
-        }
-    }
+#[derive(Args, Debug)]
+struct ExportOutputArgs {
+    /// Write the export to a file instead of STDOUT
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+    /// Pretty-print the JSON (the default)
+    #[arg(long, conflicts_with = "compact")]
+    pretty: bool,
+    /// Emit the JSON on a single line
+    #[arg(long)]
+    compact: bool,
+}
 
-    fn deromanize(lang: &'a LangEntry) -> LexurgyCmd<'a> {
-        LexurgyCmd {
-            target_lang: lang,
-            output: None,
-            evolve: false,
-            input_format: LexurgyInput::Romanized,
-            output_format: LexurgyOutput::Phonetic,
-            stop_before: None,
-            start_at: None,
-        }
+impl ExportOutputArgs {
+    fn write(&self, value: &impl Serialize) -> Result<()> {
+        let mut json = if self.compact {
+            serde_json::to_string(value)?
+        } else {
+            serde_json::to_string_pretty(value)?
+        };
+        json.push('\n');
+        self.write_text(&json)
     }
 
-    fn run<'b>(
-        self,
-        cfg: &Config,
-        words: impl Iterator<Item = &'b str>,
-    ) -> Result<Vec<WordOutput>> {
-        use std::fs::File;
-        use std::io::{BufRead, BufReader, BufWriter, Write};
-        use std::process::*;
-
-        let input_name = format!(
-            "{}_{}",
-            &self.target_lang.id,
-            if self.input_format == LexurgyInput::Romanized {
-                "rom"
-            } else {
-                "phon"
-            }
-        );
-        let mut wli = PathBuf::new();
-        wli.push(cfg.word_list_folder());
-        wli.push(&input_name);
-        wli.set_extension("wli");
-
-        {
-            let f = File::create(&wli)?;
-            let mut buf = BufWriter::new(f);
-
-            for word in words {
-                buf.write_all(word.as_bytes())?;
-                buf.write_all(b"\n")?;
+    fn write_text(&self, text: &str) -> Result<()> {
+        match &self.out {
+            Some(path) => {
+                fs::write(path, text).with_context(|| format!("Writing export file: {:?}", path))?
             }
+            None => print!("{}", text),
         }
+        Ok(())
+    }
+}
 
-        let mut lsc = PathBuf::new();
-        lsc.push(cfg.rule_list_folder());
-        lsc.push(&self.target_lang.rule);
-        lsc.set_extension("lsc");
-
-        let mut out = PathBuf::new();
-        out.push(cfg.word_list_folder());
-        out.push("out");
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Show a few example words of every language
+    #[arg(short, long)]
+    sample: bool,
+    /// Only show languages with at least N words
+    #[arg(long)]
+    min_count: Option<u32>,
+    /// Only show languages with at most N words
+    #[arg(long)]
+    max_count: Option<u32>,
+}
 
-        let mut lexurgy = Command::new(if cfg!(windows) {
-            "lexurgy.bat"
-        } else {
-            "lexurgy"
-        });
-        lexurgy
-            .arg("sc")
-            .arg(&lsc)
-            .arg(&wli)
-            .arg("--out-dir")
-            .arg(&out);
+#[derive(Args, Debug)]
+struct CompletionsArgs {
+    shell: clap_complete::Shell,
+}
 
-        let derom = self.input_format == LexurgyInput::Romanized;
+#[derive(Args, Debug)]
+struct PronArgs {
+    /// ID of the language
+    language: String,
+    /// The romanized words to deromanize, which don't have to be in the lexicon
+    #[arg(required = true)]
+    words: Vec<String>,
+}
 
-        let mut start = None;
+#[derive(Args, Debug)]
+struct ScArgs {
+    /// ID of the language whose rules to run
+    language: String,
+    /// The words to run through the rules
+    words: Vec<String>,
+    /// What form the words are given in
+    #[arg(long)]
+    input: LexurgyInput,
+    /// What form to show the results in
+    #[arg(long)]
+    output: LexurgyOutput,
+    /// Use this rule file instead of the language's own, relative to `rule_list_folder` unless
+    /// it's absolute
+    #[arg(long)]
+    rule: Option<String>,
+}
 
-        if derom && !self.evolve {
-            if self.stop_before.is_some() {
-                bail!("Can't specify `--stop-before` together with a pure deromanize command");
-            }
-            lexurgy.arg("-b").arg("init");
-        } else if !derom && self.evolve {
-            start = Some("init");
-        } else if !derom && !self.evolve {
-            bail!(
-                "Internal error! It doesn't make sense to neither want to deromanize nor to evovle"
-            );
-        }
+#[derive(Args, Debug)]
+struct PhonologyArgs {
+    /// ID of the language
+    language: String,
+}
 
-        if let Some(a) = self.start_at.as_ref().map(|x| &x[..]).or(start) {
-            lexurgy.arg("-a").arg(a);
-        }
+#[derive(Args, Debug)]
+struct FreqArgs {
+    /// ID of the language
+    language: String,
+    /// Count grapheme clusters, keeping letters with combining diacritics together, instead of
+    /// single characters
+    #[arg(long)]
+    graphemes: bool,
+}
 
-        if let Some(b) = self.stop_before.as_ref() {
-            lexurgy.arg("-b").arg(b);
-        }
+#[derive(Args, Debug)]
+struct FamilyDotArgs {
+    /// Write the graph to a file instead of STDOUT
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
 
-        match self.output_format {
-            LexurgyOutput::Phonetic => {
-                lexurgy.arg("-p");
-            }
-            LexurgyOutput::Both => {
-                lexurgy.arg("-m");
-            }
-            LexurgyOutput::Romanized => {}
-        }
+#[derive(Args, Debug)]
+struct LangInfoArgs {
+    /// ID of the language
+    language: String,
+    /// Show every ancestor instead of just the parent
+    #[arg(short, long)]
+    parents: bool,
+    /// Show every descendant instead of just the children
+    #[arg(short, long)]
+    children: bool,
+}
 
-        if cfg.debug_mode {
-            println!("Running lexurgy with: {:?}", lexurgy.get_args());
-        }
-        let cmd = format!("{:?}", &lexurgy);
-        let output = lexurgy.output()?;
-        if !output.status.success() {
-            bail!(
-                "{:?} failed.\nSTDOUT:\n{}\nSTDERR:\n{}\n",
-                cmd,
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            );
-        }
+#[derive(Args, Debug)]
+struct SearchArgs {
+    /// The text to search for. If omitted, every word passing the other filters matches
+    query: Option<String>,
+    /// Only search in the given language
+    #[arg(short, long = "lang")]
+    language: Option<String>,
+    /// Only match words of the given part-of-speech
+    #[arg(short, long)]
+    kind: Option<String>,
+    /// Only match words tagged with the given flag, can be given multiple times
+    #[arg(short, long)]
+    tag: Vec<String>,
+    /// Search in the meaning instead of the romanization
+    #[arg(short = 'M', long)]
+    in_meaning: bool,
+    /// Treat the query as a regular expression
+    #[arg(short, long, conflicts_with_all = ["exact", "prefix"])]
+    regex: bool,
+    /// Only match words equal to the query
+    #[arg(short, long, conflicts_with = "prefix")]
+    exact: bool,
+    /// Only match words starting with the query
+    #[arg(short, long)]
+    prefix: bool,
+    /// Also show this many of the words coming before and after each match alphabetically
+    #[arg(short = 'C', long, default_value_t = 0)]
+    context: usize,
+}
 
-        let mut ev_wli = PathBuf::new();
-        ev_wli.push(cfg.word_list_folder());
-        ev_wli.push("out");
-        ev_wli.push(format!("{}_ev", &input_name));
-        if self.output_format == LexurgyOutput::Both {
-            ev_wli.set_extension("wlm");
-        } else {
-            ev_wli.set_extension("wli");
-        }
-        let f = File::open(ev_wli)?;
-        let reader = BufReader::new(f);
-        Ok(reader
-            .lines()
-            .map(|l| match self.output_format {
-                LexurgyOutput::Romanized => Ok(WordOutput::Rom(l?)),
-                LexurgyOutput::Phonetic => Ok(WordOutput::Phon(l?)),
-                LexurgyOutput::Both => {
-                    let l = l?;
-                    let mut parts = l.split("=>").map(|p| p.trim()).collect::<Vec<_>>();
-                    if parts.len() < 3 {
-                        bail!("Expected at least three steps, got: {}", parts.len());
-                    }
-                    let rom = parts
-                        .pop()
-                        .ok_or(anyhow!("Expected romanized part of output"))?;
-                    let phon = parts
-                        .pop()
-                        .ok_or(anyhow!("Expecetd phonetic part of ouput"))?;
-                    Ok(WordOutput::PhonRom(phon.to_string(), rom.to_string()))
-                }
-            })
-            .collect::<Result<_, _>>()?)
-    }
+#[derive(Args, Debug)]
+struct AddArgs {
+    /// The language to add the word to
+    language: String,
+    /// The romanized spelling of the word. If omitted, tab separated `word meaning kind` lines
+    /// are read from STDIN instead
+    word: Option<String>,
+    /// The meaning of the word
+    meaning: Option<String>,
+    /// The part-of-speech the word belongs to (one of the `kinds` in `Wdb.toml`)
+    kind: Option<String>,
+    /// Where the word comes from (unspecified means it's a neoglism)
+    #[arg(short, long)]
+    origin: Option<String>,
+    /// Attach a note to the word (arbitrary text)
+    #[arg(short, long)]
+    note: Option<String>,
+    /// The phonetic transcription of the word
+    #[arg(short, long)]
+    ipa: Option<String>,
+    /// Disable auto-deromanization
+    #[arg(short = 'D', long)]
+    disable_autorom: bool,
+    /// Allow definining the word to be a homophone of any existing words
+    #[arg(short = 'H', long)]
+    homophone: bool,
+    /// Allow a kind that isn't one of the `kinds` in `Wdb.toml`
+    #[arg(long)]
+    force_kind: bool,
+    /// Store the romanization exactly as given, without trimming its whitespace
+    #[arg(long)]
+    no_normalize: bool,
 }
 
-impl Wdb {
-    fn new(cfg: Config) -> Result<Wdb> {
-        let db_file = if cfg.debug_mode {
-            cfg.root.join(&cfg.settings.db_dev_file)
-        } else {
-            cfg.root.join(&cfg.settings.db_file)
-        };
-        Ok(Wdb {
-            db: Connection::open(db_file)?,
-            cfg,
-        })
-    }
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// The language to add the words to
+    language: String,
+    /// The CSV file to read the words from
+    file: PathBuf,
+    /// The first line holds the column names and is skipped
+    #[arg(long)]
+    header: bool,
+    /// The character separating the fields
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+    /// The column holding the romanizations, counting from 1
+    #[arg(long, default_value_t = 1)]
+    rom_col: usize,
+    /// The column holding the meanings
+    #[arg(long, default_value_t = 2)]
+    meaning_col: usize,
+    /// The column holding the parts-of-speech
+    #[arg(long, default_value_t = 3)]
+    kind_col: usize,
+    /// The column holding already known IPA, used instead of deromanizing. Words with an empty
+    /// field there are still deromanized
+    #[arg(long, visible_alias = "ipa-from-field")]
+    ipa_col: Option<usize>,
+    /// Disable auto-deromanization
+    #[arg(short = 'D', long)]
+    disable_autorom: bool,
+    /// Allow definining the words to be homophones of any existing words
+    #[arg(short = 'H', long)]
+    homophone: bool,
+    /// Allow kinds that aren't one of the `kinds` in `Wdb.toml`
+    #[arg(long)]
+    force_kind: bool,
+    /// Add the words to a language that already has words without asking
+    #[arg(long)]
+    append: bool,
+    /// Don't ask for the language id before importing into a language that already has words
+    #[arg(short, long)]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct EditArgs {
+    /// The language to add the word to
+    language: String,
+    /// The romanized spelling of the word
+    word: String,
+    /// The meaning of the word
+    #[arg(short, long)]
+    meaning: Option<String>,
+    /// The part-of-speech the word belongs to (one of the `kinds` in `Wdb.toml`)
+    #[arg(short, long)]
+    kind: Option<String>,
+    /// Where the word comes from (unspecified means it's a neoglism)
+    #[arg(short, long, conflicts_with_all = ["origin_clear", "origin_note"])]
+    origin: Option<String>,
+    /// Unset where the word comes from, making it a neologism
+    #[arg(long, conflicts_with = "origin_note")]
+    origin_clear: bool,
+    /// Comment on the origin, keeping the `(inherited ...)` part recorded by `inherit`
+    #[arg(long)]
+    origin_note: Option<String>,
+    /// Allow `--origin` and `--origin-clear` to replace an `(inherited ...)` origin
+    #[arg(long)]
+    force: bool,
+    /// Attach a note to the word (arbitrary text)
+    #[arg(short, long)]
+    note: Option<String>,
+    /// The phonetic transcription of the word
+    #[arg(short, long)]
+    ipa: Option<String>,
+    /// Change the romanized spelling of the word, deromanizing it again unless `--ipa` is given
+    #[arg(long)]
+    rename: Option<String>,
+    /// Allow the new spelling to make the word a homophone of existing words
+    #[arg(short = 'H', long, requires = "rename")]
+    homophone: bool,
+    /// Disable auto-deromanization
+    #[arg(short = 'D', long)]
+    disable_autorom: bool,
+    /// Allow a kind that isn't one of the `kinds` in `Wdb.toml`
+    #[arg(long)]
+    force_kind: bool,
+    /// Store the romanization exactly as given, without trimming its whitespace
+    #[arg(long)]
+    no_normalize: bool,
+}
+
+#[derive(Args, Debug)]
+struct RebuildArgs {
+    /// The language whose inherited words to rebuild
+    language: String,
+}
+
+#[derive(Args, Debug)]
+struct InheritArgs {
+    /// The language to add the word to
+    language: String,
+    /// The romanized spelling of the word
+    word: String,
+    /// The meaning of the word
+    #[arg(short, long)]
+    meaning: Option<String>,
+    /// The part-of-speech the word belongs to (one of the `kinds` in `Wdb.toml`)
+    #[arg(short, long)]
+    kind: Option<String>,
+    /// Attach a note to the word (arbitrary text)
+    #[arg(short, long)]
+    note: Option<String>,
+    /// Allow a kind that isn't one of the `kinds` in `Wdb.toml`
+    #[arg(long)]
+    force_kind: bool,
+    /// A TOML file with a table per source romanization, overriding its `meaning`, `kind` or
+    /// `note` in the inherited word
+    #[arg(long)]
+    map: Option<PathBuf>,
+    /// Inherit from an earlier ancestor than the parent, evolving the word through every
+    /// language in between
+    #[arg(short, long)]
+    from: Option<String>,
+    /// Show the word in every language in between
+    #[arg(short = 'i', long, requires = "from")]
+    show_intermediate: bool,
+    /// Skip the words tagged `noinherit`, like suppletive or borrowed forms that shouldn't
+    /// undergo the regular sound changes
+    #[arg(long)]
+    regular_only: bool,
+}
+
+/// The tag keeping a word from being inherited with `inherit --regular-only`
+const NOINHERIT_TAG: &str = "noinherit";
+
+/// The `(inherited <lang> <romanization> <id>)` part `inherit` starts the origin of a word with
+fn structured_origin(origin: &str) -> Option<&str> {
+    if !origin.starts_with("(inherited ") {
+        return None;
+    }
+    origin.find(')').map(|end| &origin[..=end])
+}
+
+/// The language and id of the word an `(inherited ...)` origin says the word came from
+fn inherited_source(origin: &str) -> Option<(&str, u32)> {
+    let structured = structured_origin(origin)?;
+    let mut parts = structured
+        .strip_prefix("(inherited ")?
+        .strip_suffix(')')?
+        .split_whitespace();
+    let lang = parts.next()?;
+    let id = parts.next_back()?.parse().ok()?;
+    Some((lang, id))
+}
+
+/// What an `inherit --map` file changes about an inherited word
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct InheritOverride {
+    meaning: Option<String>,
+    kind: Option<String>,
+    note: Option<String>,
+}
+
+fn load_inherit_map(path: &Path) -> Result<collections::BTreeMap<String, InheritOverride>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Reading inherit map: {}", path.display()))?;
+    let map: collections::BTreeMap<String, InheritOverride> = toml::from_str(&text)
+        .with_context(|| format!("Parsing inherit map: {}", path.display()))?;
+    Ok(map
+        .into_iter()
+        .map(|(rom, o)| (normalize_text(&rom), o))
+        .collect())
+}
+
+#[derive(Args, Debug)]
+struct SwapArgs {
+    /// The language of the homophones
+    language: String,
+    /// The romanized spelling of the homophones
+    word: String,
+    /// The index of the first homophone, like in `word#index`
+    a: usize,
+    /// The index of the second homophone
+    b: usize,
+}
+
+#[derive(Args, Debug)]
+struct DelArgs {
+    /// The language to add the word to
+    language: String,
+    /// The romanized spelling of the word
+    word: String,
+}
+
+#[derive(Args, Debug)]
+struct DedupeArgs {
+    /// Only look for duplicates in the given language
+    #[arg(short, long = "lang")]
+    language: Option<String>,
+    /// List the duplicates without deleting them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct UseArgs {
+    /// ID of the language to work on, shows the current working language if omitted. Once set,
+    /// commands whose language argument is left out use it. A first argument that's a language's
+    /// id or the start of one is still taken as the language, so words like that need the
+    /// language given explicitly
+    language: Option<String>,
+    /// Forget the working language
+    #[arg(long, conflicts_with = "language")]
+    clear: bool,
+}
+
+#[derive(Args, Debug)]
+struct PruneArgs {
+    /// List the languages without deleting them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct WatchArgs {
+    /// ID of the language to keep dumped
+    language: String,
+}
+
+#[derive(Args, Debug)]
+struct HistoryArgs {
+    /// The language of the word
+    language: String,
+    /// The romanized spelling of the word
+    word: String,
+}
+
+#[derive(Args, Debug)]
+struct EvolveArgs {
+    /// The source language
+    from_lang: String,
+    /// The target language
+    to_lang: String,
+    /// The sentence to evolve
+    sentence: Vec<String>,
+    #[arg(short = 'b')]
+    stop_before: Option<String>,
+    #[arg(short = 'a')]
+    start_at: Option<String>,
+    #[arg(short = 'p')]
+    show_phonetic: bool,
+    /// Show intermediate versions
+    #[arg(short = 'i', long)]
+    show_intermediate: bool,
+    /// Warn about words of the sentence missing from the source language's lexicon
+    #[arg(short = 'c', long)]
+    check_lexicon: bool,
+    /// Offer to add the evolved words missing from the target language to its lexicon
+    #[arg(long)]
+    apply_to_lexicon: bool,
+    /// Show the rules that changed each word, step by step
+    #[arg(long)]
+    trace_rules: bool,
+    /// Show each word next to what it evolved into, instead of a line per language
+    #[arg(long)]
+    compare: bool,
+    /// The sentence is written in IPA rather than romanized, only for proto-languages
+    #[arg(long, conflicts_with = "check_lexicon")]
+    from_ipa: bool,
+    /// Ask before evolving more than this many words
+    #[arg(long, default_value_t = EVOLVE_TOKEN_LIMIT)]
+    limit: usize,
+    /// Evolve the words without asking, however many there are
+    #[arg(short, long)]
+    yes: bool,
+    /// Use this rule file for the target language instead of its own, relative to
+    /// `rule_list_folder` unless it's absolute
+    #[arg(long)]
+    rule: Option<String>,
+    /// With `json`, every word's form after each language is printed as JSON. Intermediate forms
+    /// are phonetic, the final ones come with their phonetic forms too with `--show-phonetic`
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = EvolveFormat::Text,
+        conflicts_with_all = ["compare", "trace_rules", "apply_to_lexicon"]
+    )]
+    format: EvolveFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EvolveFormat {
+    Text,
+    Json,
+}
+
+/// The words after running the sound changes of one language
+struct EvolutionStage<'l> {
+    lang: &'l LangEntry,
+    words: Vec<String>,
+    /// The rules applied to each word, laid out by `format_trace`, with `--trace-rules`
+    trace: Vec<String>,
+}
+
+struct Evolution<'l> {
+    stages: Vec<EvolutionStage<'l>>,
+    /// The romanized and phonetic forms of the final words, when adding them to the lexicon or
+    /// showing both as JSON
+    evolved: Vec<(String, String)>,
+}
+
+/// A word of the sentence and what it evolved into in each language, for `evolve --json`
+#[derive(Serialize, Debug, PartialEq)]
+struct EvolvedToken {
+    input: String,
+    stages: Vec<TokenStage>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct TokenStage {
+    stage_id: String,
+    form: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phonetic: Option<String>,
+}
+
+/// Follows each of the `tokens` through the stages of the evolution. When the final stage produced
+/// both forms, its `form` is the romanized one
+fn evolved_tokens(tokens: &[String], evolution: &Evolution) -> Vec<EvolvedToken> {
+    let last = evolution.stages.len().saturating_sub(1);
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, input)| EvolvedToken {
+            input: input.clone(),
+            stages: evolution
+                .stages
+                .iter()
+                .enumerate()
+                .filter_map(|(s, stage)| {
+                    let (form, phonetic) = match evolution.evolved.get(i).filter(|_| s == last) {
+                        Some((rom, phon)) => (rom.clone(), Some(phon.clone())),
+                        None => (stage.words.get(i)?.clone(), None),
+                    };
+                    Some(TokenStage {
+                        stage_id: stage.lang.id.clone(),
+                        form,
+                        phonetic,
+                    })
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// How many words `evolve` runs the sound changes on before asking, see `check_token_limit`
+const EVOLVE_TOKEN_LIMIT: usize = 1000;
+
+/// Asks before running the sound changes on more than `limit` words, unless `yes` is set
+fn check_token_limit(
+    count: usize,
+    limit: usize,
+    yes: bool,
+    prompt: &mut dyn io::BufRead,
+) -> Result<()> {
+    if count <= limit || yes {
+        return Ok(());
+    }
+    eprintln!(
+        "WARNING: Evolving {} words, more than the limit of {}",
+        count, limit
+    );
+    if !confirm("Evolve them anyway?", prompt)? {
+        bail!("Evolving cancelled, use --limit or --yes to evolve that many words");
+    }
+    Ok(())
+}
+
+#[derive(Args, Debug)]
+struct PhonArgs {
+    #[arg(short, long = "lang")]
+    language: Option<String>,
+    /// Regenerate ALL phonetic annotation, not just the missing ones
+    #[arg(short, long)]
+    force: bool,
+    /// Run up to N Lexurgy processes at once (defaults to the number of CPUs)
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Only (re)generate the annotations of these words, whether they're missing or not
+    #[arg(short, long, num_args = 1.., requires = "language")]
+    only: Vec<String>,
+    /// Use this rule file instead of the language's own, relative to `rule_list_folder` unless
+    /// it's absolute
+    #[arg(long, requires = "language")]
+    rule: Option<String>,
+    /// Only show the first N results and ask before writing any of them
+    #[arg(short, long)]
+    sample: Option<usize>,
+    /// Write the results after showing the sample without asking
+    #[arg(short, long, requires = "sample")]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct NeighbourArgs {
+    /// The language to look in
+    language: String,
+    /// The romanization to start from, which doesn't have to be a word itself
+    word: String,
+}
+
+#[derive(Args, Debug)]
+struct SuggestArgs {
+    /// The language to look in
+    language: String,
+    /// The (misspelled) romanization to look for
+    word: String,
+    /// How many edits away a word may be (defaults to one per three letters)
+    #[arg(short = 'd', long)]
+    max_distance: Option<usize>,
+    /// Show at most N words
+    #[arg(short, long, default_value_t = DEFAULT_SUGGESTIONS)]
+    limit: usize,
+}
+
+#[derive(Args, Debug)]
+struct RandomArgs {
+    /// The language to generate words for
+    language: String,
+    /// How many words to generate
+    #[arg(short, long, default_value_t = 10)]
+    count: usize,
+    /// Seed the generator to get the same words as a previous run
+    #[arg(short, long)]
+    seed: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+struct IpaArgs {
+    #[command(subcommand)]
+    command: IpaCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum IpaCommand {
+    /// Add or replace a pronunciation variant of a word
+    Add {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+        /// What the variant is called, like `colloquial`
+        label: String,
+        /// The phonetic transcription of the variant
+        ipa: String,
+    },
+    /// List the pronunciations of a word
+    List {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+    },
+    /// Remove a pronunciation variant of a word
+    Rm {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+        /// The label of the variant to remove
+        label: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct ExampleArgs {
+    #[command(subcommand)]
+    command: ExampleCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ExampleCommand {
+    /// Add an example sentence using a word
+    Add {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+        /// The sentence, in the language
+        text: String,
+        /// Its translation or interlinear gloss
+        gloss: String,
+    },
+    /// List the example sentences of a word
+    List {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+    },
+    /// Remove an example sentence of a word
+    Rm {
+        /// The language of the word
+        language: String,
+        /// The romanized spelling of the word
+        word: String,
+        /// The number of the example, as shown by `example list`
+        number: usize,
+    },
+}
+
+/*
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OriginKind {
+    Inherited {
+        from_lang: String,
+        romanization: String,
+        word_id: u32,
+    },
+    Compound {
+
+    }
+}
+*/
+
+#[derive(Debug, Serialize)]
+struct WordEntry {
+    id: u32,
+    lang: String,
+    romanization: String,
+    ipa: Option<String>,
+    meaning: String,
+    kind: String,
+    origin: Option<String>,
+    flags: Flags,
+    note: Option<String>,
+    /// Pronunciations besides the standard one in `ipa`, only filled in where needed
+    ipa_variants: Vec<IpaVariant>,
+    /// Sentences showing how the word is used
+    examples: Vec<Example>,
+}
+
+/// An alternative pronunciation of a word, like a dialectal or colloquial one
+#[derive(Debug, Serialize, PartialEq)]
+struct IpaVariant {
+    label: String,
+    ipa: String,
+}
+
+/// A sentence using a word, along with its translation
+#[derive(Debug, Serialize, PartialEq)]
+struct Example {
+    #[serde(skip)]
+    id: u32,
+    conlang_text: String,
+    gloss: String,
+}
+
+impl WordEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<WordEntry> {
+        Ok(WordEntry {
+            id: row.get(0)?,
+            lang: row.get(1)?,
+            romanization: row.get(2)?,
+            ipa: row.get(3)?,
+            meaning: row.get(4)?,
+            kind: row.get(5)?,
+            origin: row.get(6)?,
+            flags: row.get(7)?,
+            note: row.get(8)?,
+            ipa_variants: vec![],
+            examples: vec![],
+        })
+    }
+}
+
+/// A single field change from the changelog
+#[derive(Debug, PartialEq)]
+struct ChangeEntry {
+    field: String,
+    old: Option<String>,
+    new: Option<String>,
+    timestamp: String,
+}
+
+impl ChangeEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<ChangeEntry> {
+        Ok(ChangeEntry {
+            field: row.get("field")?,
+            old: row.get("old")?,
+            new: row.get("new")?,
+            timestamp: row.get("timestamp")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LangEntry {
+    id: String,
+    name: String,
+    origin: Option<String>,
+    rule: String,
+}
+
+/// A language together with all of its words, as written by `export`
+#[derive(Debug, Serialize)]
+struct LangExport {
+    lang: LangEntry,
+    words: Vec<WordEntry>,
+}
+
+impl std::fmt::Display for LangEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.id)
+    }
+}
+
+impl LangEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<LangEntry> {
+        Ok(LangEntry {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            origin: row.get(2)?,
+            rule: row.get(3)?,
+        })
+    }
+
+    /// The language with its rule file swapped for `rule` when given, which is resolved like the
+    /// `rule` stored in the database. The database itself isn't touched
+    fn with_rule(mut self, rule: Option<&str>) -> LangEntry {
+        if let Some(rule) = rule {
+            self.rule = rule.to_string();
+        }
+        self
+    }
+}
+
+/// Maps each language id to the languages directly descending from it. Languages without a (known)
+/// origin are listed under `None`.
+type FamilyGraph<'l> = collections::BTreeMap<Option<&'l str>, Vec<&'l LangEntry>>;
+
+fn family_graph(langs: &[LangEntry]) -> FamilyGraph<'_> {
+    let mut graph = FamilyGraph::new();
+    for lang in langs {
+        let parent = lang
+            .origin
+            .as_deref()
+            .filter(|o| langs.iter().any(|l| &l.id == o));
+        graph.entry(parent).or_default().push(lang);
+    }
+    for children in graph.values_mut() {
+        children.sort_by(|a, b| a.id.cmp(&b.id));
+    }
+    graph
+}
+
+/// Quotes `s` as a DOT identifier, keeping line breaks as `\n`
+fn dot_id(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Makes sure following the `origin` of every language eventually reaches a proto-language
+fn validate_family(langs: &[LangEntry]) -> Result<()> {
+    for lang in langs {
+        let mut chain = vec![&lang.id[..]];
+        let mut l = lang;
+        while let Some(ref l_id) = l.origin {
+            if chain.contains(&&l_id[..]) {
+                chain.push(l_id);
+                bail!(
+                    "Language {} has a cyclic origin chain: {}",
+                    lang,
+                    chain.join(" -> ")
+                );
+            }
+            chain.push(l_id);
+            match langs.iter().find(|l| &l.id == l_id) {
+                Some(parent) => l = parent,
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn homophone_error(lang: &LangEntry, rom: &str, homophones: &[WordEntry]) -> String {
+    use std::fmt::Write;
+    let mut err_msg = format!(
+        "Error adding word `{}` to language {}. \n\nThe following homophone(s) exist already:\n",
+        rom, lang
+    );
+    for line in format_table(&homophone_rows(homophones), " ") {
+        let _ = writeln!(&mut err_msg, " - {}", line);
+    }
+    err_msg
+}
+
+/// A table row per homophone, numbered by the index `#N` picks it with
+fn homophone_rows(homophones: &[WordEntry]) -> Vec<Vec<String>> {
+    homophones
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            vec![
+                format!("{}:", i),
+                format!("{}:", h.romanization),
+                format!("{}, {}", h.meaning, h.kind),
+            ]
+        })
+        .collect()
+}
+
+/// Meaning and kind of words added without knowing them yet, like by `evolve --apply-to-lexicon`
+const PLACEHOLDER_MEANING: &str = "?";
+const PLACEHOLDER_KIND: &str = "?";
+
+struct PhonemeCounts {
+    /// Each symbol with how often it appears, most frequent first
+    counts: Vec<(String, usize)>,
+    /// The romanizations of the words without IPA
+    missing: Vec<String>,
+}
+
+/// A word to be added, as read from a line of input
+#[derive(Debug, PartialEq)]
+struct NewWord {
+    romanization: String,
+    meaning: String,
+    kind: String,
+    /// Given along with the word, instead of deromanizing it
+    ipa: Option<String>,
+}
+
+/// Reads `romanization<TAB>meaning<TAB>kind` lines, skipping blank ones
+fn parse_word_lines(input: impl io::BufRead) -> Result<Vec<NewWord>> {
+    let mut words = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let [rom, meaning, kind] = line.split('\t').collect::<Vec<_>>()[..] else {
+            bail!(
+                "Line {}: expected `romanization<TAB>meaning<TAB>kind`, got: {:?}",
+                i + 1,
+                line
+            );
+        };
+        words.push(NewWord {
+            romanization: normalize_text(rom),
+            meaning: meaning.trim().to_string(),
+            kind: normalize_text(kind),
+            ipa: None,
+        });
+    }
+    Ok(words)
+}
+
+/// Splits a line of CSV into its fields. Fields can be quoted, with `""` standing for a quote
+fn parse_csv_line(line: &str, delimiter: char) -> Result<Vec<String>> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => fields.push(mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        bail!("Unterminated quote");
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Reads the words out of CSV rows, taking the columns `args` points at
+fn parse_csv_words(input: impl io::BufRead, args: &ImportArgs) -> Result<Vec<NewWord>> {
+    let mut words = vec![];
+    for (i, line) in input.lines().enumerate().skip(args.header as usize) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = parse_csv_line(&line, args.delimiter)
+            .with_context(|| format!("Line {}: {:?}", i + 1, line))?;
+        let column = |col: usize| match fields.get(col.wrapping_sub(1)) {
+            Some(f) => Ok(f.trim()),
+            None => Err(anyhow!(
+                "Line {}: there's no column {}, got: {:?}",
+                i + 1,
+                col,
+                line
+            )),
+        };
+        let ipa = args
+            .ipa_col
+            .map(column)
+            .transpose()?
+            .filter(|ipa| !ipa.is_empty());
+        words.push(NewWord {
+            romanization: normalize_text(column(args.rom_col)?),
+            meaning: column(args.meaning_col)?.to_string(),
+            kind: normalize_text(column(args.kind_col)?),
+            ipa: ipa.map(normalize_text),
+        });
+    }
+    Ok(words)
+}
+
+/// Asks a yes or no question, anything but `y`/`yes` counts as no
+fn confirm(question: &str, input: &mut dyn io::BufRead) -> Result<bool> {
+    use std::io::Write;
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks the user to confirm by typing `expected`, surrounding whitespace aside
+fn confirm_typed(question: &str, expected: &str, input: &mut dyn io::BufRead) -> Result<bool> {
+    use std::io::Write;
+    print!("{} Type `{}` to continue: ", question, expected);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim() == expected)
+}
+
+/// The number of user-perceived characters in `s`, counting a letter with combining diacritics as
+/// one
+fn grapheme_len(s: &str) -> usize {
+    s.graphemes(true).count()
+}
+
+/// The number of terminal columns `s` takes up, wide characters like CJK taking two and combining
+/// diacritics none
+fn display_width(s: &str) -> usize {
+    unicode_width::UnicodeWidthStr::width(s)
+}
+
+/// Pads `s` with spaces to take up `width` terminal columns
+fn pad_display(s: &str, width: usize) -> String {
+    let pad = width.saturating_sub(display_width(s));
+    format!("{}{}", s, " ".repeat(pad))
+}
+
+/// Lines with the cells of each row padded to line up in columns, joined by `separator`. Rows can
+/// have fewer cells than others, and trailing whitespace is trimmed
+fn format_table(rows: &[Vec<String>], separator: &str) -> Vec<String> {
+    let mut widths = vec![];
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if i == widths.len() {
+                widths.push(0);
+            }
+            widths[i] = widths[i].max(display_width(cell));
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, &w)| pad_display(cell, w))
+                .collect::<Vec<_>>()
+                .join(separator)
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Lines with each of the `counts` and its share of the total, aligned into columns
+fn frequency_table(counts: &[(String, usize)]) -> Vec<String> {
+    let total = counts.iter().map(|(_, n)| n).sum::<usize>();
+    let rows = counts
+        .iter()
+        .map(|(s, n)| {
+            vec![
+                s.clone(),
+                format!("{:>5}", n),
+                format!("{:5.1}%", 100.0 * *n as f64 / total as f64),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(&rows, "  ")
+}
+
+/// Levenshtein distance between `a` and `b`, treating every grapheme as a single character
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.graphemes(true).collect::<Vec<_>>();
+    let b = b.graphemes(true).collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ga) in a.iter().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, gb) in b.iter().enumerate() {
+            let subst = prev[j] + usize::from(ga != gb);
+            cur[j + 1] = subst.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// How many suggestions are shown when a word isn't found
+const DEFAULT_SUGGESTIONS: usize = 5;
+
+/// Allows about one typo every three graphemes
+fn default_max_distance(query: &str) -> usize {
+    (grapheme_len(query) / 3).max(1)
+}
+
+fn format_suggestions(words: &[(WordEntry, usize)]) -> String {
+    let rows = words
+        .iter()
+        .map(|(w, _)| vec![w.romanization.clone(), format!("{}, {}", w.meaning, w.kind)])
+        .collect::<Vec<_>>();
+    format_table(&rows, "  ")
+        .into_iter()
+        .map(|line| format!(" - {}\n", line))
+        .collect()
+}
+
+fn normalize_text(s: &str) -> String {
+    s.trim().to_string()
+}
+
+/// `normalize_text`, unless `--no-normalize` asked for the text to be kept as is
+fn normalize_unless(s: &str, verbatim: bool) -> String {
+    if verbatim {
+        s.to_string()
+    } else {
+        normalize_text(s)
+    }
+}
+
+/// Strips the `/.../` or `[...]` some stored IPA is written with, so it can be processed the same
+/// as bare IPA
+fn normalize_ipa(ipa: &str) -> &str {
+    let ipa = ipa.trim();
+    ipa.strip_prefix('/')
+        .and_then(|i| i.strip_suffix('/'))
+        .or_else(|| ipa.strip_prefix('[').and_then(|i| i.strip_suffix(']')))
+        .map_or(ipa, str::trim)
+}
+
+/// Escapes the wildcards of a `LIKE` pattern with backslashes, for `ESCAPE '\'`
+fn like_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Stored IPA as printed between the brackets of command output, `?` if there's none
+fn display_ipa(ipa: Option<&str>) -> &str {
+    ipa.map_or("?", normalize_ipa)
+}
+
+struct Wdb {
+    db: Connection,
+    cfg: Config,
+    /// The languages looked up by `get_lang` so far, see `forget_langs`
+    lang_cache: cell::RefCell<collections::HashMap<String, LangEntry>>,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum LexurgyInput {
+    Romanized,
+    Phonetic,
+}
+
+#[derive(ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum LexurgyOutput {
+    Romanized,
+    Phonetic,
+    /// The phonetic and the romanized form
+    Both,
+}
+
+struct LexurgyCmd<'a> {
+    target_lang: &'a LangEntry,
+    evolve: bool,
+    input_format: LexurgyInput,
+    output_format: LexurgyOutput,
+    stop_before: Option<String>,
+    start_at: Option<String>,
+}
+
+enum WordOutput {
+    Phon(String),
+    Rom(String),
+    PhonRom(String, String),
+}
+
+impl WordOutput {
+    fn get_value(self) -> Result<String> {
+        match self {
+            WordOutput::Phon(x) => Ok(x),
+            WordOutput::Rom(x) => Ok(x),
+            WordOutput::PhonRom(_, _) => {
+                bail!("Expected a single value, but this word output has two")
+            }
+        }
+    }
+
+    fn get_phon(self) -> Result<String> {
+        match self {
+            WordOutput::Phon(x) => Ok(x),
+            WordOutput::Rom(_) => bail!("Expected phonetic word, got romanized word"),
+            WordOutput::PhonRom(x, _) => Ok(x),
+        }
+    }
+
+    fn get_phon_rom(self) -> Result<(String, String)> {
+        match self {
+            WordOutput::PhonRom(p, r) => Ok((p, r)),
+            _ => bail!("Expected both phonetic and romanized versions of the word"),
+        }
+    }
+}
+
+impl<'a> LexurgyCmd<'a> {
+    fn evolve(to: &'a LangEntry, inp: LexurgyInput, out: LexurgyOutput) -> LexurgyCmd<'a> {
+        LexurgyCmd {
+            target_lang: to,
+            evolve: true,
+            input_format: inp,
+            output_format: out,
+            stop_before: None,
+            start_at: None,
+        }
+    }
+
+    fn deromanize(lang: &'a LangEntry) -> LexurgyCmd<'a> {
+        LexurgyCmd {
+            target_lang: lang,
+            evolve: false,
+            input_format: LexurgyInput::Romanized,
+            output_format: LexurgyOutput::Phonetic,
+            stop_before: None,
+            start_at: None,
+        }
+    }
+
+    fn run<'b>(
+        self,
+        cfg: &Config,
+        words: impl Iterator<Item = &'b str>,
+    ) -> Result<Vec<WordOutput>> {
+        let words = words.collect::<Vec<_>>();
+        let engine: &dyn SoundChanger = if cfg.native {
+            &NativeEngine
+        } else {
+            &LexurgyProcess
+        };
+        engine.run(&self, cfg, &words)
+    }
+
+    /// Like `run`, but also returns the names of the rules that changed each word, in order
+    fn run_traced<'b>(
+        self,
+        cfg: &Config,
+        words: impl Iterator<Item = &'b str>,
+    ) -> Result<(Vec<WordOutput>, Vec<Vec<String>>)> {
+        let words = words.collect::<Vec<_>>();
+        let engine: &dyn SoundChanger = if cfg.native {
+            &NativeEngine
+        } else {
+            &LexurgyProcess
+        };
+        engine.run_traced(&self, cfg, &words)
+    }
+
+    /// The rule to start at and the one to stop before, the way Lexurgy's `-a` and `-b` take them
+    fn rule_range(&self) -> Result<(Option<&str>, Option<&str>)> {
+        let derom = self.input_format == LexurgyInput::Romanized;
+        let mut start_at = self.start_at.as_deref();
+        let mut stop_before = self.stop_before.as_deref();
+        if derom && !self.evolve {
+            if stop_before.is_some() {
+                bail!("Can't specify `--stop-before` together with a pure deromanize command");
+            }
+            stop_before = Some("init");
+        } else if !derom && self.evolve {
+            start_at = start_at.or(Some("init"));
+        } else if !derom && !self.evolve {
+            bail!(
+                "Internal error! It doesn't make sense to neither want to deromanize nor to evovle"
+            );
+        }
+        Ok((start_at, stop_before))
+    }
+}
+
+/// Carries out a `LexurgyCmd`
+trait SoundChanger {
+    fn run(&self, cmd: &LexurgyCmd, cfg: &Config, words: &[&str]) -> Result<Vec<WordOutput>>;
+
+    fn run_traced(
+        &self,
+        _cmd: &LexurgyCmd,
+        _cfg: &Config,
+        _words: &[&str],
+    ) -> Result<(Vec<WordOutput>, Vec<Vec<String>>)> {
+        bail!("This sound change engine can't trace which rules apply")
+    }
+}
+
+/// Runs the external `lexurgy` program, talking to it through word list files
+struct LexurgyProcess;
+
+impl SoundChanger for LexurgyProcess {
+    fn run(&self, cmd: &LexurgyCmd, cfg: &Config, words: &[&str]) -> Result<Vec<WordOutput>> {
+        Ok(self.invoke(cmd, cfg, words, false)?.0)
+    }
+
+    fn run_traced(
+        &self,
+        cmd: &LexurgyCmd,
+        cfg: &Config,
+        words: &[&str],
+    ) -> Result<(Vec<WordOutput>, Vec<Vec<String>>)> {
+        let (out, stdout) = self.invoke(cmd, cfg, words, true)?;
+        Ok((out, parse_lexurgy_trace(&stdout, words)))
+    }
+}
+
+impl LexurgyProcess {
+    /// Runs Lexurgy on `words`, returning the evolved words and what it printed. With `trace` it's
+    /// asked to trace every word
+    fn invoke(
+        &self,
+        cmd: &LexurgyCmd,
+        cfg: &Config,
+        words: &[&str],
+        trace: bool,
+    ) -> Result<(Vec<WordOutput>, String)> {
+        use std::fs::File;
+        use std::io::{BufReader, BufWriter, Write};
+        use std::process::*;
+
+        let input_name = format!(
+            "{}_{}",
+            &cmd.target_lang.id,
+            if cmd.input_format == LexurgyInput::Romanized {
+                "rom"
+            } else {
+                "phon"
+            }
+        );
+        let wli = cfg.word_list_file(&input_name);
+
+        {
+            let f = File::create(&wli)?;
+            let mut buf = BufWriter::new(f);
+
+            for word in words {
+                buf.write_all(word.as_bytes())?;
+                buf.write_all(b"\n")?;
+            }
+        }
+
+        let lsc = cfg.rule_file(cmd.target_lang);
+
+        let out = cfg.lexurgy_output_folder();
+
+        let mut lexurgy = Command::new(if cfg!(windows) {
+            "lexurgy.bat"
+        } else {
+            "lexurgy"
+        });
+        lexurgy
+            .arg("sc")
+            .arg(&lsc)
+            .arg(&wli)
+            .arg("--out-dir")
+            .arg(&out);
+
+        let (start_at, stop_before) = cmd.rule_range()?;
+        if let Some(a) = start_at {
+            lexurgy.arg("-a").arg(a);
+        }
+        if let Some(b) = stop_before {
+            lexurgy.arg("-b").arg(b);
+        }
+
+        match cmd.output_format {
+            LexurgyOutput::Phonetic => {
+                lexurgy.arg("-p");
+            }
+            LexurgyOutput::Both => {
+                lexurgy.arg("-m");
+            }
+            LexurgyOutput::Romanized => {}
+        }
+
+        if trace {
+            for word in words {
+                lexurgy.arg("-t").arg(word);
+            }
+        }
+
+        if cfg.debug_mode {
+            eprintln!("Running lexurgy with: {:?}", lexurgy.get_args());
+        }
+        let invocation = format!("{:?}", &lexurgy);
+        let output = lexurgy.output()?;
+        if !output.status.success() {
+            cleanup_temps(cfg, &[&wli]);
+            bail!(
+                "{:?} failed.\nSTDOUT:\n{}\nSTDERR:\n{}\n",
+                invocation,
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let ev_wli = cfg.lexurgy_output_file(&input_name, cmd.output_format);
+        let f = match open_lexurgy_output(&ev_wli) {
+            Ok(f) => f,
+            Err(err) => {
+                cleanup_temps(cfg, &[&wli]);
+                return Err(err);
+            }
+        };
+        let words = parse_lexurgy_output(BufReader::new(f), cmd.output_format, words.len());
+        cleanup_temps(cfg, &[&wli, &ev_wli]);
+        Ok((words?, String::from_utf8_lossy(&output.stdout).into_owned()))
+    }
+}
+
+/// Picks the rules that changed each of `words` out of Lexurgy's trace output. Versions differ in
+/// how they print it, so both `Applied rule: a -> b` and `rule: a => b` lines are understood,
+/// with or without a `Tracing word` header before each word's lines. Without headers, a change
+/// belongs to the word currently spelled like its left side
+fn parse_lexurgy_trace(output: &str, words: &[&str]) -> Vec<Vec<String>> {
+    let mut forms = words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+    let mut rules = vec![vec![]; words.len()];
+    let mut headed = vec![false; words.len()];
+    let mut current = None;
+    for line in output.lines().map(str::trim) {
+        if let Some(word) = line.strip_prefix("Tracing ") {
+            let word = word.trim().trim_end_matches(':').trim();
+            current = (0..words.len()).find(|&i| !headed[i] && words[i] == word);
+            if let Some(i) = current {
+                headed[i] = true;
+            }
+            continue;
+        }
+        let line = line.strip_prefix("Applied ").unwrap_or(line);
+        let Some((rule, change)) = line.split_once(':') else {
+            continue;
+        };
+        let Some((before, after)) = change.split_once("->").or_else(|| change.split_once("=>"))
+        else {
+            continue;
+        };
+        // Older versions name the word too: `rule to word: a -> b`
+        let Some(rule) = rule.split_whitespace().next() else {
+            continue;
+        };
+        let (before, after) = (before.trim(), after.trim());
+        let Some(i) = current.or_else(|| forms.iter().position(|f| f == before)) else {
+            continue;
+        };
+        rules[i].push(rule.to_string());
+        forms[i] = after.to_string();
+    }
+    rules
+}
+
+/// Lays the rules out in a column under each of the `tokens` they applied to
+fn format_trace(tokens: &[String], trace: &[Vec<String>]) -> Vec<String> {
+    let rows = trace.iter().map(|rs| rs.len().max(1)).max().unwrap_or(0);
+    let table = (0..=rows)
+        .map(|row| {
+            tokens
+                .iter()
+                .zip(trace)
+                .map(|(t, rs)| match row {
+                    0 => t.clone(),
+                    1 if rs.is_empty() => "-".to_string(),
+                    _ => rs.get(row - 1).cloned().unwrap_or_default(),
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>();
+    format_table(&table, "  ")
+}
+
+/// Lays the words of each language out in a column headed by the language, each word on the same
+/// line as what it evolved into. Words are paired up by their position, so a stage with more or
+/// fewer words than the previous one leaves `-` in the shorter columns
+fn format_comparison(stages: &[(&str, Vec<String>)]) -> Vec<String> {
+    let rows = stages.iter().map(|(_, ws)| ws.len()).max().unwrap_or(0);
+    let mut table = vec![stages
+        .iter()
+        .map(|(l, _)| l.to_string())
+        .collect::<Vec<_>>()];
+    for row in 0..rows {
+        table.push(
+            stages
+                .iter()
+                .map(|(_, ws)| ws.get(row).map_or("-", |w| w.as_str()).to_string())
+                .collect(),
+        );
+    }
+    format_table(&table, " → ")
+}
+
+/// Reads the word list Lexurgy wrote for `expected` input words. Lexurgy writes a line per word,
+/// an empty one for a word its rules deleted entirely, so only the blank lines past the last word
+/// are dropped. Trailing whitespace is trimmed from every line
+fn parse_lexurgy_output(
+    reader: impl io::BufRead,
+    format: LexurgyOutput,
+    expected: usize,
+) -> Result<Vec<WordOutput>> {
+    let mut lines = reader
+        .lines()
+        .map(|l| l.map(|l| l.trim_end().to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    while lines.len() > expected && lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines
+        .into_iter()
+        .map(|l| match format {
+            LexurgyOutput::Romanized => Ok(WordOutput::Rom(l)),
+            LexurgyOutput::Phonetic => Ok(WordOutput::Phon(l)),
+            LexurgyOutput::Both => {
+                let mut parts = l.split("=>").map(|p| p.trim()).collect::<Vec<_>>();
+                if parts.len() < 3 {
+                    bail!("Expected at least three steps, got: {}", parts.len());
+                }
+                let rom = parts
+                    .pop()
+                    .ok_or(anyhow!("Expected romanized part of output"))?;
+                let phon = parts
+                    .pop()
+                    .ok_or(anyhow!("Expecetd phonetic part of ouput"))?;
+                Ok(WordOutput::PhonRom(phon.to_string(), rom.to_string()))
+            }
+        })
+        .collect()
+}
+
+/// Runs the rules with the `sc` crate's engine instead of Lexurgy. It doesn't support everything
+/// Lexurgy does yet: absent features, diacritics, negation, intersections, syllable
+/// captures, rule references, filters, most rule modifiers and intermediate romanizers. The parts
+/// of the rules using them are left out with a warning
+struct NativeEngine;
+
+impl SoundChanger for NativeEngine {
+    fn run(&self, cmd: &LexurgyCmd, cfg: &Config, words: &[&str]) -> Result<Vec<WordOutput>> {
+        let lsc = cfg.rule_file(cmd.target_lang);
+        let lexurgy = sc::sc::Lexurgy::from_ast(sc::include::load_lsc(&lsc)?);
+        if !lexurgy.incomplete_rules().is_empty() {
+            eprintln!(
+                "WARNING: The native engine doesn't support everything {} uses, these rules won't fully apply: {}",
+                lsc.display(),
+                lexurgy.incomplete_rules().join(", ")
+            );
+        }
+
+        let (start_at, stop_before) = cmd.rule_range()?;
+        let mut ws = words.iter().map(|w| lexurgy.word(w)).collect::<Vec<_>>();
+        // Like Lexurgy, starting at a later rule means the input is already phonetic
+        if cmd.input_format == LexurgyInput::Romanized && start_at.is_none() {
+            lexurgy.deromanize(&mut ws)?;
+        }
+        lexurgy.apply_range(&mut ws, start_at, stop_before)?;
+        let phon = ws.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        if cmd.output_format == LexurgyOutput::Phonetic {
+            return Ok(phon.into_iter().map(WordOutput::Phon).collect());
+        }
+
+        lexurgy.romanize(&mut ws)?;
+        let rom = ws.iter().map(|w| w.to_string());
+        Ok(match cmd.output_format {
+            LexurgyOutput::Both => phon
+                .into_iter()
+                .zip(rom)
+                .map(|(p, r)| WordOutput::PhonRom(p, r))
+                .collect(),
+            _ => rom.map(WordOutput::Rom).collect(),
+        })
+    }
+}
+
+/// Opens the word list Lexurgy wrote. If it isn't there, the files that are get listed, since
+/// that usually means Lexurgy names its output differently than we expect
+fn open_lexurgy_output(path: &Path) -> Result<fs::File> {
+    if path.exists() {
+        return fs::File::open(path)
+            .with_context(|| format!("Opening Lexurgy output: {}", path.display()));
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut found = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| Some(e.ok()?.file_name().to_string_lossy().into_owned()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    found.sort();
+    bail!(
+        "Lexurgy didn't write the expected output file `{}` to {}, found {}. Is this a version of Lexurgy that names its output differently?",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        dir.display(),
+        if found.is_empty() {
+            "no files".to_string()
+        } else {
+            format!("only: {}", found.join(", "))
+        }
+    )
+}
+
+/// Removes the word list files used to talk to Lexurgy, unless we've been asked to keep them
+/// around for inspection, in which case their paths are printed instead.
+fn cleanup_temps(cfg: &Config, paths: &[&Path]) {
+    for path in paths {
+        if cfg.keep_temps {
+            eprintln!("Kept Lexurgy word list: {}", path.display());
+        } else if let Err(err) = fs::remove_file(path) {
+            eprintln!(
+                "WARNING: Failed to remove temporary file {}: {}",
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Compiles one of the templates from `Wdb.toml`, named after its setting so errors can point at it
+fn add_template<'t>(
+    tt: &mut TinyTemplate<'t>,
+    setting: &'static str,
+    template: &'t str,
+) -> Result<()> {
+    tt.add_template(setting, template)
+        .with_context(|| format!("Invalid `{}` in `Wdb.toml`", setting))
+}
+
+fn render_template(tt: &TinyTemplate, setting: &str, context: &impl Serialize) -> Result<String> {
+    tt.render(setting, context)
+        .with_context(|| format!("Failed to render `{}` from `Wdb.toml`", setting))
+}
+
+/// Finds the language with the id `partial`, or else the only one whose id starts with it
+fn lang_index(langs: &[LangEntry], partial: &str) -> Result<usize> {
+    if let Some(i) = langs.iter().position(|l| l.id == partial) {
+        return Ok(i);
+    }
+    let candidates = (0..langs.len())
+        .filter(|&i| langs[i].id.starts_with(partial))
+        .collect::<Vec<_>>();
+    match candidates[..] {
+        [] => bail!("No such language: `{}`", partial),
+        [i] => Ok(i),
+        _ => {
+            let mut ids = candidates
+                .iter()
+                .map(|&i| &langs[i].id[..])
+                .collect::<Vec<_>>();
+            ids.sort();
+            bail!(
+                "`{}` is ambiguous, it could be any of: {}",
+                partial,
+                ids.join(", ")
+            )
+        }
+    }
+}
+
+/// The phonetic forms of words in a language they're evolved through, see `Wdb::evolve_along`
+type Stage<'a> = (&'a LangEntry, Vec<String>);
+
+/// The languages whose rules evolve words from `from` into `to`, the first one being a child of
+/// `from` and the last one `to` itself
+fn evolution_steps<'a>(
+    langs: &'a [LangEntry],
+    from: &LangEntry,
+    to: &'a LangEntry,
+) -> Result<Vec<&'a LangEntry>> {
+    let mut steps = vec![];
+    let mut l = to;
+    while let Some(ref l_id) = l.origin {
+        steps.push(l);
+
+        l = langs.iter().find(|l| &l.id == l_id).ok_or(anyhow!(
+            "Internal Error! Language {}({}) has an invalid origin language: `{}`",
+            l.name,
+            l.id,
+            l_id
+        ))?;
+
+        if l_id == &from.id {
+            break;
+        }
+    }
+
+    if l.id != from.id {
+        bail!(
+            "{}({}) is not a descendent of {}({})!",
+            to.name,
+            to.id,
+            from.name,
+            from.id
+        );
+    }
+    steps.reverse();
+    Ok(steps)
+}
+
+/// How often `watch` checks the database for changes
+const WATCH_INTERVAL: time::Duration = time::Duration::from_millis(500);
+/// How long `watch` waits for another process to finish writing to the database
+const WATCH_BUSY_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+
+/// How long a statement waits for another connection to release the database before failing
+const BUSY_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+/// How many more times `begin` tries to take the database after timing out
+const BUSY_RETRIES: u32 = 5;
+/// How long `begin` waits before its first retry, doubling every time after
+const BUSY_BACKOFF: time::Duration = time::Duration::from_millis(50);
+
+/// Whether the SQLite error is the database being locked by another connection
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(f, _)
+            if matches!(f.code, rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Whether any error in the chain is the database being locked, see `is_busy`
+fn is_locked(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|e| e.downcast_ref::<rusqlite::Error>().is_some_and(is_busy))
+}
+
+/// Starts a transaction holding the write lock from the start, so the writes in it can't fail
+/// for another connection using the database. Taking the lock is retried with backoff while
+/// another connection (like Obsidian's sync or another `wdb`) holds it
+fn begin(db: &mut Connection) -> Result<rusqlite::Transaction<'_>> {
+    use rusqlite::{Transaction, TransactionBehavior};
+    // Taking `db` mutably still keeps transactions from nesting, like `Connection::transaction`
+    let db: &Connection = db;
+    let mut backoff = BUSY_BACKOFF;
+    for _ in 0..BUSY_RETRIES {
+        match Transaction::new_unchecked(db, TransactionBehavior::Immediate) {
+            Err(err) if is_busy(&err) => {
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            tr => return Ok(tr?),
+        }
+    }
+    Transaction::new_unchecked(db, TransactionBehavior::Immediate)
+        .context("The database is in use by another program, try again later")
+}
+
+fn add_sql_functions(db: &Connection) -> rusqlite::Result<()> {
+    use rusqlite::functions::FunctionFlags;
+    use std::sync::Arc;
+
+    // SQLite rewrites `X REGEXP Y` into `regexp(Y, X)`
+    db.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let re: Arc<regex::Regex> = ctx.get_or_create_aux(0, |v| {
+                Ok::<_, Box<dyn error::Error + Send + Sync>>(regex::Regex::new(v.as_str()?)?)
+            })?;
+            let text = ctx
+                .get_raw(1)
+                .as_str()
+                .map_err(|e| rusqlite::Error::UserFunctionError(e.into()))?;
+            Ok(re.is_match(text))
+        },
+    )?;
+    // `has_tag(flags, tag)`, splitting the flags like `Flags`
+    db.create_scalar_function(
+        "has_tag",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let flags = ctx.get::<Flags>(0)?;
+            let tag = ctx.get::<String>(1)?;
+            Ok(flags.contains(&tag))
+        },
+    )
+}
+
+/// Creates the tables added since the original schema, if they're missing
+fn migrate(db: &Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ipa_variants (
+            word_id INTEGER NOT NULL REFERENCES words(id),
+            label TEXT NOT NULL,
+            ipa TEXT NOT NULL,
+            PRIMARY KEY (word_id, label)
+        );
+        CREATE TABLE IF NOT EXISTS examples (
+            id INTEGER PRIMARY KEY,
+            word_id INTEGER NOT NULL REFERENCES words(id),
+            conlang_text TEXT NOT NULL,
+            gloss TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS changelog (
+            id INTEGER PRIMARY KEY,
+            word_id INTEGER NOT NULL,
+            field TEXT NOT NULL,
+            old TEXT,
+            new TEXT,
+            timestamp TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        -- The newest word of the last `export --append` of each language. Word ids are never
+        -- reused, so the words added since are exactly the ones with a larger id
+        CREATE TABLE IF NOT EXISTS exports (
+            lang TEXT PRIMARY KEY,
+            last_word_id INTEGER NOT NULL,
+            last_export TEXT NOT NULL DEFAULT (datetime('now'))
+        );",
+    )
+}
+
+/// Records a change to one of a word's fields in the changelog
+fn log_change(
+    db: &Connection,
+    word_id: u32,
+    field: &str,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT INTO changelog (word_id, field, old, new) VALUES (?, ?, ?, ?)",
+        params![word_id, field, old, new],
+    )?;
+    Ok(())
+}
+
+/// Records a new word in the changelog, as its spelling going from unset to `rom`
+fn log_added(db: &Connection, word_id: u32, rom: &str) -> rusqlite::Result<()> {
+    log_change(db, word_id, "romanization", None, Some(rom))
+}
+
+/// Records a deleted word in the changelog, as its spelling `rom` becoming unset
+fn log_deleted(db: &Connection, word_id: u32, rom: &str) -> rusqlite::Result<()> {
+    log_change(db, word_id, "romanization", Some(rom), None)
+}
+
+impl Wdb {
+    fn new(cfg: Config) -> Result<Wdb> {
+        let db = Connection::open(cfg.db_path())?;
+        db.busy_timeout(BUSY_TIMEOUT)?;
+        Wdb::from_connection(db, cfg)
+    }
+
+    fn from_connection(db: Connection, cfg: Config) -> Result<Wdb> {
+        add_sql_functions(&db)?;
+        migrate(&db)?;
+        Ok(Wdb {
+            db,
+            cfg,
+            lang_cache: Default::default(),
+        })
+    }
 
     fn get_lang(&self, lang: &str) -> Result<LangEntry> {
+        if let Some(entry) = self.lang_cache.borrow().get(lang) {
+            return Ok(entry.clone());
+        }
+        let entry = self.db.query_row(
+            "SELECT * FROM langs WHERE id = ?",
+            [lang],
+            LangEntry::from_row,
+        )?;
+        self.lang_cache
+            .borrow_mut()
+            .insert(lang.to_string(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Drops the languages cached by `get_lang`, for when the database might have been changed
+    /// by another process
+    fn forget_langs(&self) {
+        self.lang_cache.borrow_mut().clear();
+    }
+
+    fn get_langs(&self) -> Result<Vec<LangEntry>> {
+        let mut stmt = self.db.prepare("SELECT * FROM langs")?;
+        let entries = stmt.query_map([], LangEntry::from_row)?;
+        Ok(entries.collect::<Result<_, _>>()?)
+    }
+
+    /// Looks up a language given on the command line, which can also be a unique prefix of its id
+    fn resolve_lang(&self, partial: &str) -> Result<LangEntry> {
+        let mut langs = self.get_langs()?;
+        let i = lang_index(&langs, partial)?;
+        Ok(langs.swap_remove(i))
+    }
+
+    fn dump(&mut self, args: DumpArgs) -> Result<()> {
+        let dict = self.render_dictionary(&args)?;
+        match &dict.file {
+            Some(file) => fs::write(
+                file,
+                self.cfg.settings.dictionary_encoding.encode(&dict.text),
+            )
+            .with_context(|| format!("Writing dictionary file: {:?}", file))?,
+            None => print!("{}", dict.text),
+        }
+        Ok(())
+    }
+
+    fn render_dictionary(&self, args: &DumpArgs) -> Result<RenderedDictionary> {
+        let lang = self.resolve_lang(&args.language)?;
+        if args.reverse {
+            return self.render_reverse_dictionary(lang, args);
+        }
+        if args.strip_flags && args.group_by == Some(GroupBy::Tag) {
+            bail!("Can't group by tag when stripping the flags the tags are in");
+        }
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization")?;
+        let mut entries = stmt
+            .query_map([&lang.id], WordEntry::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        args.filter(&mut entries);
+        self.load_word_details(&mut entries)?;
+        let settings = &self.cfg.settings;
+        for entry in &mut entries {
+            let delimiter = settings.ipa_delimiter;
+            entry.ipa = entry.ipa.as_deref().map(|ipa| delimiter.render(ipa));
+            for variant in &mut entry.ipa_variants {
+                variant.ipa = delimiter.render(&variant.ipa);
+            }
+        }
+        let mut tt = TinyTemplate::new();
+        if !args.dictionary_only {
+            add_template(
+                &mut tt,
+                "dictionary_file_template",
+                &settings.dictionary_file_template,
+            )?;
+        }
+        add_template(
+            &mut tt,
+            "dictionary_template",
+            &settings.dictionary_template,
+        )?;
+
+        #[derive(Serialize)]
+        struct DictionaryTemplateContext<'a> {
+            lang: LangEntry,
+            words: &'a [WordEntry],
+            /// The gloss abbreviations the words use
+            glosses: Vec<Gloss>,
+            /// The words split up by `--group-by`, empty without it
+            groups: Vec<WordGroup<'a>>,
+        }
+
+        let context = DictionaryTemplateContext {
+            lang,
+            glosses: gloss_legend(&settings.glosses, &entries),
+            words: &entries,
+            groups: match args.group_by {
+                Some(GroupBy::Kind) => group_by_kind(&entries, &settings.kind_order),
+                Some(GroupBy::Letter) => group_by_letter(&entries),
+                Some(GroupBy::Tag) => group_by_tag(&entries),
+                None => vec![],
+            },
+        };
+
+        let file = if args.dictionary_only {
+            None
+        } else {
+            let name = render_template(&tt, "dictionary_file_template", &context)?;
+            Some(self.cfg.root.join(name))
+        };
+        let text = render_template(&tt, "dictionary_template", &context)?;
+        Ok(RenderedDictionary { file, text })
+    }
+
+    fn ipa_variants(&self, word_id: u32) -> Result<Vec<IpaVariant>> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT label, ipa FROM ipa_variants WHERE word_id = ? ORDER BY label")?;
+        let variants = stmt
+            .query_map([word_id], |row| {
+                Ok(IpaVariant {
+                    label: row.get(0)?,
+                    ipa: normalize_ipa(&row.get::<_, String>(1)?).to_string(),
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(variants)
+    }
+
+    fn examples(&self, word_id: u32) -> Result<Vec<Example>> {
+        let mut stmt = self.db.prepare(
+            "SELECT id, conlang_text, gloss FROM examples WHERE word_id = ? ORDER BY id",
+        )?;
+        let examples = stmt
+            .query_map([word_id], |row| {
+                Ok(Example {
+                    id: row.get(0)?,
+                    conlang_text: row.get(1)?,
+                    gloss: row.get(2)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(examples)
+    }
+
+    /// Fills in the pronunciation variants and examples of `words`
+    fn load_word_details(&self, words: &mut [WordEntry]) -> Result<()> {
+        for word in words {
+            word.ipa_variants = self.ipa_variants(word.id)?;
+            word.examples = self.examples(word.id)?;
+        }
+        Ok(())
+    }
+
+    fn ipa(&mut self, args: IpaArgs) -> Result<()> {
+        let (language, word) = match &args.command {
+            IpaCommand::Add { language, word, .. }
+            | IpaCommand::List { language, word }
+            | IpaCommand::Rm { language, word, .. } => (language, word),
+        };
+        let lang = self.resolve_lang(language)?;
+        let Some(entry) = self.try_get_unique_word(&lang, &normalize_text(word))? else {
+            return Ok(());
+        };
+        match args.command {
+            IpaCommand::Add { label, ipa, .. } => {
+                let label = normalize_text(&label);
+                self.db.execute(
+                    "INSERT OR REPLACE INTO ipa_variants (word_id, label, ipa) VALUES (?, ?, ?)",
+                    params![entry.id, &label, normalize_text(&ipa)],
+                )?;
+                println!("Added {} pronunciation of `{}`", label, entry.romanization);
+            }
+            IpaCommand::List { .. } => {
+                println!(
+                    "{}: standard [{}]",
+                    entry.romanization,
+                    display_ipa(entry.ipa.as_deref())
+                );
+                for v in self.ipa_variants(entry.id)? {
+                    println!("{}: {} [{}]", entry.romanization, v.label, v.ipa);
+                }
+                return Ok(());
+            }
+            IpaCommand::Rm { label, .. } => {
+                let removed = self.db.execute(
+                    "DELETE FROM ipa_variants WHERE word_id = ? AND label = ?",
+                    params![entry.id, normalize_text(&label)],
+                )?;
+                if removed == 0 {
+                    bail!(
+                        "`{}` has no pronunciation labelled `{}`",
+                        entry.romanization,
+                        label
+                    );
+                }
+                println!(
+                    "Removed {} pronunciation of `{}`",
+                    label, entry.romanization
+                );
+            }
+        }
+        if self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(lang.id))?;
+        }
+        Ok(())
+    }
+
+    fn example(&mut self, args: ExampleArgs) -> Result<()> {
+        let (language, word) = match &args.command {
+            ExampleCommand::Add { language, word, .. }
+            | ExampleCommand::List { language, word }
+            | ExampleCommand::Rm { language, word, .. } => (language, word),
+        };
+        let lang = self.resolve_lang(language)?;
+        let Some(entry) = self.try_get_unique_word(&lang, &normalize_text(word))? else {
+            return Ok(());
+        };
+        match args.command {
+            ExampleCommand::Add { text, gloss, .. } => {
+                self.db.execute(
+                    "INSERT INTO examples (word_id, conlang_text, gloss) VALUES (?, ?, ?)",
+                    params![entry.id, normalize_text(&text), gloss.trim()],
+                )?;
+                println!("Added example of `{}`", entry.romanization);
+            }
+            ExampleCommand::List { .. } => {
+                for (i, e) in self.examples(entry.id)?.iter().enumerate() {
+                    println!("{}. {}\n   '{}'", i + 1, e.conlang_text, e.gloss);
+                }
+                return Ok(());
+            }
+            ExampleCommand::Rm { number, .. } => {
+                let examples = self.examples(entry.id)?;
+                let Some(example) = number.checked_sub(1).and_then(|i| examples.get(i)) else {
+                    bail!(
+                        "`{}` has {} examples, there's no example {}",
+                        entry.romanization,
+                        examples.len(),
+                        number
+                    );
+                };
+                self.db
+                    .execute("DELETE FROM examples WHERE id = ?", [example.id])?;
+                println!("Removed example {} of `{}`", number, entry.romanization);
+            }
+        }
+        if self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(lang.id))?;
+        }
+        Ok(())
+    }
+
+    fn render_reverse_dictionary(
+        &self,
+        lang: LangEntry,
+        args: &DumpArgs,
+    ) -> Result<RenderedDictionary> {
+        let settings = &self.cfg.settings;
+        let (file_template, Some(template)) = (
+            &settings.reverse_dictionary_file_template,
+            &settings.reverse_dictionary_template,
+        ) else {
+            bail!("Dumping a reverse dictionary requires `reverse_dictionary_template` to be set in `Wdb.toml`");
+        };
+        let file_template = match file_template {
+            Some(t) => Some(t),
+            None if args.dictionary_only => None,
+            None => bail!("Dumping a reverse dictionary to a file requires `reverse_dictionary_file_template` to be set in `Wdb.toml`"),
+        };
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY meaning, romanization, id")?;
+        let mut entries = stmt
+            .query_map([&lang.id], WordEntry::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        args.filter(&mut entries);
+        self.load_word_details(&mut entries)?;
+        let mut tt = TinyTemplate::new();
+        if !args.dictionary_only {
+            if let Some(file_template) = file_template {
+                add_template(&mut tt, "reverse_dictionary_file_template", file_template)?;
+            }
+        }
+        add_template(&mut tt, "reverse_dictionary_template", template)?;
+
+        #[derive(Serialize)]
+        struct MeaningGroup {
+            meaning: String,
+            words: Vec<WordEntry>,
+        }
+
+        #[derive(Serialize)]
+        struct ReverseDictionaryTemplateContext {
+            lang: LangEntry,
+            meanings: Vec<MeaningGroup>,
+            /// The gloss abbreviations the words use
+            glosses: Vec<Gloss>,
+        }
+
+        let glosses = gloss_legend(&self.cfg.settings.glosses, &entries);
+        let mut meanings: Vec<MeaningGroup> = vec![];
+        for word in entries {
+            match meanings.last_mut() {
+                Some(group) if group.meaning == word.meaning => group.words.push(word),
+                _ => meanings.push(MeaningGroup {
+                    meaning: word.meaning.clone(),
+                    words: vec![word],
+                }),
+            }
+        }
+
+        let context = ReverseDictionaryTemplateContext {
+            lang,
+            meanings,
+            glosses,
+        };
+
+        let file = if args.dictionary_only {
+            None
+        } else {
+            let name = render_template(&tt, "reverse_dictionary_file_template", &context)?;
+            Some(self.cfg.root.join(name))
+        };
+        let text = render_template(&tt, "reverse_dictionary_template", &context)?;
+        Ok(RenderedDictionary { file, text })
+    }
+
+    fn lang_export(&self, lang: LangEntry) -> Result<LangExport> {
+        // Order by id as well so homophones always come out in the same order
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization, id")?;
+        let mut words = stmt
+            .query_map([&lang.id], WordEntry::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        self.load_word_details(&mut words)?;
+        Ok(LangExport { lang, words })
+    }
+
+    fn export(&mut self, args: ExportArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let export = self.lang_export(lang)?;
+        if args.append && args.format != ExportFormat::Csv {
+            bail!("Only CSV exports can be appended to");
+        }
+        match args.format {
+            ExportFormat::Json => args.output.write(&export),
+            ExportFormat::Anki => {
+                args.output
+                    .write_text(&anki_deck(&export.words, &args.front, &args.back))
+            }
+            ExportFormat::Csv => match &args.output.out {
+                Some(path) if args.append => {
+                    let lang = export.lang.id.clone();
+                    let count = self.append_csv_export(path, export, args.full)?;
+                    println!("Exported {} word(s) of {} to {:?}", count, lang, path);
+                    Ok(())
+                }
+                _ => {
+                    args.output
+                        .write_text(&format!("{}\n{}", CSV_HEADER, csv_rows(&export.words)))
+                }
+            },
+            ExportFormat::Latex => {
+                let preamble = match &args.preamble {
+                    Some(path) => fs::read_to_string(path)
+                        .with_context(|| format!("Reading preamble file: {:?}", path))?,
+                    None => String::new(),
+                };
+                args.output
+                    .write_text(&latex_document(&export, &args.documentclass, &preamble))
+            }
+        }
+    }
+
+    /// Appends the words added since the last time to the CSV file, returning how many. With
+    /// `full`, or without a file to append to yet, the file is written from scratch with every
+    /// word instead. The marker of the last export only moves once the file is written
+    fn append_csv_export(&self, path: &Path, mut export: LangExport, full: bool) -> Result<usize> {
+        use std::io::Write;
+        let last = if full || !path.exists() {
+            None
+        } else {
+            self.db
+                .query_row(
+                    "SELECT last_word_id FROM exports WHERE lang = ?",
+                    [&export.lang.id],
+                    |row| row.get::<_, u32>(0),
+                )
+                .optional()?
+        };
+        let newest = export.words.iter().map(|w| w.id).max();
+        let mut text = String::new();
+        match last {
+            Some(last) => export.words.retain(|w| w.id > last),
+            None => text.push_str(&format!("{}\n", CSV_HEADER)),
+        }
+        text.push_str(&csv_rows(&export.words));
+
+        fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(last.is_some())
+            .truncate(last.is_none())
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|| format!("Writing export file: {:?}", path))?;
+        if let Some(newest) = newest {
+            self.db.execute(
+                "INSERT OR REPLACE INTO exports (lang, last_word_id) VALUES (?, ?)",
+                params![&export.lang.id, newest],
+            )?;
+        }
+        Ok(export.words.len())
+    }
+
+    fn export_all(&mut self, args: ExportAllArgs) -> Result<()> {
+        let mut langs = collections::BTreeMap::new();
+        for lang in self.get_langs()? {
+            langs.insert(lang.id.clone(), self.lang_export(lang)?);
+        }
+        args.output.write(&langs)
+    }
+
+    fn word_count(&self, lang: &str) -> Result<u32> {
         Ok(self.db.query_row(
-            "SELECT * FROM langs WHERE id = ?",
+            "SELECT COUNT(id) FROM words WHERE lang = ?",
             [lang],
-            LangEntry::from_row,
+            |row| row.get(0),
         )?)
     }
 
-    fn get_langs(&self) -> Result<Vec<LangEntry>> {
-        let mut stmt = self.db.prepare("SELECT * FROM langs")?;
-        let entries = stmt.query_map([], LangEntry::from_row)?;
-        Ok(entries.collect::<Result<_, _>>()?)
+    fn list(&mut self, args: ListArgs) -> Result<()> {
+        const SAMPLE_SIZE: usize = 3;
+        println!("Languages:");
+        let langs = self.counted_langs(&args)?;
+        let rows = langs
+            .iter()
+            .map(|(entry, words)| {
+                vec![
+                    format!("{}:", entry.id),
+                    entry.name.clone(),
+                    format!("({} words)", words),
+                ]
+            })
+            .collect::<Vec<_>>();
+        for ((entry, _), line) in langs.iter().zip(format_table(&rows, " ")) {
+            println!(" {}", line);
+            if args.sample {
+                for word in self.word_sample(&entry.id, SAMPLE_SIZE)? {
+                    println!(
+                        "    {} [{}] – {}",
+                        word.romanization,
+                        display_ipa(word.ipa.as_deref()),
+                        word.meaning
+                    );
+                }
+            }
+        }
+        for problem in self.validate_rules()? {
+            println!("WARNING: {}", problem);
+        }
+        Ok(())
+    }
+
+    /// The first `size` words of the language in dictionary order, for `list --sample`
+    fn word_sample(&self, lang: &str, size: usize) -> Result<Vec<WordEntry>> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization, id LIMIT ?")?;
+        let sample = stmt
+            .query_map(params![lang, size], WordEntry::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(sample)
+    }
+
+    /// The languages `list` shows along with their word counts, within its count limits
+    fn counted_langs(&self, args: &ListArgs) -> Result<Vec<(LangEntry, u32)>> {
+        let mut langs = vec![];
+        for entry in self.get_langs()? {
+            let words = self.word_count(&entry.id)?;
+            if args.min_count.is_some_and(|min| words < min)
+                || args.max_count.is_some_and(|max| words > max)
+            {
+                continue;
+            }
+            langs.push((entry, words));
+        }
+        Ok(langs)
+    }
+
+    /// Describes every language whose rule file is missing or can't be parsed
+    fn validate_rules(&self) -> Result<Vec<String>> {
+        let mut problems = vec![];
+        for lang in self.get_langs()? {
+            let lsc = self.cfg.rule_file(&lang);
+            if !lsc.exists() {
+                problems.push(format!("{}: Missing rule file {}", lang.id, lsc.display()));
+            } else if let Err(err) = sc::include::load_lsc(&lsc) {
+                problems.push(format!("{}: {}", lang.id, err));
+            }
+        }
+        Ok(problems)
+    }
+
+    fn check(&self) -> Result<()> {
+        let problems = self.validate_rules()?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            bail!("{} rule file(s) have problems", problems.len());
+        }
+        println!("All rule files are fine");
+        Ok(())
+    }
+
+    /// Describes every word whose romanization uses graphemes its language's rule file doesn't
+    /// declare, since the deromanizer passes those through silently. Languages without a rule
+    /// file, or with one that doesn't declare any graphemes, are skipped
+    fn validate_lexicon(&self) -> Result<Vec<String>> {
+        let mut problems = vec![];
+        for lang in self.get_langs()? {
+            let lsc = self.cfg.rule_file(&lang);
+            if !lsc.exists() {
+                continue;
+            }
+            let inventory = sc::sc::Inventory::from_ast(
+                &sc::include::load_lsc(&lsc)
+                    .with_context(|| format!("Failed to load `{}`", lsc.display()))?,
+            );
+            if inventory.is_empty() {
+                continue;
+            }
+            let mut stmt = self
+                .db
+                .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization")?;
+            for word in stmt.query_map([&lang.id], WordEntry::from_row)? {
+                let word = word?;
+                let unknown = inventory.unknown(&word.romanization);
+                if !unknown.is_empty() {
+                    problems.push(format!(
+                        "{}: {}: {}",
+                        lang.id,
+                        word.romanization,
+                        unknown.join(", ")
+                    ));
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let problems = self.validate_lexicon()?;
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        if !problems.is_empty() {
+            bail!(
+                "{} word(s) use graphemes their rule files don't declare",
+                problems.len()
+            );
+        }
+        println!("All words only use declared graphemes");
+        Ok(())
+    }
+
+    /// Splits the IPA of every word of the language into the symbols its rule file declares, and
+    /// counts them
+    fn phoneme_counts(&self, lang: &LangEntry) -> Result<PhonemeCounts> {
+        let lsc = self.cfg.rule_file(lang);
+        let lexurgy = sc::sc::Lexurgy::from_ast(
+            sc::include::load_lsc(&lsc)
+                .with_context(|| format!("Failed to load `{}`", lsc.display()))?,
+        );
+        let mut counts = collections::BTreeMap::<String, usize>::new();
+        let mut missing = vec![];
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization")?;
+        for word in stmt.query_map([&lang.id], WordEntry::from_row)? {
+            let word = word?;
+            let Some(ipa) = word
+                .ipa
+                .as_deref()
+                .map(normalize_ipa)
+                .filter(|ipa| !ipa.is_empty())
+            else {
+                missing.push(word.romanization);
+                continue;
+            };
+            for symbol in lexurgy.word(ipa).symbols() {
+                *counts.entry(symbol.to_string()).or_default() += 1;
+            }
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|(a, n), (b, m)| m.cmp(n).then(a.cmp(b)));
+        Ok(PhonemeCounts { counts, missing })
+    }
+
+    /// Runs the words through the rules of the language, formatting each result as `phon => rom`
+    /// for `LexurgyOutput::Both`
+    fn sc_words(&self, args: &ScArgs) -> Result<Vec<String>> {
+        let lang = self
+            .resolve_lang(&args.language)?
+            .with_rule(args.rule.as_deref());
+        LexurgyCmd::evolve(&lang, args.input, args.output)
+            .run(&self.cfg, args.words.iter().map(|w| &w[..]))?
+            .into_iter()
+            .map(|w| match w {
+                WordOutput::PhonRom(p, r) => Ok(format!("{} => {}", p, r)),
+                w => w.get_value(),
+            })
+            .collect()
+    }
+
+    /// The phonetic form of each of the words, deromanized with a single Lexurgy call
+    fn pronunciations(&self, lang: &LangEntry, words: &[String]) -> Result<Vec<String>> {
+        LexurgyCmd::deromanize(lang)
+            .run(&self.cfg, words.iter().map(|w| &w[..]))?
+            .into_iter()
+            .map(WordOutput::get_phon)
+            .collect()
+    }
+
+    fn pron(&self, args: PronArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let words = args
+            .words
+            .iter()
+            .map(|w| normalize_text(w))
+            .collect::<Vec<_>>();
+        for (word, phon) in words.iter().zip(self.pronunciations(&lang, &words)?) {
+            println!("{} [{}]", word, phon);
+        }
+        Ok(())
+    }
+
+    fn sc(&self, args: ScArgs) -> Result<()> {
+        for (word, result) in args.words.iter().zip(self.sc_words(&args)?) {
+            println!("{} => {}", word, result);
+        }
+        Ok(())
+    }
+
+    fn phonology(&self, args: PhonologyArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let PhonemeCounts { counts, missing } = self.phoneme_counts(&lang)?;
+        if !missing.is_empty() {
+            println!(
+                "WARNING: Skipped {} word(s) without IPA: {}\n",
+                missing.len(),
+                missing.join(", ")
+            );
+        }
+        let total = counts.iter().map(|(_, n)| n).sum::<usize>();
+        println!("{}: {} sound(s) in total", lang, total);
+        for line in frequency_table(&counts) {
+            println!("  {}", line);
+        }
+        Ok(())
+    }
+
+    /// Counts the characters, or grapheme clusters with `graphemes`, of every romanization of
+    /// the language, most frequent first. Whitespace isn't counted
+    fn letter_counts(&self, lang: &LangEntry, graphemes: bool) -> Result<Vec<(String, usize)>> {
+        let mut counts = collections::BTreeMap::<String, usize>::new();
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization")?;
+        for word in stmt.query_map([&lang.id], WordEntry::from_row)? {
+            let word = word?;
+            let letters: Vec<&str> = if graphemes {
+                word.romanization.graphemes(true).collect()
+            } else {
+                word.romanization
+                    .char_indices()
+                    .map(|(i, c)| &word.romanization[i..i + c.len_utf8()])
+                    .collect()
+            };
+            for letter in letters {
+                if !letter.trim().is_empty() {
+                    *counts.entry(letter.to_string()).or_default() += 1;
+                }
+            }
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by(|(a, n), (b, m)| m.cmp(n).then(a.cmp(b)));
+        Ok(counts)
+    }
+
+    fn freq(&self, args: FreqArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let counts = self.letter_counts(&lang, args.graphemes)?;
+        if counts.is_empty() {
+            println!("{} has no words to count the letters of", lang);
+            return Ok(());
+        }
+        let total = counts.iter().map(|(_, n)| n).sum::<usize>();
+        println!("{}: {} letter(s) in total", lang, total);
+        for line in frequency_table(&counts) {
+            println!("  {}", line);
+        }
+        Ok(())
+    }
+
+    fn lang_info(&self, args: &LangInfoArgs) -> Result<String> {
+        use std::fmt::Write;
+        let langs = self.get_langs()?;
+        let lang = &langs[lang_index(&langs, &args.language)?];
+        let mut out = format!("{}\n", lang);
+        writeln!(
+            &mut out,
+            " Rule file: {}",
+            self.cfg.rule_file(lang).display()
+        )?;
+        writeln!(&mut out, " Words: {}", self.word_count(&lang.id)?)?;
+
+        // Nearest first, stopping at the first language seen twice in case the origins are cyclic
+        let mut parents = vec![];
+        let mut l = lang;
+        while let Some(parent) = l
+            .origin
+            .as_ref()
+            .and_then(|o| langs.iter().find(|l| &l.id == o))
+        {
+            if parent.id == lang.id || parents.iter().any(|p: &&LangEntry| p.id == parent.id) {
+                break;
+            }
+            parents.push(parent);
+            if !args.parents {
+                break;
+            }
+            l = parent;
+        }
+        let parents = parents.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        writeln!(
+            &mut out,
+            " {}: {}",
+            if args.parents { "Ancestors" } else { "Parent" },
+            if parents.is_empty() {
+                "<none>".to_string()
+            } else {
+                parents.join(" <- ")
+            }
+        )?;
+
+        let graph = family_graph(&langs);
+        let mut children = vec![];
+        let mut todo = vec![&lang.id[..]];
+        while let Some(id) = todo.pop() {
+            for child in graph.get(&Some(id)).into_iter().flatten() {
+                if child.id != lang.id && !children.iter().any(|c: &&LangEntry| c.id == child.id) {
+                    children.push(*child);
+                    if args.children {
+                        todo.push(&child.id);
+                    }
+                }
+            }
+        }
+        let children = children.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        writeln!(
+            &mut out,
+            " {}: {}",
+            if args.children {
+                "Descendants"
+            } else {
+                "Children"
+            },
+            if children.is_empty() {
+                "<none>".to_string()
+            } else {
+                children.join(", ")
+            }
+        )?;
+        Ok(out)
+    }
+
+    fn tree(&mut self) -> Result<()> {
+        let langs = self.get_langs()?;
+        let graph = family_graph(&langs);
+        let mut visited = collections::HashSet::new();
+        let mut out = String::new();
+        for root in graph.get(&None).into_iter().flatten() {
+            self.write_family_tree(&mut out, &graph, root, "", "", &mut visited)?;
+        }
+        print!("{}", out);
+
+        // Every language has at most one parent, so anything we can't reach from a root has to
+        // be part of (or descend from) a cycle
+        let cyclic = langs
+            .iter()
+            .filter(|l| !visited.contains(&l.id[..]))
+            .map(|l| l.to_string())
+            .collect::<Vec<_>>();
+        if !cyclic.is_empty() {
+            println!(
+                "\nWARNING: The following languages have cyclic origins: {}",
+                cyclic.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    fn family_dot_cmd(&self, args: FamilyDotArgs) -> Result<()> {
+        let dot = self.family_dot()?;
+        match &args.out {
+            Some(path) => {
+                fs::write(path, dot).with_context(|| format!("Writing graph file: {:?}", path))?
+            }
+            None => print!("{}", dot),
+        }
+        Ok(())
+    }
+
+    /// The language family as a DOT graph, with an edge from each language to its children
+    fn family_dot(&self) -> Result<String> {
+        use std::fmt::Write;
+        let langs = self.get_langs()?;
+        let mut out = String::from("digraph family {\n");
+        for lang in &langs {
+            let label = format!("{}\n({} words)", lang.name, self.word_count(&lang.id)?);
+            writeln!(out, "    {} [label={}];", dot_id(&lang.id), dot_id(&label))?;
+        }
+        for (parent, children) in &family_graph(&langs) {
+            let Some(parent) = parent else { continue };
+            for child in children {
+                writeln!(out, "    {} -> {};", dot_id(parent), dot_id(&child.id))?;
+            }
+        }
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    fn write_family_tree<'l>(
+        &self,
+        out: &mut String,
+        graph: &FamilyGraph<'l>,
+        lang: &'l LangEntry,
+        prefix: &str,
+        child_prefix: &str,
+        visited: &mut collections::HashSet<&'l str>,
+    ) -> Result<()> {
+        use std::fmt::Write;
+        visited.insert(&lang.id);
+        write!(
+            out,
+            "{}{}: {} ({} words)",
+            prefix,
+            lang.id,
+            lang.name,
+            self.word_count(&lang.id)?
+        )?;
+        match &lang.origin {
+            Some(origin) if prefix.is_empty() => writeln!(out, " (unknown origin `{}`)", origin)?,
+            _ => writeln!(out)?,
+        }
+        let children = graph
+            .get(&Some(&lang.id[..]))
+            .map(|c| &c[..])
+            .unwrap_or(&[]);
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            self.write_family_tree(
+                out,
+                graph,
+                child,
+                &format!("{}{}", child_prefix, if last { "`-- " } else { "|-- " }),
+                &format!("{}{}", child_prefix, if last { "    " } else { "|   " }),
+                visited,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn search_words(&self, args: &SearchArgs) -> Result<Vec<WordEntry>> {
+        use rusqlite::ToSql;
+        let column = if args.in_meaning {
+            "meaning"
+        } else {
+            "romanization"
+        };
+        let mut conds = vec![];
+        let mut vs: Vec<Box<dyn ToSql>> = vec![];
+        if let Some(query) = &args.query {
+            let (cond, pattern) = if args.regex {
+                regex::Regex::new(query)
+                    .with_context(|| format!("Invalid regular expression: `{}`", query))?;
+                ("REGEXP ?", query.clone())
+            } else if args.exact {
+                ("= ?", query.clone())
+            } else if args.prefix {
+                ("LIKE ? ESCAPE '\\'", format!("{}%", like_escape(query)))
+            } else {
+                ("LIKE ? ESCAPE '\\'", format!("%{}%", like_escape(query)))
+            };
+            conds.push(format!("{} {}", column, cond));
+            vs.push(Box::new(pattern));
+        }
+        if let Some(lang) = &args.language {
+            conds.push("lang = ?".to_string());
+            vs.push(Box::new(self.resolve_lang(lang)?.id));
+        }
+        if let Some(kind) = &args.kind {
+            conds.push("kind = ?".to_string());
+            vs.push(Box::new(normalize_text(kind)));
+        }
+        for tag in &args.tag {
+            conds.push("has_tag(flags, ?)".to_string());
+            vs.push(Box::new(tag.clone()));
+        }
+        if conds.is_empty() {
+            conds.push("1".to_string());
+        }
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT * FROM words WHERE {} ORDER BY lang, romanization, id",
+            conds.join(" AND ")
+        ))?;
+        let words = stmt
+            .query_map(
+                rusqlite::params_from_iter(vs.iter().map(|v| v.as_ref())),
+                WordEntry::from_row,
+            )?
+            .collect::<Result<_, _>>()?;
+        Ok(words)
+    }
+
+    /// Surrounds each of the words with the `n` words of its language coming before and after it
+    /// in romanization order. Returns the runs of consecutive words, overlapping ones merged, each
+    /// word along with whether it was one of the given ones
+    fn with_context(&self, words: Vec<WordEntry>, n: usize) -> Result<Vec<Vec<(WordEntry, bool)>>> {
+        let mut by_lang = collections::BTreeMap::<String, collections::BTreeSet<u32>>::new();
+        for w in words {
+            by_lang.entry(w.lang).or_default().insert(w.id);
+        }
+        let mut runs = vec![];
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization, id")?;
+        for (lang, ids) in by_lang {
+            let all = stmt
+                .query_map([&lang], WordEntry::from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut shown = collections::BTreeSet::new();
+            for (i, _) in all.iter().enumerate().filter(|(_, w)| ids.contains(&w.id)) {
+                shown.extend(i.saturating_sub(n)..=(i + n).min(all.len() - 1));
+            }
+            let mut prev = None;
+            for (i, w) in all.into_iter().enumerate() {
+                if !shown.contains(&i) {
+                    continue;
+                }
+                if prev.is_none_or(|p| p + 1 != i) {
+                    runs.push(vec![]);
+                }
+                let hit = ids.contains(&w.id);
+                runs.last_mut().unwrap().push((w, hit));
+                prev = Some(i);
+            }
+        }
+        Ok(runs)
+    }
+
+    fn search(&mut self, args: SearchArgs) -> Result<()> {
+        let words = self.search_words(&args)?;
+        if words.is_empty() {
+            println!("No matching words found");
+        }
+        let runs = if args.context == 0 {
+            words.into_iter().map(|w| vec![(w, true)]).collect()
+        } else {
+            self.with_context(words, args.context)?
+        };
+        // The words come sorted by language already
+        let mut lang = None;
+        for run in runs {
+            if lang.as_ref() != Some(&run[0].0.lang) {
+                println!("{}:", run[0].0.lang);
+                lang = Some(run[0].0.lang.clone());
+            } else if args.context != 0 {
+                println!("  ...");
+            }
+            for (word, hit) in run {
+                let marker = match (args.context, hit) {
+                    (0, _) => "",
+                    (_, true) => "* ",
+                    (_, false) => "  ",
+                };
+                println!(
+                    "  {}{} [{}]: {} ({})",
+                    marker,
+                    word.romanization,
+                    display_ipa(word.ipa.as_deref()),
+                    word.meaning,
+                    word.kind
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the romanization with a parameter, ignoring case with `Config::ignore_case`
+    fn romanization_eq(&self) -> &'static str {
+        if self.cfg.ignore_case {
+            "romanization = ? COLLATE NOCASE"
+        } else {
+            "romanization = ?"
+        }
+    }
+
+    /// The words spelled `rom`, in the order they were added. `#N` in a word lookup indexes
+    /// into this list
+    fn homophones(&self, lang: &LangEntry, rom: &str) -> Result<Vec<WordEntry>> {
+        let mut stmt = self.db.prepare(&format!(
+            "SELECT * FROM words WHERE {} AND lang = ? ORDER BY id",
+            self.romanization_eq()
+        ))?;
+        let homophones = stmt
+            .query_map([rom, &lang.id], WordEntry::from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(homophones)
+    }
+
+    fn add(&mut self, args: AddArgs) -> Result<()> {
+        let stdin = io::stdin();
+        if args.word.is_none() && !stdin.is_terminal() {
+            return self.add_from_stdin(args);
+        }
+        // Only ask about homophones when there's someone to answer
+        let mut prompt = stdin.is_terminal().then(|| stdin.lock());
+        self.add_word(args, prompt.as_mut().map(|p| p as &mut dyn io::BufRead))
+    }
+
+    /// Adds a single word. If it turns out to be a homophone and `-H` wasn't given, the user is
+    /// asked whether to add it anyway through `prompt`, or the word is rejected without one.
+    fn add_word(&mut self, args: AddArgs, prompt: Option<&mut dyn io::BufRead>) -> Result<()> {
+        println!("{:?}", args);
+        let (Some(word), Some(meaning), Some(kind)) = (&args.word, &args.meaning, &args.kind)
+        else {
+            bail!("Expected a word, its meaning and its kind");
+        };
+        self.cfg.settings.validate_required(meaning, kind)?;
+        self.cfg
+            .settings
+            .validate_kind(&normalize_text(kind), args.force_kind)?;
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_unless(word, args.no_normalize);
+        // Make sure there isn't already another word in the db if it's not supposed to be a homophone
+        if !args.homophone {
+            let homophones = self.homophones(&lang, &rom)?;
+            if !homophones.is_empty() {
+                let err = homophone_error(&lang, &rom, &homophones);
+                let Some(prompt) = prompt else {
+                    bail!(
+                        "{}\nIf you want to add it as a homophone, use the -H flag.",
+                        err
+                    );
+                };
+                println!("{}", err);
+                if !confirm("Add it as a homophone anyway?", prompt)? {
+                    bail!("Not adding `{}`", rom);
+                }
+            }
+        }
+
+        let mut phon: Option<String> = args.ipa.clone();
+
+        if phon.is_none() && !args.disable_autorom {
+            println!("Reromanization...");
+            let mut phons =
+                LexurgyCmd::deromanize(&lang).run(&self.cfg, std::iter::once(&rom[..]))?;
+            if phons.len() != 1 {
+                bail!("expected a single word back, got {}", phons.len());
+            }
+            let p = phons.remove(0).get_phon()?;
+            println!("  {} => {}", &rom, &p);
+            phon = Some(p);
+        }
+
+        let tr = begin(&mut self.db)?;
+        tr.execute(
+            "INSERT INTO words
+               (lang, romanization, ipa, meaning, kind, note, origin, flags)
+               VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                &lang.id,
+                &rom,
+                &phon,
+                meaning,
+                &normalize_text(kind),
+                &args.note.clone().unwrap_or_default(),
+                &args.origin.clone().unwrap_or_default(),
+                Flags::default(),
+            ],
+        )?;
+        log_added(&tr, tr.last_insert_rowid() as u32, &rom)?;
+        tr.commit()?;
+        println!("Added `{}` to {}", word, lang);
+        if self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(args.language))?;
+        }
+        Ok(())
+    }
+
+    fn add_from_stdin(&mut self, args: AddArgs) -> Result<()> {
+        let words = parse_word_lines(io::stdin().lock())?;
+        self.add_batch(args, words)
+    }
+
+    /// Adds several words at once, deromanizing the ones without IPA with a single Lexurgy call
+    fn add_batch(&mut self, args: AddArgs, words: Vec<NewWord>) -> Result<()> {
+        if args.ipa.is_some() {
+            bail!("Can't specify `--ipa` when adding multiple words");
+        }
+        if args.no_normalize {
+            bail!("Can't specify `--no-normalize` when adding multiple words");
+        }
+        for word in &words {
+            self.cfg
+                .settings
+                .validate_required(&word.meaning, &word.kind)
+                .and_then(|_| self.cfg.settings.validate_kind(&word.kind, args.force_kind))
+                .with_context(|| format!("Adding `{}`", word.romanization))?;
+        }
+        let lang = self.resolve_lang(&args.language)?;
+
+        // Words conflicting with existing words (or each other) are reported once everything
+        // else has been added
+        let mut conflicts = vec![];
+        let mut accepted: Vec<NewWord> = vec![];
+        for word in words {
+            if !args.homophone {
+                let homophones = self.homophones(&lang, &word.romanization)?;
+                if !homophones.is_empty() {
+                    conflicts.push(homophone_error(&lang, &word.romanization, &homophones));
+                    continue;
+                }
+                if accepted.iter().any(|w| w.romanization == word.romanization) {
+                    conflicts.push(format!(
+                        "`{}` appears more than once in the input\n",
+                        word.romanization
+                    ));
+                    continue;
+                }
+            }
+            accepted.push(word);
+        }
+
+        let mut phons = accepted.iter().map(|w| w.ipa.clone()).collect::<Vec<_>>();
+        // Only the words that didn't come with their IPA get deromanized
+        let missing = accepted
+            .iter()
+            .zip(&mut phons)
+            .filter(|(_, p)| p.is_none())
+            .collect::<Vec<_>>();
+        if !args.disable_autorom && !missing.is_empty() {
+            println!("Reromanization...");
+            let out = LexurgyCmd::deromanize(&lang)
+                .run(&self.cfg, missing.iter().map(|(w, _)| &w.romanization[..]))?;
+            if out.len() != missing.len() {
+                bail!("expected {} words back, got {}", missing.len(), out.len());
+            }
+            for ((word, p), o) in missing.into_iter().zip(out) {
+                let o = o.get_phon()?;
+                println!("  {} => {}", &word.romanization, &o);
+                *p = Some(o);
+            }
+        }
+
+        let tr = begin(&mut self.db)?;
+        for (word, phon) in accepted.iter().zip(&phons) {
+            tr.execute(
+                "INSERT INTO words
+                   (lang, romanization, ipa, meaning, kind, note, origin, flags)
+                   VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    &lang.id,
+                    &word.romanization,
+                    phon,
+                    &word.meaning,
+                    &word.kind,
+                    &args.note.clone().unwrap_or_default(),
+                    &args.origin.clone().unwrap_or_default(),
+                    Flags::default(),
+                ],
+            )?;
+            log_added(&tr, tr.last_insert_rowid() as u32, &word.romanization)?;
+        }
+        tr.commit()?;
+        println!("Added {} word(s) to {}", accepted.len(), lang);
+        if !accepted.is_empty() && self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(args.language))?;
+        }
+
+        if !conflicts.is_empty() {
+            bail!(
+                "{} word(s) were not added:\n\n{}\nIf you want to add them as homophones, use the -H flag.",
+                conflicts.len(),
+                conflicts.join("\n")
+            );
+        }
+        Ok(())
+    }
+
+    fn import(&mut self, args: ImportArgs, prompt: &mut dyn io::BufRead) -> Result<()> {
+        let f = fs::File::open(&args.file)
+            .with_context(|| format!("Failed to open `{}`", args.file.display()))?;
+        let words = parse_csv_words(io::BufReader::new(f), &args)
+            .with_context(|| format!("Failed to read `{}`", args.file.display()))?;
+        let lang = self.resolve_lang(&args.language)?;
+        let existing = self.word_count(&lang.id)?;
+        if existing > 0 && !args.append && !args.yes {
+            let question = format!(
+                "{} already has {} word(s), importing {} more might duplicate them.",
+                lang,
+                existing,
+                words.len()
+            );
+            if !confirm_typed(&question, &lang.id, prompt)? {
+                bail!("Import cancelled, use --append to add to a language with words");
+            }
+        }
+        let add_args = AddArgs {
+            language: args.language,
+            word: None,
+            meaning: None,
+            kind: None,
+            origin: None,
+            note: None,
+            ipa: None,
+            disable_autorom: args.disable_autorom,
+            homophone: args.homophone,
+            force_kind: args.force_kind,
+            no_normalize: false,
+        };
+        self.add_batch(add_args, words)
+    }
+
+    fn try_get_unique_word(&self, lang: &LangEntry, mut rom: &str) -> Result<Option<WordEntry>> {
+        use std::fmt::Write;
+        let mut index = None;
+
+        if let Some((r, i)) = rom.split_once('#') {
+            index = Some(i.parse::<usize>()?);
+            rom = r;
+        }
+
+        // Indexed in the order `homophones` lists them
+        let mut words = self.homophones(lang, rom)?;
+
+        if words.is_empty() {
+            bail!("No matching words found{}", self.suggestions(lang, rom)?);
+        }
+
+        if let Some(index) = index {
+            if index >= words.len() {
+                let mut err_msg = format!("Index `{}` out of bounds for homophone list:", index);
+                for line in format_table(&homophone_rows(&words), " ") {
+                    writeln!(&mut err_msg, " {}", line)?;
+                }
+                bail!(err_msg);
+            }
+            return Ok(Some(words.swap_remove(index)));
+        }
+
+        if words.len() == 1 {
+            return Ok(Some(words.swap_remove(0)));
+        }
+
+        let mut err_msg = format!("`{0}` has homophones, please specify by passing `{0}#N` where N is one of the following indices:\n", &rom);
+        for (i, h) in words.iter().enumerate() {
+            writeln!(
+                &mut err_msg,
+                " {}:  {}: {}, {}",
+                i, h.romanization, h.meaning, h.kind
+            )?;
+        }
+        bail!(err_msg);
+    }
+
+    /// Finds the words of `lang` spelled at most `max_distance` edits away from `query`, closest
+    /// first
+    fn suggest(
+        &self,
+        lang: &LangEntry,
+        query: &str,
+        max_distance: usize,
+        limit: usize,
+    ) -> Result<Vec<(WordEntry, usize)>> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization, id")?;
+        let mut close = vec![];
+        for word in stmt.query_map([&lang.id], WordEntry::from_row)? {
+            let word = word?;
+            let distance = edit_distance(&word.romanization, query);
+            if distance <= max_distance {
+                close.push((word, distance));
+            }
+        }
+        // Stable, so homophones stay ordered by id
+        close.sort_by(|(a, da), (b, db)| {
+            da.cmp(db).then_with(|| a.romanization.cmp(&b.romanization))
+        });
+        close.truncate(limit);
+        Ok(close)
+    }
+
+    /// Lists the words of `lang` spelled similarly to `rom`, for when there's no exact match
+    fn suggestions(&self, lang: &LangEntry, rom: &str) -> Result<String> {
+        let close = self.suggest(lang, rom, default_max_distance(rom), DEFAULT_SUGGESTIONS)?;
+        if close.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(format!(". Did you mean:\n{}", format_suggestions(&close)))
+    }
+
+    fn suggest_cmd(&mut self, args: SuggestArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let word = normalize_text(&args.word);
+        let max_distance = args
+            .max_distance
+            .unwrap_or_else(|| default_max_distance(&word));
+        let close = self.suggest(&lang, &word, max_distance, args.limit)?;
+        if close.is_empty() {
+            println!("No similar words found");
+        }
+        print!("{}", format_suggestions(&close));
+        Ok(())
+    }
+
+    /// The words (more than one if they're homophones) spelled right after `rom`, or right
+    /// before it if not `next`. Empty at either end of the lexicon
+    fn neighbour(&self, lang: &LangEntry, rom: &str, next: bool) -> Result<Vec<WordEntry>> {
+        let query = if next {
+            "SELECT romanization FROM words WHERE lang = ? AND romanization > ?
+               ORDER BY romanization LIMIT 1"
+        } else {
+            "SELECT romanization FROM words WHERE lang = ? AND romanization < ?
+               ORDER BY romanization DESC LIMIT 1"
+        };
+        let found: Option<String> = self
+            .db
+            .query_row(query, [&lang.id, rom], |row| row.get(0))
+            .optional()?;
+        match found {
+            Some(found) => self.homophones(lang, &found),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn neighbour_cmd(&self, args: NeighbourArgs, next: bool) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_text(&args.word);
+        let words = self.neighbour(&lang, &rom, next)?;
+        if words.is_empty() {
+            println!(
+                "Nothing comes {} `{}` in {}",
+                if next { "after" } else { "before" },
+                rom,
+                lang
+            );
+        }
+        for word in words {
+            println!(
+                "{} [{}]: {} ({})",
+                word.romanization,
+                display_ipa(word.ipa.as_deref()),
+                word.meaning,
+                word.kind
+            );
+        }
+        Ok(())
+    }
+
+    fn edit(&mut self, args: EditArgs) -> Result<()> {
+        use rusqlite::ToSql;
+        use std::fmt::Write;
+        if let Some(kind) = &args.kind {
+            self.cfg.settings.validate_kind(kind, args.force_kind)?;
+        }
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_unless(&args.word, args.no_normalize);
+        if let Some(entry) = self.try_get_unique_word(&lang, &rom)? {
+            let rename = args
+                .rename
+                .as_deref()
+                .map(|new| normalize_unless(new, args.no_normalize))
+                .filter(|new| *new != entry.romanization);
+            let mut ipa = args.ipa.clone();
+            if let Some(new) = &rename {
+                if !args.homophone {
+                    let homophones = self.homophones(&lang, new)?;
+                    if !homophones.is_empty() {
+                        bail!(
+                            "{}\nIf you want to rename it to a homophone, use the -H flag.",
+                            homophone_error(&lang, new, &homophones)
+                        );
+                    }
+                }
+                if ipa.is_none() && !args.disable_autorom {
+                    println!("Reromanization...");
+                    let mut phons =
+                        LexurgyCmd::deromanize(&lang).run(&self.cfg, iter::once(&new[..]))?;
+                    if phons.len() != 1 {
+                        bail!("expected a single word back, got {}", phons.len());
+                    }
+                    let p = phons.remove(0).get_phon()?;
+                    println!("  {} => {}", new, &p);
+                    ipa = Some(p);
+                }
+            }
+
+            let structured = entry.origin.as_deref().and_then(structured_origin);
+            if let Some(structured) = structured {
+                if (args.origin.is_some() || args.origin_clear) && !args.force {
+                    bail!(
+                        "`{}` has the origin `{}` recorded by `inherit`.\nUse --origin-note to comment on it, or --force to replace it.",
+                        entry.romanization,
+                        structured
+                    );
+                }
+            }
+            // `Some(None)` unsets the origin
+            let origin = if args.origin_clear {
+                Some(None)
+            } else if let Some(note) = &args.origin_note {
+                Some(Some(match structured {
+                    Some(structured) => format!("{} {}", structured, note.trim()),
+                    None => note.trim().to_string(),
+                }))
+            } else {
+                args.origin.clone().map(Some)
+            };
+
+            let mut changed = format!("Changed the following for `{}`:\n", rom);
+            let mut query_str = "UPDATE words SET ".to_string();
+            let mut first = true;
+            fn set(v: &Option<String>) -> Option<Option<&str>> {
+                v.as_deref().map(Some)
+            }
+            // The new value of each field to change, `Some(None)` setting it to NULL
+            let fields = &[
+                ("romanization", Some(&entry.romanization[..]), set(&rename)),
+                ("ipa", entry.ipa.as_deref(), set(&ipa)),
+                ("meaning", Some(&entry.meaning[..]), set(&args.meaning)),
+                ("kind", Some(&entry.kind[..]), set(&args.kind)),
+                (
+                    "origin",
+                    entry.origin.as_deref(),
+                    origin.as_ref().map(Option::as_deref),
+                ),
+                ("note", entry.note.as_deref(), set(&args.note)),
+            ];
+            if fields.iter().all(|(_, _, val)| val.is_none()) {
+                println!("Nothing to change for `{}`", rom);
+                return Ok(());
+            }
+            for (fld, old, val) in fields {
+                if let Some(v) = val {
+                    if !first {
+                        query_str.push_str(", ");
+                    }
+                    write!(&mut query_str, "{} = ?", fld)?;
+                    writeln!(
+                        &mut changed,
+                        " {}: {} => {}",
+                        fld,
+                        old.unwrap_or("<unset>"),
+                        v.unwrap_or("<unset>")
+                    )?;
+                    first = false;
+                }
+            }
+            query_str.push_str(" WHERE id = ?");
+            let vs = fields
+                .iter()
+                .filter_map(|(_, _, val)| val.as_ref().map(|v| v.to_sql()))
+                .chain(iter::once(entry.id.to_sql()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let tr = begin(&mut self.db)?;
+            let _ = tr.execute(&query_str[..], rusqlite::params_from_iter(vs.iter()))?;
+            for (fld, old, val) in fields {
+                if let Some(v) = val {
+                    log_change(&tr, entry.id, fld, *old, *v)?;
+                }
+            }
+            tr.commit()?;
+            println!("{}", changed);
+            if self.cfg.settings.auto_dump {
+                self.dump(DumpArgs::new(args.language))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn swap(&mut self, args: SwapArgs) -> Result<()> {
+        if args.a == args.b {
+            bail!("Can't swap homophone {} with itself", args.a);
+        }
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_text(&args.word);
+        let homophone = |i| -> Result<WordEntry> {
+            self.try_get_unique_word(&lang, &format!("{}#{}", rom, i))?
+                .ok_or(anyhow!("No homophone `{}#{}`", rom, i))
+        };
+        let a = homophone(args.a)?;
+        let b = homophone(args.b)?;
+
+        let tr = begin(&mut self.db)?;
+        for (word, other) in [(&a, &b), (&b, &a)] {
+            tr.execute(
+                "UPDATE words SET meaning = ?, kind = ?, note = ?, origin = ? WHERE id = ?",
+                params![other.meaning, other.kind, other.note, other.origin, word.id],
+            )?;
+            let fields = [
+                ("meaning", Some(&word.meaning), Some(&other.meaning)),
+                ("kind", Some(&word.kind), Some(&other.kind)),
+                ("note", word.note.as_ref(), other.note.as_ref()),
+                ("origin", word.origin.as_ref(), other.origin.as_ref()),
+            ];
+            for (fld, old, new) in fields {
+                if old != new {
+                    log_change(&tr, word.id, fld, old.map(|o| &o[..]), new.map(|n| &n[..]))?;
+                }
+            }
+        }
+        tr.commit()?;
+        println!(
+            "Swapped `{}#{}` ({}) and `{}#{}` ({})",
+            rom, args.a, a.meaning, rom, args.b, b.meaning
+        );
+        if self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(args.language))?;
+        }
+        Ok(())
+    }
+
+    fn del(&mut self, args: DelArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_text(&args.word);
+        if let Some(entry) = self.try_get_unique_word(&lang, &rom)? {
+            let tr = begin(&mut self.db)?;
+            tr.execute("DELETE FROM ipa_variants WHERE word_id = ?", [entry.id])?;
+            tr.execute("DELETE FROM examples WHERE word_id = ?", [entry.id])?;
+            tr.execute("DELETE FROM words WHERE id = ?", [entry.id])?;
+            log_deleted(&tr, entry.id, &entry.romanization)?;
+            tr.commit()?;
+            println!(
+                "Deleted: {}: {} ({})",
+                entry.romanization, entry.meaning, entry.kind
+            );
+            if self.cfg.settings.auto_dump {
+                self.dump(DumpArgs::new(args.language))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The words that have an older word of the same language with the same spelling, meaning and
+    /// kind. Homophones differing in meaning or kind aren't duplicates
+    fn duplicates(&self, lang: Option<&LangEntry>) -> Result<Vec<WordEntry>> {
+        let mut stmt = self.db.prepare(
+            "SELECT * FROM words w WHERE (?1 IS NULL OR lang = ?1) AND EXISTS (
+               SELECT 1 FROM words o
+                 WHERE o.lang = w.lang AND o.romanization = w.romanization
+                   AND o.meaning = w.meaning AND o.kind = w.kind AND o.id < w.id
+             ) ORDER BY lang, romanization, id",
+        )?;
+        let words = stmt
+            .query_map([lang.map(|l| &l.id)], WordEntry::from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(words)
+    }
+
+    fn dedupe(&mut self, args: DedupeArgs) -> Result<()> {
+        let lang = args
+            .language
+            .as_ref()
+            .map(|l| self.resolve_lang(l))
+            .transpose()?;
+        let duplicates = self.duplicates(lang.as_ref())?;
+        if duplicates.is_empty() {
+            println!("No duplicates found");
+            return Ok(());
+        }
+        println!(
+            "{} duplicate(s){}:",
+            duplicates.len(),
+            if args.dry_run {
+                " would be removed"
+            } else {
+                " removed"
+            }
+        );
+        for w in &duplicates {
+            println!(
+                " {}: {} ({}): {} [id {}]",
+                w.lang, w.romanization, w.kind, w.meaning, w.id
+            );
+        }
+        if args.dry_run {
+            return Ok(());
+        }
+
+        let tr = begin(&mut self.db)?;
+        for w in &duplicates {
+            tr.execute("DELETE FROM ipa_variants WHERE word_id = ?", [w.id])?;
+            tr.execute("DELETE FROM examples WHERE word_id = ?", [w.id])?;
+            tr.execute("DELETE FROM words WHERE id = ?", [w.id])?;
+            log_deleted(&tr, w.id, &w.romanization)?;
+        }
+        tr.commit()?;
+        if self.cfg.settings.auto_dump {
+            let mut langs = duplicates.into_iter().map(|w| w.lang).collect::<Vec<_>>();
+            langs.dedup();
+            for lang in langs {
+                self.dump(DumpArgs::new(lang))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The languages without any words that no other language descends from, so deleting them
+    /// doesn't break any `origin` chain
+    fn prunable_langs(&self) -> Result<Vec<LangEntry>> {
+        let mut stmt = self.db.prepare(
+            "SELECT * FROM langs l
+             WHERE NOT EXISTS (SELECT 1 FROM words WHERE lang = l.id)
+               AND NOT EXISTS (SELECT 1 FROM langs c WHERE c.origin = l.id)
+             ORDER BY id",
+        )?;
+        let langs = stmt
+            .query_map([], LangEntry::from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(langs)
+    }
+
+    fn prune(&mut self, args: PruneArgs) -> Result<()> {
+        let langs = self.prunable_langs()?;
+        if langs.is_empty() {
+            println!("No languages to prune");
+            return Ok(());
+        }
+        println!(
+            "{} language(s){}:",
+            langs.len(),
+            if args.dry_run {
+                " would be removed"
+            } else {
+                " removed"
+            }
+        );
+        for lang in &langs {
+            println!(" {}", lang);
+        }
+        if args.dry_run {
+            return Ok(());
+        }
+
+        let tr = begin(&mut self.db)?;
+        for lang in &langs {
+            tr.execute("DELETE FROM exports WHERE lang = ?", [&lang.id])?;
+            tr.execute("DELETE FROM langs WHERE id = ?", [&lang.id])?;
+        }
+        tr.commit()?;
+        self.forget_langs();
+        Ok(())
+    }
+
+    fn use_lang(&self, args: UseArgs) -> Result<()> {
+        let file = self.cfg.root.join(WORKING_LANG_FILE);
+        if args.clear {
+            if file.exists() {
+                fs::remove_file(&file)
+                    .with_context(|| format!("Removing the working language file {:?}", file))?;
+            }
+            println!("Forgot the working language");
+        } else if let Some(lang) = &args.language {
+            let lang = self.resolve_lang(lang)?;
+            fs::write(&file, &lang.id)
+                .with_context(|| format!("Writing the working language file {:?}", file))?;
+            println!("Now working on {}", lang);
+        } else {
+            match working_lang(&self.cfg.root) {
+                Some(lang) => println!("Working on {}", self.resolve_lang(&lang)?),
+                None => println!("No working language set"),
+            }
+        }
+        if env::var(WORKING_LANG_VAR).is_ok() {
+            println!(
+                "WARNING: `{}` is set, it overrides the language chosen with `use`",
+                WORKING_LANG_VAR
+            );
+        }
+        Ok(())
+    }
+
+    /// The changes made to a word, oldest first
+    fn word_history(&self, word_id: u32) -> Result<Vec<ChangeEntry>> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM changelog WHERE word_id = ? ORDER BY id")?;
+        let changes = stmt
+            .query_map([word_id], ChangeEntry::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(changes)
+    }
+
+    fn watch(&mut self, args: WatchArgs) -> Result<()> {
+        let db_file = self.cfg.db_path();
+        // Wait out other processes writing to the database rather than failing right away
+        self.db.busy_timeout(WATCH_BUSY_TIMEOUT)?;
+        println!(
+            "Watching `{}` for changes, press Ctrl-C to stop",
+            db_file.display()
+        );
+        let mut last = None;
+        loop {
+            if self.redump_if_changed(&args.language, &db_file, &mut last)? {
+                println!("Dumped `{}`", args.language);
+            }
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+
+    /// Dumps the language if `db_file` was modified since `last`, returning whether it did. If the
+    /// database is locked by another process, the dump is left for the next time
+    fn redump_if_changed(
+        &mut self,
+        language: &str,
+        db_file: &Path,
+        last: &mut Option<time::SystemTime>,
+    ) -> Result<bool> {
+        let modified = fs::metadata(db_file)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to check `{}`", db_file.display()))?;
+        if *last == Some(modified) {
+            return Ok(false);
+        }
+        self.forget_langs();
+        match self.dump(DumpArgs::new(language.to_string())) {
+            Ok(()) => {}
+            Err(err) if is_locked(&err) => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        *last = Some(modified);
+        Ok(true)
+    }
+
+    fn history(&mut self, args: HistoryArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let rom = normalize_text(&args.word);
+        if let Some(entry) = self.try_get_unique_word(&lang, &rom)? {
+            let changes = self.word_history(entry.id)?;
+            if changes.is_empty() {
+                println!("No recorded changes to `{}`", rom);
+            }
+            for c in changes {
+                println!(
+                    "{} {}: {} => {}",
+                    c.timestamp,
+                    c.field,
+                    c.old.as_deref().unwrap_or("<unset>"),
+                    c.new.as_deref().unwrap_or("<unset>")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Why each of the `tokens` that doesn't pick out a word of `lang` doesn't, looked up like any
+    /// word so indices like `ka#1` are checked too. Homophones without an index are still words
+    fn lexicon_warnings(&self, lang: &LangEntry, tokens: &[String]) -> Result<Vec<String>> {
+        let mut warnings = vec![];
+        for tok in tokens {
+            if let Err(err) = self.try_get_unique_word(lang, tok) {
+                if tok.contains('#') || self.homophones(lang, tok)?.is_empty() {
+                    warnings.push(format!("`{}` is not a word in {}: {}", tok, lang, err));
+                }
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// The evolved words go to `out`, warnings to STDERR so they can't get mixed into the JSON
+    fn evolve(&mut self, args: EvolveArgs, out: &mut dyn io::Write) -> Result<()> {
+        let langs = self.get_langs()?;
+        validate_family(&langs)?;
+        let from =
+            &langs[lang_index(&langs, &args.from_lang).context("Invalid 'from' language")?];
+        let to = &langs[lang_index(&langs, &args.to_lang).context("Invalid 'to' language")?];
+
+        if from.id == to.id {
+            bail!("'from' and 'to' language are the same. Nothing to evolve");
+        }
+
+        if args.from_ipa && from.origin.is_some() {
+            bail!(
+                "{} isn't a proto-language, only the words of one can be given as IPA",
+                from
+            );
+        }
+        let target = to.clone().with_rule(args.rule.as_deref());
+        let mut steps = evolution_steps(&langs, from, to)?;
+        if let Some(last) = steps.last_mut() {
+            *last = &target;
+        }
+
+        let mut tokens = vec![];
+        for sentence_fragment in &args.sentence {
+            tokens.extend(sentence_fragment.split(' ').map(|f| f.replace('-', " ")));
+        }
+        check_token_limit(tokens.len(), args.limit, args.yes, &mut io::stdin().lock())?;
+
+        if args.check_lexicon {
+            for warning in self.lexicon_warnings(from, &tokens)? {
+                eprintln!("WARNING: {}", warning);
+            }
+        }
+
+        let evolution = self.run_evolution(&args, &steps, tokens.clone())?;
+        if args.format == EvolveFormat::Json {
+            let json = serde_json::to_string_pretty(&evolved_tokens(&tokens, &evolution))?;
+            writeln!(out, "{}", json)?;
+            return Ok(());
+        }
+        let Evolution { stages, evolved } = evolution;
+        // The words in each language shown, for `--compare`
+        let mut compared = vec![(&from.id[..], tokens)];
+        for stage in &stages {
+            if args.trace_rules {
+                writeln!(out, "{} rules:", stage.lang.id)?;
+                for line in &stage.trace {
+                    writeln!(out, "  {}", line)?;
+                }
+            }
+            if stage.lang.id != to.id && !args.show_intermediate {
+                continue;
+            }
+            if args.compare {
+                compared.push((&stage.lang.id[..], stage.words.clone()));
+            } else {
+                write!(out, "{}: ", stage.lang.id)?;
+                for tok in &stage.words {
+                    write!(out, "{} ", tok)?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        if args.compare {
+            for line in format_comparison(&compared) {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        if args.apply_to_lexicon {
+            self.apply_to_lexicon(to, &evolved, &mut io::stdin().lock())?;
+        }
+        Ok(())
+    }
+
+    /// Runs the sound changes of each of the `steps` on the words in turn
+    fn run_evolution<'l>(
+        &self,
+        args: &EvolveArgs,
+        steps: &[&'l LangEntry],
+        mut tokens: Vec<String>,
+    ) -> Result<Evolution<'l>> {
+        let mut stages = vec![];
+        let mut evolved = vec![];
+        for (i, &step) in steps.iter().enumerate() {
+            let first = i == 0;
+            let last = i + 1 == steps.len();
+            // Both forms of the final words are needed to add them, or to show them as JSON
+            let both = last
+                && (args.apply_to_lexicon
+                    || args.format == EvolveFormat::Json && args.show_phonetic);
+            let mut cmd = LexurgyCmd::evolve(
+                step,
+                if first && !args.from_ipa {
+                    LexurgyInput::Romanized
+                } else {
+                    LexurgyInput::Phonetic
+                },
+                if last {
+                    if both {
+                        LexurgyOutput::Both
+                    } else if args.show_phonetic {
+                        LexurgyOutput::Phonetic
+                    } else {
+                        LexurgyOutput::Romanized
+                    }
+                } else {
+                    LexurgyOutput::Phonetic
+                },
+            );
+            if last {
+                cmd.stop_before = args.stop_before.clone();
+            }
+            if first {
+                cmd.start_at = args.start_at.clone();
+            }
+            let (new_tokens, trace) = if args.trace_rules {
+                let (new_tokens, trace) =
+                    cmd.run_traced(&self.cfg, tokens.iter().map(|x| &x[..]))?;
+                (new_tokens, format_trace(&tokens, &trace))
+            } else {
+                (cmd.run(&self.cfg, tokens.iter().map(|x| &x[..]))?, vec![])
+            };
+            tokens.clear();
+            for tok in new_tokens {
+                if both {
+                    let (phon, rom) = tok.get_phon_rom()?;
+                    tokens.push(if args.show_phonetic { &phon } else { &rom }.clone());
+                    evolved.push((rom, phon));
+                } else {
+                    tokens.push(tok.get_value()?);
+                }
+            }
+            stages.push(EvolutionStage {
+                lang: step,
+                words: tokens.clone(),
+                trace,
+            });
+        }
+        Ok(Evolution { stages, evolved })
+    }
+
+    /// Offers to add each of the `(romanization, ipa)` words missing from `lang`, with a
+    /// placeholder meaning and kind to be filled in with `edit` later
+    fn apply_to_lexicon(
+        &mut self,
+        lang: &LangEntry,
+        words: &[(String, String)],
+        prompt: &mut dyn io::BufRead,
+    ) -> Result<()> {
+        for (rom, phon) in words {
+            if !self.homophones(lang, rom)?.is_empty() {
+                continue;
+            }
+            if !confirm(&format!("Add `{}` [{}] to {}?", rom, phon, lang), prompt)? {
+                continue;
+            }
+            self.add_word(
+                AddArgs {
+                    language: lang.id.clone(),
+                    word: Some(rom.clone()),
+                    meaning: Some(PLACEHOLDER_MEANING.to_string()),
+                    kind: Some(PLACEHOLDER_KIND.to_string()),
+                    origin: None,
+                    note: None,
+                    ipa: Some(phon.clone()),
+                    disable_autorom: true,
+                    homophone: false,
+                    force_kind: true,
+                    no_normalize: false,
+                },
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Evolves the phonetic forms `phon` through the languages of `steps` in order. Returns the
+    /// phonetic forms in every language between the source and the last one, along with both
+    /// forms in the last one
+    fn evolve_along<'a>(
+        &self,
+        steps: &[&'a LangEntry],
+        phon: Vec<&str>,
+    ) -> Result<(Vec<Stage<'a>>, Vec<WordOutput>)> {
+        let Some((last, between)) = steps.split_last() else {
+            bail!("Internal error! There are no languages to evolve through");
+        };
+        let count = phon.len();
+        let mut stages: Vec<Stage> = vec![];
+        for step in between {
+            let input = stages.last().map_or(phon.clone(), |(_, forms)| {
+                forms.iter().map(|f| &f[..]).collect()
+            });
+            let forms = LexurgyCmd::evolve(step, LexurgyInput::Phonetic, LexurgyOutput::Phonetic)
+                .run(&self.cfg, input.into_iter())?
+                .into_iter()
+                .map(WordOutput::get_phon)
+                .collect::<Result<Vec<_>>>()?;
+            if forms.len() != count {
+                bail!("Expected {} resulting word, got: {}", count, forms.len());
+            }
+            stages.push((step, forms));
+        }
+        let input = stages
+            .last()
+            .map_or(phon, |(_, forms)| forms.iter().map(|f| &f[..]).collect());
+        let evolved = LexurgyCmd::evolve(last, LexurgyInput::Phonetic, LexurgyOutput::Both)
+            .run(&self.cfg, input.into_iter())?;
+        if evolved.len() != count {
+            bail!("Expected {} resulting word, got: {}", count, evolved.len());
+        }
+        Ok((stages, evolved))
+    }
+
+    fn inherit(&mut self, args: InheritArgs) -> Result<()> {
+        if let Some(kind) = &args.kind {
+            self.cfg.settings.validate_kind(kind, args.force_kind)?;
+        }
+        let langs = self.get_langs()?;
+        validate_family(&langs)?;
+        let dest_lang = &langs[lang_index(&langs, &args.language)?];
+        let Some(parent) = &dest_lang.origin else {
+            bail!("There no parent language to inherit from!");
+        };
+        let src_lang = match &args.from {
+            Some(from) => &langs[lang_index(&langs, from).context("Invalid 'from' language")?],
+            None => &langs[lang_index(&langs, parent)?],
+        };
+        let steps = evolution_steps(&langs, src_lang, dest_lang)?;
+        let mut words: Vec<WordEntry> = Vec::new();
+        if args.word == "*" {
+            let mut stmt = self.db.prepare("SELECT * FROM words WHERE lang = ?")?;
+            words = stmt
+                .query_map(params![&src_lang.id], WordEntry::from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+        } else {
+            if let Some(word) = self.try_get_unique_word(src_lang, &args.word)? {
+                words.push(word);
+            } else {
+                return Ok(());
+            }
+        }
+        if args.regular_only {
+            let (skipped, regular) = words
+                .into_iter()
+                .partition::<Vec<_>, _>(|w| w.flags.contains(NOINHERIT_TAG));
+            if !skipped.is_empty() {
+                let roms = skipped.iter().map(|w| &w.romanization[..]);
+                println!(
+                    "Skipped {} word(s) tagged `{}`: {}",
+                    skipped.len(),
+                    NOINHERIT_TAG,
+                    roms.collect::<Vec<_>>().join(", ")
+                );
+            }
+            words = regular;
+            if words.is_empty() {
+                println!("Nothing left to inherit");
+                return Ok(());
+            }
+        }
+
+        let phon = words
+            .iter()
+            .map(|w| {
+                w.ipa.as_deref().map(normalize_ipa).ok_or(anyhow!(
+                    "The inherited words must have a phonetic annotation"
+                ))
+            })
+            .collect::<Result<Vec<&str>>>()?;
+        println!("Applying sound changes..");
+        let (stages, evolved) = self.evolve_along(&steps, phon)?;
+        let map = match &args.map {
+            Some(path) => load_inherit_map(path)?,
+            None => Default::default(),
+        };
+        for rom in map.keys() {
+            if !words.iter().any(|w| w.romanization == *rom) {
+                println!("WARNING: `{}` from the map isn't being inherited", rom);
+            }
+        }
+        // The map overrides the flags, which override the source word
+        let mut fields = vec![];
+        for word in &words {
+            let o = map.get(&word.romanization);
+            let meaning = o
+                .and_then(|o| o.meaning.as_ref())
+                .or(args.meaning.as_ref())
+                .unwrap_or(&word.meaning);
+            let mapped_kind = o.and_then(|o| o.kind.as_ref());
+            let kind = mapped_kind.or(args.kind.as_ref()).unwrap_or(&word.kind);
+            let settings = &self.cfg.settings;
+            mapped_kind
+                .map_or(Ok(()), |k| settings.validate_kind(k, args.force_kind))
+                .and_then(|_| settings.validate_required(meaning, kind))
+                .with_context(|| format!("Inheriting `{}`", word.romanization))?;
+            let note = o
+                .and_then(|o| o.note.as_ref())
+                .or(args.note.as_ref())
+                .or(word.note.as_ref());
+            fields.push((meaning.clone(), kind.clone(), note.cloned()));
+        }
+        let tr = begin(&mut self.db)?;
+        for (i, ((word, output), (meaning, kind, note))) in
+            words.iter().zip(evolved).zip(fields).enumerate()
+        {
+            let (phon, rom) = output.get_phon_rom()?;
+            let mut intermediate = String::new();
+            if args.show_intermediate {
+                for (step, forms) in &stages {
+                    intermediate.push_str(&format!("{}: {} => ", step.id, forms[i]));
+                }
+            }
+            println!(
+                "  {} ({}) => {}{} ({})",
+                &word.romanization,
+                display_ipa(word.ipa.as_deref()),
+                intermediate,
+                &rom,
+                &phon
+            );
+            tr.execute(
+                "INSERT INTO words
+                (lang, romanization, ipa, meaning, kind, note, origin, flags)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    dest_lang.id,
+                    rom,
+                    phon,
+                    meaning,
+                    kind,
+                    note,
+                    format!(
+                        "(inherited {} {} {})",
+                        src_lang.id, word.romanization, word.id
+                    ),
+                    word.flags
+                ],
+            )?;
+            log_added(&tr, tr.last_insert_rowid() as u32, &rom)?;
+        }
+        tr.commit()?;
+        if self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(args.language))?;
+        }
+        Ok(())
+    }
+
+    /// Evolves the current version of the source of every inherited word of the language again,
+    /// updating their spelling and pronunciation. Everything else about them is left alone
+    fn rebuild(&mut self, args: RebuildArgs) -> Result<()> {
+        let langs = self.get_langs()?;
+        validate_family(&langs)?;
+        let lang = &langs[lang_index(&langs, &args.language)?];
+        if lang.origin.is_none() {
+            bail!("{} has no parent language to inherit from", lang);
+        }
+        let words = {
+            let mut stmt = self.db.prepare(
+                "SELECT * FROM words WHERE lang = ? AND origin LIKE '(inherited %'
+                   ORDER BY romanization, id",
+            )?;
+            let words = stmt
+                .query_map([&lang.id], WordEntry::from_row)?
+                .collect::<Result<Vec<_>, _>>()?;
+            words
+        };
+
+        // The inherited words along with the current pronunciation of their source, by the
+        // language they were inherited from
+        let mut sourced: collections::BTreeMap<&str, Vec<(WordEntry, String)>> =
+            collections::BTreeMap::new();
+        for word in words {
+            let Some((source_lang, source_id)) = word.origin.as_deref().and_then(inherited_source)
+            else {
+                continue;
+            };
+            let Some(source_lang) = langs.iter().find(|l| l.id == source_lang) else {
+                println!(
+                    "WARNING: `{}` was inherited from the unknown language {}, leaving it",
+                    word.romanization, source_lang
+                );
+                continue;
+            };
+            let source = self
+                .db
+                .query_row(
+                    "SELECT * FROM words WHERE id = ?",
+                    [source_id],
+                    WordEntry::from_row,
+                )
+                .optional()?;
+            match source.and_then(|s| s.ipa) {
+                Some(ipa) => sourced
+                    .entry(&source_lang.id)
+                    .or_default()
+                    .push((word, normalize_ipa(&ipa).to_string())),
+                None => println!(
+                    "WARNING: The source of `{}` is gone or has no phonetic annotation, leaving it",
+                    word.romanization
+                ),
+            }
+        }
+        if sourced.is_empty() {
+            println!("{} has no inherited words to rebuild", lang);
+            return Ok(());
+        }
+
+        println!("Applying sound changes..");
+        // Words inherited from earlier ancestors are evolved through every language in between
+        let mut rebuilt = vec![];
+        for (source_id, group) in sourced {
+            let source = &langs[lang_index(&langs, source_id)?];
+            let steps = evolution_steps(&langs, source, lang)?;
+            let phon = group.iter().map(|(_, ipa)| &ipa[..]).collect();
+            let (_, evolved) = self.evolve_along(&steps, phon)?;
+            rebuilt.extend(group.into_iter().map(|(word, _)| word).zip(evolved));
+        }
+        let total = rebuilt.len();
+        let tr = begin(&mut self.db)?;
+        let mut changed = 0;
+        for (word, output) in rebuilt {
+            let (phon, rom) = output.get_phon_rom()?;
+            let same_ipa = word.ipa.as_deref().map(normalize_ipa) == Some(&phon[..]);
+            if word.romanization == rom && same_ipa {
+                continue;
+            }
+            tr.execute(
+                "UPDATE words SET romanization = ?, ipa = ? WHERE id = ?",
+                params![rom, phon, word.id],
+            )?;
+            if word.romanization != rom {
+                log_change(
+                    &tr,
+                    word.id,
+                    "romanization",
+                    Some(&word.romanization),
+                    Some(&rom),
+                )?;
+            }
+            if !same_ipa {
+                log_change(&tr, word.id, "ipa", word.ipa.as_deref(), Some(&phon))?;
+            }
+            println!(
+                "  {} ({}) => {} ({})",
+                word.romanization,
+                display_ipa(word.ipa.as_deref()),
+                rom,
+                phon
+            );
+            changed += 1;
+        }
+        tr.commit()?;
+        println!(
+            "Rebuilt {} words, {} were unchanged",
+            changed,
+            total - changed
+        );
+        if changed > 0 && self.cfg.settings.auto_dump {
+            self.dump(DumpArgs::new(lang.id.clone()))?;
+        }
+        Ok(())
+    }
+
+    fn random(&mut self, args: RandomArgs) -> Result<()> {
+        let lang = self.resolve_lang(&args.language)?;
+        let ast = sc::include::load_lsc(&self.cfg.rule_file(&lang))?;
+        let gen = sc::generate::WordGenerator::from_ast(&ast)
+            .with_context(|| format!("Generating words for {}", lang))?;
+        let seed = args.seed.unwrap_or_else(Rng::time_seed);
+        println!("Seed: {}", seed);
+        let words = self.random_words(&lang, &gen, args.count, &mut Rng::from_seed(seed))?;
+        if words.is_empty() {
+            println!("Couldn't generate any new words");
+            return Ok(());
+        }
+
+        let phons = LexurgyCmd::deromanize(&lang).run(&self.cfg, words.iter().map(|w| &w[..]))?;
+        if phons.len() != words.len() {
+            bail!(
+                "Expected {} deromanized words back, got {}",
+                words.len(),
+                phons.len()
+            );
+        }
+        for (word, phon) in words.iter().zip(phons) {
+            println!(" {} [{}]", word, phon.get_phon()?);
+        }
+        Ok(())
+    }
+
+    /// Generates up to `count` distinct words that aren't in the lexicon yet
+    fn random_words(
+        &self,
+        lang: &LangEntry,
+        gen: &sc::generate::WordGenerator,
+        count: usize,
+        rng: &mut Rng,
+    ) -> Result<Vec<String>> {
+        const MAX_SYLLABLES: usize = 3;
+        // Small inventories run out of new words quickly, so don't keep trying forever
+        const ATTEMPTS_PER_WORD: usize = 20;
+        let mut words: Vec<String> = vec![];
+        for _ in 0..count * ATTEMPTS_PER_WORD {
+            if words.len() == count {
+                break;
+            }
+            let syllables = 1 + rng.below(MAX_SYLLABLES);
+            let word = gen.generate(syllables, &mut |n| rng.below(n));
+            if word.is_empty() || words.contains(&word) || !self.homophones(lang, &word)?.is_empty()
+            {
+                continue;
+            }
+            words.push(word);
+        }
+        Ok(words)
+    }
+
+    fn check_missing_ipa(&mut self) -> Result<()> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT * FROM words WHERE ipa IS NULL ORDER BY lang DESC, romanization")?;
+        let words = stmt
+            .query_map([], WordEntry::from_row)?
+            .collect::<rusqlite::Result<Vec<WordEntry>>>()?;
+
+        if words.is_empty() {
+            return Ok(());
+        }
+
+        println!("\nNOTE: The following words are missing phonetic annotation:");
+        for lang_words in words.chunk_by(|w0, w1| w0.lang == w1.lang) {
+            println!("{}", self.get_lang(&lang_words[0].lang)?);
+            for word in lang_words {
+                println!("- {}: {} ({})", word.romanization, word.meaning, word.kind);
+            }
+        }
+        println!("\nRun `wdb phon` to generate phonetic annotations based on their romanization.");
+        Ok(())
+    }
+
+    /// Picks the words to deromanize, grouped by language
+    fn phon_batches(&self, args: &PhonArgs) -> Result<Vec<(LangEntry, Vec<WordEntry>)>> {
+        if !args.only.is_empty() {
+            let lang = self
+                .resolve_lang(args.language.as_ref().unwrap())?
+                .with_rule(args.rule.as_deref());
+            let mut words: Vec<WordEntry> = vec![];
+            for rom in &args.only {
+                if let Some(word) = self.try_get_unique_word(&lang, &normalize_text(rom))? {
+                    if !words.iter().any(|w| w.id == word.id) {
+                        words.push(word);
+                    }
+                }
+            }
+            return Ok(vec![(lang, words)]);
+        }
+
+        let languages = args
+            .language
+            .as_ref()
+            .map(|l| {
+                self.resolve_lang(l)
+                    .map(|x| vec![x.with_rule(args.rule.as_deref())])
+            })
+            .unwrap_or_else(|| self.get_langs())?;
+        let mut batches = vec![];
+        for lang in languages {
+            let words: Vec<WordEntry> = {
+                let mut stmt = self.db.prepare(&format!(
+                    "SELECT * FROM words WHERE lang = ? {}",
+                    if args.force { "" } else { "AND ipa IS NULL" }
+                ))?;
+                let ws = stmt
+                    .query_map([&lang.id], WordEntry::from_row)?
+                    .collect::<Result<_, _>>()?;
+                ws
+            };
+
+            if !words.is_empty() {
+                batches.push((lang, words));
+            }
+        }
+        Ok(batches)
+    }
+
+    /// With `--sample`, only the first results are shown and `prompt` is asked before writing
+    fn deromanize(&mut self, args: PhonArgs, prompt: &mut dyn io::BufRead) -> Result<()> {
+        let batches = self.phon_batches(&args)?;
+        if batches.is_empty() {
+            println!("No word updated, every word present has a phonetic annotation.\nIf you want to update all anyway, use the -f flag.");
+            return Ok(());
+        }
+
+        for (lang, _) in &batches {
+            println!("Running `{}` deromanization rule...", &lang.rule);
+        }
+        // Every language gets its own word list files, so the Lexurgy processes can't interfere
+        // with each other
+        let jobs = args.jobs.unwrap_or_else(default_jobs).max(1);
+        let cfg = &self.cfg;
+        let results = run_parallel(jobs, &batches, |(lang, words)| {
+            LexurgyCmd::deromanize(lang)
+                .run(cfg, words.iter().map(|w| &w.romanization[..]))?
+                .into_iter()
+                .map(|o| o.get_phon())
+                .collect::<Result<Vec<_>>>()
+        });
+
+        let mut updates = vec![];
+        let mut shown = 0;
+        for ((lang, words), phons) in batches.iter().zip(results) {
+            let phons = phons.with_context(|| format!("Deromanizing {}", lang))?;
+            if phons.len() != words.len() {
+                println!(
+                    "Number of words out ({}) doesn't match number of words in({})!'",
+                    phons.len(),
+                    words.len()
+                );
+            }
+            for (word, phon) in words.iter().zip(phons.iter()) {
+                if args.sample.is_some_and(|n| shown >= n) {
+                    break;
+                }
+                println!(" {} => {}", &word.romanization, phon);
+                shown += 1;
+            }
+            updates.push(phons);
+        }
+
+        if args.sample.is_some() {
+            let total = updates.iter().map(Vec::len).sum::<usize>();
+            if total > shown {
+                println!(" ... and {} more", total - shown);
+            }
+            let question = format!("Write the phonetic annotation of {} word(s)?", total);
+            if !args.yes && !confirm(&question, prompt)? {
+                println!("No words changed.");
+                return Ok(());
+            }
+        }
+
+        let mut write_phons = || {
+            let tr = begin(&mut self.db)?;
+            for ((_, words), phons) in batches.iter().zip(updates.iter()) {
+                for (word, phon) in words.iter().zip(phons.iter()) {
+                    tr.execute(
+                        "UPDATE words SET ipa = ? WHERE id = ?",
+                        params![phon, word.id],
+                    )?;
+                }
+            }
+            Ok::<_, anyhow::Error>(tr.commit()?)
+        };
+
+        if let Err(err) = write_phons() {
+            bail!(
+                "failed to update phonetic annotation. No words changed.\n{}",
+                err
+            );
+        }
+
+        for (lang, words) in batches {
+            println!("Updated {} word entries in {}", words.len(), lang);
+            if self.cfg.settings.auto_dump {
+                self.dump(DumpArgs::new(lang.id))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A small xorshift generator, random words don't need anything better
+struct Rng(u64);
+
+impl Rng {
+    /// A seed that differs from run to run
+    fn time_seed() -> u64 {
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    }
+
+    /// Scrambles the seed first, so similar seeds don't start out with similar numbers
+    fn from_seed(seed: u64) -> Rng {
+        // splitmix64's finalizer
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        // The state must never be zero
+        Rng(z | 1)
+    }
+
+    /// Returns a random number below `n`
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Calls `f` on every item using up to `jobs` threads, returning the results in the same order as
+/// the items
+fn run_parallel<T: Sync, R: Send>(jobs: usize, items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let next = AtomicUsize::new(0);
+    let results = sync::Mutex::new(items.iter().map(|_| None).collect::<Vec<_>>());
+    thread::scope(|s| {
+        for _ in 0..jobs.min(items.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                let r = f(item);
+                results.lock().unwrap()[i] = Some(r);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every item is processed"))
+        .collect()
+}
+
+fn find_obsidian_root() -> Result<PathBuf> {
+    let cur = env::current_dir()?;
+    let mut obsidian = PathBuf::new();
+    for root in cur.ancestors() {
+        obsidian.clear();
+        obsidian.push(root);
+        obsidian.push(".obsidian");
+        if obsidian.exists() {
+            return Ok(root.into());
+        }
+    }
+    bail!("you must run this command from inside of an Obsidian vault!");
+}
+
+fn load_settings(root: &path::Path) -> Result<Settings> {
+    let text =
+        fs::read_to_string(root.join("Wdb.toml")).context("No `Wdb.toml` settings file present")?;
+    parse_settings(&text).context("Invalid `Wdb.toml`")
+}
+
+/// Parses `Wdb.toml`, pointing out a missing `db_file` instead of a generic missing field error
+fn parse_settings(text: &str) -> Result<Settings> {
+    let table: toml::Table = toml::from_str(text)?;
+    if !table.contains_key("db_file") {
+        bail!("The required `db_file` setting, the database file to use, is missing");
+    }
+    Ok(toml::Value::Table(table).try_into()?)
+}
+
+/// The file in the vault the working language set by `use` is kept in
+const WORKING_LANG_FILE: &str = ".wdb-lang";
+/// Overrides the working language set by `use`
+const WORKING_LANG_VAR: &str = "WDB_LANG";
+
+/// The working language, from `WDB_LANG` or else the last `use`
+fn working_lang(root: &Path) -> Option<String> {
+    env::var(WORKING_LANG_VAR)
+        .ok()
+        .or_else(|| fs::read_to_string(root.join(WORKING_LANG_FILE)).ok())
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+}
+
+/// Whether the option `arg` of `cmd` is followed by its value as a separate argument
+fn takes_separate_value(cmd: &clap::Command, arg: &str) -> bool {
+    let option = if let Some(long) = arg.strip_prefix("--") {
+        cmd.get_arguments().find(|a| {
+            a.get_long() == Some(long) || a.get_all_aliases().is_some_and(|al| al.contains(&long))
+        })
+    } else {
+        // A cluster like `-Hf` or `-ofoo` never has its value in the next argument
+        let mut shorts = arg[1..].chars();
+        match (shorts.next(), shorts.next()) {
+            (Some(short), None) => cmd.get_arguments().find(|a| a.get_short() == Some(short)),
+            _ => None,
+        }
+    };
+    !arg.contains('=') && option.is_some_and(|a| a.get_action().takes_values())
+}
+
+/// Where the language argument of the subcommand is, or would be, on the command line. `None` if
+/// the subcommand doesn't start with a language
+fn language_position(args: &[String]) -> Option<usize> {
+    use clap::CommandFactory;
+    let mut cli = Cli::command();
+    cli.build();
+    let mut i = 1;
+    let sub = loop {
+        let arg = args.get(i)?;
+        i += 1;
+        if let Some(sub) = cli.find_subcommand(arg) {
+            break sub;
+        }
+        i += takes_separate_value(&cli, arg) as usize;
+    };
+    if sub.get_name() == "use" || sub.get_positionals().next()?.get_id() != "language" {
+        return None;
+    }
+    while let Some(arg) = args.get(i) {
+        if arg == "--" {
+            return Some(i + 1);
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            return Some(i);
+        }
+        i += 1 + takes_separate_value(sub, arg) as usize;
+    }
+    Some(i)
+}
+
+/// Fills in `lang` as the language of the command line when it's left out, which is when the
+/// first positional argument isn't a language's id or the start of one, as `resolve_lang` accepts
+/// those too. Returns whether it did
+fn insert_working_lang(args: &mut Vec<String>, lang: &str, langs: &[LangEntry]) -> bool {
+    let Some(i) = language_position(args) else {
+        return false;
+    };
+    if args
+        .get(i)
+        .is_some_and(|a| langs.iter().any(|l| l.id.starts_with(&a[..])))
+    {
+        return false;
+    }
+    args.insert(i.min(args.len()), lang.to_string());
+    true
+}
+
+/// Opens the database of the vault, `db` overriding the configured one
+fn open_wdb(debug_mode: bool, db: Option<PathBuf>) -> Result<Wdb> {
+    let root = find_obsidian_root()?;
+    let settings = load_settings(&root)?;
+    let mut cfg = Config::new(root, settings, debug_mode | cfg!(debug_assertions));
+    cfg.db_file = db;
+    Wdb::new(cfg)
+}
+
+/// The command line with the working language filled in, see `insert_working_lang`, along with
+/// the database if it had to be opened for that, for `run` to reuse. The database is opened with
+/// just the global options, as the rest may not parse without the language. Anything going wrong
+/// leaves the command line as it is, for `run` to report
+fn with_working_lang(mut args: Vec<String>) -> (Vec<String>, Option<Wdb>) {
+    use clap::CommandFactory;
+    let Some(lang) = find_obsidian_root()
+        .ok()
+        .and_then(|root| working_lang(&root))
+    else {
+        return (args, None);
+    };
+    let Ok(globals) = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    else {
+        return (args, None);
+    };
+    let db = globals.get_one::<PathBuf>("db").cloned();
+    let Ok(wdb) = open_wdb(globals.get_flag("debug_mode"), db) else {
+        return (args, None);
+    };
+    if let Ok(langs) = wdb.get_langs() {
+        if insert_working_lang(&mut args, &lang, &langs) {
+            eprintln!("NOTE: Using the working language `{}`\n", lang);
+        }
+    }
+    (args, Some(wdb))
+}
+
+/// How a failure is reported with `--json`
+#[derive(Serialize)]
+struct ErrorReport {
+    error: String,
+    /// The errors underlying `error`, outermost first
+    causes: Vec<String>,
+}
+
+impl ErrorReport {
+    fn new(err: &anyhow::Error) -> ErrorReport {
+        ErrorReport {
+            error: err.to_string(),
+            causes: err.chain().skip(1).map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+fn main() {
+    let (args, wdb) = with_working_lang(env::args().collect());
+    let cli = Cli::parse_from(args);
+    let json = cli.json;
+    if let Err(err) = run(cli, wdb) {
+        if json {
+            eprintln!(
+                "{}",
+                serde_json::to_string(&ErrorReport::new(&err))
+                    .expect("Error reports can always be serialized")
+            );
+        } else {
+            eprintln!("Error: {:?}", err);
+        }
+        process::exit(1);
+    }
+}
+
+/// Writes the completion script for `shell` to `out`
+fn write_completions(shell: clap_complete::Shell, out: &mut dyn io::Write) {
+    use clap::CommandFactory;
+    clap_complete::generate(shell, &mut Cli::command(), "wdb", out);
+}
+
+/// `wdb` is the database if `with_working_lang` already opened it
+fn run(mut cli: Cli, wdb: Option<Wdb>) -> Result<()> {
+    // Completion scripts don't depend on the vault, so they can be generated anywhere
+    if let Some(Command::Completions(args)) = &cli.command {
+        write_completions(args.shell, &mut io::stdout());
+        return Ok(());
+    }
+    let mut wdb = match wdb {
+        Some(wdb) => wdb,
+        None => open_wdb(cli.debug_mode, cli.db.take())?,
+    };
+    wdb.cfg.keep_temps |= cli.keep_temps;
+    wdb.cfg.native |= cli.native;
+    wdb.cfg.ignore_case |= cli.ignore_case;
+    if cli.complete_langs {
+        for lang in wdb.get_langs()? {
+            println!("{}", lang.id);
+        }
+        return Ok(());
+    }
+    let cfg = &wdb.cfg;
+    if let Some(db_file) = &cfg.db_file {
+        eprintln!("NOTE: Using the database `{}`\n", db_file.display());
+    } else if cfg.debug_mode {
+        eprintln!(
+            "NOTE: Running in debug, changes are done to the `{}` instead of `{}`\n",
+            cfg.settings.dev_db_file(),
+            &cfg.settings.db_file,
+        );
+    }
+
+    let mut cmd = cli.command;
+    let interactive = matches!(cmd, Some(Command::Interactive));
+    let ignore_case = wdb.cfg.ignore_case;
+    let mut buf = String::new();
+
+    loop {
+        wdb.forget_langs();
+        match cmd {
+            Some(Command::Dump(args)) => wdb.dump(args)?,
+            Some(Command::Watch(args)) => wdb.watch(args)?,
+            Some(Command::Export(args)) => wdb.export(args)?,
+            Some(Command::ExportAll(args)) => wdb.export_all(args)?,
+            Some(Command::List(args)) => wdb.list(args)?,
+            Some(Command::Tree) => wdb.tree()?,
+            Some(Command::FamilyDot(args)) => wdb.family_dot_cmd(args)?,
+            Some(Command::Check) => wdb.check()?,
+            Some(Command::Validate) => wdb.validate()?,
+            Some(Command::LangInfo(args)) => print!("{}", wdb.lang_info(&args)?),
+            Some(Command::Phonology(args)) => wdb.phonology(args)?,
+            Some(Command::Freq(args)) => wdb.freq(args)?,
+            Some(Command::Sc(args)) => wdb.sc(args)?,
+            Some(Command::Pron(args)) => wdb.pron(args)?,
+            Some(Command::Search(args)) => wdb.search(args)?,
+            Some(Command::Add(args)) => wdb.add(args)?,
+            Some(Command::Edit(args)) => wdb.edit(args)?,
+            Some(Command::Swap(args)) => wdb.swap(args)?,
+            Some(Command::Import(args)) => wdb.import(args, &mut io::stdin().lock())?,
+            Some(Command::Del(args)) => wdb.del(args)?,
+            Some(Command::Dedupe(args)) => wdb.dedupe(args)?,
+            Some(Command::Prune(args)) => wdb.prune(args)?,
+            Some(Command::Use(args)) => wdb.use_lang(args)?,
+            Some(Command::History(args)) => wdb.history(args)?,
+            Some(Command::Evolve(args)) => wdb.evolve(args, &mut io::stdout().lock())?,
+            Some(Command::Inherit(args)) => wdb.inherit(args)?,
+            Some(Command::Rebuild(args)) => wdb.rebuild(args)?,
+            Some(Command::Ipa(args)) => wdb.ipa(args)?,
+            Some(Command::Example(args)) => wdb.example(args)?,
+            Some(Command::Random(args)) => wdb.random(args)?,
+            Some(Command::Suggest(args)) => wdb.suggest_cmd(args)?,
+            Some(Command::Next(args)) => wdb.neighbour_cmd(args, true)?,
+            Some(Command::Prev(args)) => wdb.neighbour_cmd(args, false)?,
+            Some(Command::Phon(args)) => {
+                cli.disable_checks = args.language.is_none();
+                wdb.deromanize(args, &mut io::stdin().lock())?
+            }
+            _ => {}
+        }
+        if !interactive {
+            break;
+        }
+        loop {
+            std::io::stdin().read_line(&mut buf)?;
+            let mut args = buf.split(' ').map(str::to_string).collect::<Vec<_>>();
+            if let Some(lang) = working_lang(&wdb.cfg.root) {
+                if insert_working_lang(&mut args, &lang, &wdb.get_langs()?) {
+                    eprintln!("NOTE: Using the working language `{}`", lang);
+                }
+            }
+            match Cli::try_parse_from(args) {
+                Err(err) => {
+                    println!("Failed to parse command: {:?}", err)
+                }
+                Ok(Cli {
+                    command: c,
+                    ignore_case: line_ignore_case,
+                    ..
+                }) => {
+                    cmd = c;
+                    wdb.cfg.ignore_case = ignore_case || line_ignore_case;
+                    break;
+                }
+            }
+        }
+    }
+
+    if !cli.disable_checks {
+        wdb.check_missing_ipa()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = "
+        CREATE TABLE langs (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            origin TEXT,
+            rule TEXT NOT NULL
+        );
+        CREATE TABLE words (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            lang TEXT NOT NULL,
+            romanization TEXT NOT NULL,
+            ipa TEXT,
+            meaning TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            origin TEXT,
+            flags TEXT,
+            note TEXT
+        );
+    ";
+
+    const TEST_SETTINGS: &str = r#"
+        db_file = "wdb.db"
+        db_dev_file = "wdb_dev.db"
+        word_list_folder = "words"
+        rule_list_folder = "rules"
+        dictionary_file_template = "{lang.name}.md"
+        dictionary_template = "{{ for w in words }}{w.romanization}\n{{ endfor }}"
+        auto_dump = false
+    "#;
+
+    fn test_settings() -> Settings {
+        toml::from_str(TEST_SETTINGS).unwrap()
+    }
+
+    /// A fresh directory for a test, removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = env::temp_dir().join(format!("wdb-test-{}-{}", name, process::id()));
+            // Whatever an earlier run that didn't get to clean up left behind
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn test_wdb() -> Wdb {
+        let cfg = Config::new(env::temp_dir(), test_settings(), false);
+        let wdb = Wdb::from_connection(Connection::open_in_memory().unwrap(), cfg).unwrap();
+        wdb.db.execute_batch(SCHEMA).unwrap();
+        wdb
+    }
+
+    fn add_lang(wdb: &Wdb, id: &str, origin: Option<&str>) {
+        wdb.db
+            .execute(
+                "INSERT INTO langs (id, name, origin, rule) VALUES (?, ?, ?, ?)",
+                params![id, id.to_uppercase(), origin, id],
+            )
+            .unwrap();
+    }
+
+    fn add_word(wdb: &Wdb, lang: &str, rom: &str, meaning: &str, kind: &str) -> u32 {
+        wdb.db
+            .execute(
+                "INSERT INTO words
+                   (lang, romanization, ipa, meaning, kind, note, origin, flags)
+                   VALUES (?, ?, ?, ?, ?, '', '', '')",
+                params![lang, rom, rom, meaning, kind],
+            )
+            .unwrap();
+        wdb.db.last_insert_rowid() as u32
+    }
+
+    #[test]
+    fn test_get_lang_cache() {
+        use rusqlite::functions::FunctionFlags;
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        // Count the language rows read by shadowing `langs` with a view calling `counted`
+        let reads = Arc::new(AtomicU32::new(0));
+        let counter = reads.clone();
+        wdb.db
+            .create_scalar_function("counted", 0, FunctionFlags::SQLITE_UTF8, move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(true)
+            })
+            .unwrap();
+        wdb.db
+            .execute_batch("CREATE TEMP VIEW langs AS SELECT * FROM main.langs WHERE counted()")
+            .unwrap();
+
+        assert_eq!(wdb.get_lang("mid").unwrap().origin.as_deref(), Some("pro"));
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+        assert_eq!(wdb.get_lang("mid").unwrap().name, "MID");
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+        wdb.get_lang("pro").unwrap();
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        wdb.db
+            .execute("UPDATE main.langs SET name = 'Middle' WHERE id = 'mid'", [])
+            .unwrap();
+        wdb.forget_langs();
+        assert_eq!(wdb.get_lang("mid").unwrap().name, "Middle");
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_completions() {
+        let cli = Cli::try_parse_from(["wdb", "completions", "fish"]).unwrap();
+        let Some(Command::Completions(args)) = cli.command else {
+            panic!("Expected a completions command");
+        };
+        let mut script = vec![];
+        write_completions(args.shell, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("complete -c wdb"), "{}", script);
+        assert!(script.contains("inherit"), "{}", script);
+
+        assert!(Cli::try_parse_from(["wdb", "completions", "tcsh"]).is_err());
+        assert!(
+            Cli::try_parse_from(["wdb", "--complete-langs"])
+                .unwrap()
+                .complete_langs
+        );
+    }
+
+    fn search_args(query: &str) -> SearchArgs {
+        SearchArgs {
+            query: Some(query.to_string()),
+            language: None,
+            kind: None,
+            tag: vec![],
+            in_meaning: false,
+            regex: false,
+            exact: false,
+            prefix: false,
+            context: 0,
+        }
+    }
+
+    fn romanizations(words: &[WordEntry]) -> Vec<&str> {
+        words.iter().map(|w| &w.romanization[..]).collect()
+    }
+
+    #[test]
+    fn test_search_regex() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+        add_word(&wdb, "pro", "anak", "child", "n");
+        add_word(&wdb, "pro", "kala", "fish", "n");
+        add_word(&wdb, "pro", "aka", "red", "adj");
+
+        let mut args = search_args("^a.*a$");
+        args.regex = true;
+        let words = wdb.search_words(&args).unwrap();
+        assert_eq!(romanizations(&words), vec!["aka", "ana"]);
+
+        args.query = Some("a[".to_string());
+        assert!(wdb.search_words(&args).is_err());
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "dau", Some("pro"));
+        let ka = add_word(&wdb, "pro", "ka", "fish", "n");
+        add_word(&wdb, "pro", "kat", "to fish", "v");
+        let dau_ka = add_word(&wdb, "dau", "ka", "fish", "n");
+        let kana = add_word(&wdb, "dau", "kana", "fishes", "n");
+        for (id, flags) in [
+            (ka, "archaic"),
+            (dau_ka, "archaic, poetic"),
+            (kana, "poetic"),
+        ] {
+            wdb.db
+                .execute(
+                    "UPDATE words SET flags = ? WHERE id = ?",
+                    params![flags, id],
+                )
+                .unwrap();
+        }
+        let search = |args: &SearchArgs| {
+            wdb.search_words(args)
+                .unwrap()
+                .iter()
+                .map(|w| format!("{}:{}", w.lang, w.romanization))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let mut args = search_args("ka");
+        args.kind = Some("n".to_string());
+        assert_eq!(search(&args), "dau:ka dau:kana pro:ka");
+        args.tag = vec!["archaic".to_string()];
+        assert_eq!(search(&args), "dau:ka pro:ka");
+        args.tag.push("poetic".to_string());
+        assert_eq!(search(&args), "dau:ka");
+        args.language = Some("pro".to_string());
+        assert_eq!(search(&args), "");
+
+        // Filters alone without a query
+        let mut args = search_args("");
+        args.query = None;
+        args.tag = vec!["poetic".to_string()];
+        assert_eq!(search(&args), "dau:ka dau:kana");
+        args.tag = vec![];
+        args.kind = Some("v".to_string());
+        assert_eq!(search(&args), "pro:kat");
+        args.kind = None;
+        assert_eq!(search(&args).split(' ').count(), 4);
+    }
+
+    #[test]
+    fn test_search_context() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "dau", Some("pro"));
+        for rom in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            add_word(&wdb, "pro", rom, rom, "n");
+        }
+        add_word(&wdb, "dau", "c", "c", "n");
+        let context = |query: &str, n| {
+            let mut args = search_args(query);
+            args.regex = true;
+            let words = wdb.search_words(&args).unwrap();
+            wdb.with_context(words, n)
+                .unwrap()
+                .into_iter()
+                .map(|run| {
+                    run.iter()
+                        .map(|(w, hit)| {
+                            format!("{}{}", w.romanization, if *hit { "*" } else { "" })
+                        })
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Overlapping windows are merged, each language gets its own
+        assert_eq!(context("^[ce]$", 1), ["c*", "b c* d e* f"]);
+        assert_eq!(context("^[ah]$", 1), ["a* b", "g h*"]);
+        assert_eq!(context("^[ad]$", 2), ["a* b c d* e f"]);
+        assert_eq!(context("^b$", 0), ["b*"]);
+    }
+
+    #[test]
+    fn test_search_modes() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "dau", Some("pro"));
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        add_word(&wdb, "pro", "kala", "fisherman", "n");
+        add_word(&wdb, "pro", "aka", "red", "adj");
+        add_word(&wdb, "dau", "ka", "fish", "n");
+
+        let search = |args: &SearchArgs| romanizations(&wdb.search_words(args).unwrap()).join(" ");
+
+        let mut args = search_args("ka");
+        assert_eq!(search(&args), "ka aka ka kala");
+
+        args.exact = true;
+        assert_eq!(search(&args), "ka ka");
+        args.language = Some("pro".to_string());
+        assert_eq!(search(&args), "ka");
+
+        args.exact = false;
+        args.prefix = true;
+        assert_eq!(search(&args), "ka kala");
+
+        let mut args = search_args("fish");
+        args.in_meaning = true;
+        args.exact = true;
+        assert_eq!(search(&args), "ka ka");
+        args.exact = false;
+        args.prefix = true;
+        assert_eq!(search(&args), "ka ka kala");
+
+        // Wildcards in the query match only themselves
+        add_word(&wdb, "pro", "k_a", "50% off", "n");
+        assert_eq!(search(&search_args("_")), "k_a");
+        let mut args = search_args("50%");
+        args.in_meaning = true;
+        assert_eq!(search(&args), "k_a");
+        args.query = Some("%".to_string());
+        args.prefix = true;
+        assert_eq!(search(&args), "");
+    }
+
+    fn add_args(lang: &str) -> AddArgs {
+        AddArgs {
+            language: lang.to_string(),
+            word: None,
+            meaning: None,
+            kind: None,
+            origin: None,
+            note: None,
+            ipa: None,
+            disable_autorom: true,
+            homophone: false,
+            force_kind: false,
+            no_normalize: false,
+        }
+    }
+
+    fn edit_args(lang: &str, word: &str) -> EditArgs {
+        EditArgs {
+            language: lang.to_string(),
+            word: word.to_string(),
+            meaning: None,
+            kind: None,
+            origin: None,
+            origin_clear: false,
+            origin_note: None,
+            force: false,
+            note: None,
+            ipa: None,
+            rename: None,
+            homophone: false,
+            disable_autorom: true,
+            force_kind: false,
+            no_normalize: false,
+        }
+    }
+
+    fn inherit_args(lang: &str, word: &str) -> InheritArgs {
+        InheritArgs {
+            language: lang.to_string(),
+            word: word.to_string(),
+            meaning: None,
+            kind: None,
+            note: None,
+            force_kind: false,
+            map: None,
+            from: None,
+            show_intermediate: false,
+            regular_only: false,
+        }
+    }
+
+    fn evolve_args(from: &str, to: &str, word: &str) -> EvolveArgs {
+        EvolveArgs {
+            from_lang: from.to_string(),
+            to_lang: to.to_string(),
+            sentence: vec![word.to_string()],
+            stop_before: None,
+            start_at: None,
+            show_phonetic: false,
+            show_intermediate: false,
+            check_lexicon: false,
+            apply_to_lexicon: false,
+            trace_rules: false,
+            compare: false,
+            from_ipa: false,
+            limit: EVOLVE_TOKEN_LIMIT,
+            yes: false,
+            rule: None,
+            format: EvolveFormat::Text,
+        }
+    }
+
+    #[test]
+    fn test_add_batch() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+
+        let input = "ka\tfish\tn\nana\triver\tn\n\nbo\tgo\tv\nka\tcatch\tv\n";
+        let words = parse_word_lines(input.as_bytes()).unwrap();
+        assert_eq!(words.len(), 4);
+        assert_eq!(
+            words[2],
+            NewWord {
+                romanization: "bo".to_string(),
+                meaning: "go".to_string(),
+                kind: "v".to_string(),
+                ipa: None,
+            }
+        );
+
+        // Both conflicts get reported, but the rest is still added
+        let err = wdb
+            .add_batch(add_args("pro"), words)
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("2 word(s) were not added"), "{}", err);
+        assert!(err.contains(" - 0: ana: water, n"), "{}", err);
+        assert!(err.contains("`ka` appears more than once"), "{}", err);
+        let mut args = search_args("");
+        args.language = Some("pro".to_string());
+        let words = wdb.search_words(&args).unwrap();
+        assert_eq!(romanizations(&words), vec!["ana", "bo", "ka"]);
+        let added = wdb.word_history(words[1].id).unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(
+            (
+                &added[0].field[..],
+                added[0].old.as_deref(),
+                added[0].new.as_deref()
+            ),
+            ("romanization", None, Some("bo"))
+        );
+
+        assert!(parse_word_lines("ka fish n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_add_homophone_prompt() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+        let args = || AddArgs {
+            word: Some("ana".to_string()),
+            meaning: Some("river".to_string()),
+            kind: Some("n".to_string()),
+            ..add_args("pro")
+        };
+        let count = |wdb: &Wdb| {
+            wdb.homophones(&wdb.get_lang("pro").unwrap(), "ana")
+                .unwrap()
+                .len()
+        };
+
+        // Without anyone to ask, the word is rejected with instructions
+        let err = wdb.add_word(args(), None).unwrap_err().to_string();
+        assert!(err.contains("use the -H flag"), "{}", err);
+        assert_eq!(count(&wdb), 1);
+
+        let err = wdb
+            .add_word(args(), Some(&mut "n\n".as_bytes()))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Not adding `ana`");
+        assert_eq!(count(&wdb), 1);
+
+        wdb.add_word(args(), Some(&mut "y\n".as_bytes())).unwrap();
+        assert_eq!(count(&wdb), 2);
+    }
+
+    #[test]
+    fn test_add_no_normalize() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+        let args = |no_normalize| AddArgs {
+            word: Some(" ana ".to_string()),
+            meaning: Some("river".to_string()),
+            kind: Some("n".to_string()),
+            no_normalize,
+            ..add_args("pro")
+        };
+        let lang = wdb.get_lang("pro").unwrap();
+
+        // Trimmed, it's a homophone of `ana`
+        assert!(wdb.add_word(args(false), None).is_err());
+        // Verbatim, it's a word of its own
+        wdb.add_word(args(true), None).unwrap();
+        let word = wdb.homophones(&lang, " ana ").unwrap().remove(0);
+        assert_eq!(word.meaning, "river");
+        assert!(wdb.add_word(args(true), None).is_err());
+
+        wdb.edit(EditArgs {
+            rename: Some("  ana".to_string()),
+            no_normalize: true,
+            ..edit_args("pro", " ana ")
+        })
+        .unwrap();
+        let renamed = wdb.homophones(&lang, "  ana").unwrap();
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[0].id, word.id);
+        assert_eq!(wdb.homophones(&lang, "ana").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_ipa_variants() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let id = add_word(&wdb, "pro", "ana", "water", "n");
+        let add = |label: &str, ipa: &str| IpaArgs {
+            command: IpaCommand::Add {
+                language: "pro".to_string(),
+                word: "ana".to_string(),
+                label: label.to_string(),
+                ipa: ipa.to_string(),
+            },
+        };
+        wdb.ipa(add("colloquial", "an")).unwrap();
+        wdb.ipa(add("northern", "ane")).unwrap();
+        wdb.ipa(add("colloquial", "[ã]")).unwrap();
+
+        let export = wdb.lang_export(wdb.get_lang("pro").unwrap()).unwrap();
+        assert_eq!(export.words[0].ipa.as_deref(), Some("ana"));
+        assert_eq!(
+            export.words[0].ipa_variants,
+            vec![
+                IpaVariant {
+                    label: "colloquial".to_string(),
+                    ipa: "ã".to_string(),
+                },
+                IpaVariant {
+                    label: "northern".to_string(),
+                    ipa: "ane".to_string(),
+                },
+            ]
+        );
+
+        let rm = |label: &str| IpaArgs {
+            command: IpaCommand::Rm {
+                language: "pro".to_string(),
+                word: "ana".to_string(),
+                label: label.to_string(),
+            },
+        };
+        wdb.ipa(rm("northern")).unwrap();
+        assert!(wdb.ipa(rm("northern")).is_err());
+        assert_eq!(wdb.ipa_variants(id).unwrap().len(), 1);
+        assert_eq!(display_ipa(Some("[ana]")), "ana");
+        assert_eq!(display_ipa(None), "?");
+    }
+
+    #[test]
+    fn test_list_counts() {
+        let wdb = test_wdb();
+        for (lang, words) in [("pro", 3), ("mid", 0), ("new", 1), ("old", 5)] {
+            add_lang(&wdb, lang, None);
+            for i in 0..words {
+                add_word(&wdb, lang, &format!("ka{}", i), "fish", "n");
+            }
+        }
+        let counts = |min_count, max_count| {
+            let args = ListArgs {
+                sample: false,
+                min_count,
+                max_count,
+            };
+            let langs = wdb.counted_langs(&args).unwrap();
+            langs
+                .into_iter()
+                .map(|(l, n)| format!("{} {}", l.id, n))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(counts(None, None), ["pro 3", "mid 0", "new 1", "old 5"]);
+        assert_eq!(counts(Some(1), None), ["pro 3", "new 1", "old 5"]);
+        assert_eq!(counts(None, Some(1)), ["mid 0", "new 1"]);
+        assert_eq!(counts(Some(2), Some(4)), ["pro 3"]);
+        assert!(counts(Some(4), Some(2)).is_empty());
+    }
+
+    #[test]
+    fn test_list_sample() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        let tree = add_word(&wdb, "pro", "ta", "tree", "n");
+        add_word(&wdb, "pro", "na", "fish", "n");
+        add_word(&wdb, "pro", "ta", "say", "v");
+        add_word(&wdb, "pro", "ka", "eel", "n");
+        add_word(&wdb, "mid", "a", "water", "n");
+        let sample = wdb.word_sample("pro", 3).unwrap();
+        // Homophones come in the order they were added
+        assert_eq!(romanizations(&sample), ["ka", "na", "ta"]);
+        assert_eq!(sample[2].id, tree);
+        assert_eq!(wdb.word_sample("pro", 10).unwrap().len(), 4);
+        assert!(wdb.word_sample("new", 3).unwrap().is_empty());
+
+        let cli = Cli::try_parse_from(["wdb", "list", "--sample"]).unwrap();
+        let Some(Command::List(args)) = cli.command else {
+            panic!("Expected a list command");
+        };
+        assert!(args.sample);
+    }
+
+    #[test]
+    fn test_family_dot() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        add_lang(&wdb, "old", Some("pro"));
+        add_lang(&wdb, "new", Some("mid"));
+        add_word(&wdb, "mid", "ka", "fish", "n");
+        wdb.db
+            .execute("UPDATE langs SET name = 'Say \"hi\"' WHERE id = 'old'", [])
+            .unwrap();
+        assert_eq!(
+            wdb.family_dot().unwrap(),
+            r#"digraph family {
+    "pro" [label="PRO\n(0 words)"];
+    "mid" [label="MID\n(1 words)"];
+    "old" [label="Say \"hi\"\n(0 words)"];
+    "new" [label="NEW\n(0 words)"];
+    "mid" -> "new";
+    "pro" -> "mid";
+    "pro" -> "old";
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_neighbour() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        for rom in ["ka", "ta", "na", "ta"] {
+            add_word(&wdb, "pro", rom, rom, "n");
+        }
+        add_word(&wdb, "mid", "la", "la", "n");
+        let lang = wdb.get_lang("pro").unwrap();
+        let neighbour = |rom, next| {
+            let words = wdb.neighbour(&lang, rom, next).unwrap();
+            words
+                .into_iter()
+                .map(|w| w.romanization)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(neighbour("ka", true), ["na"]);
+        assert_eq!(neighbour("na", true), ["ta", "ta"]);
+        assert_eq!(neighbour("ta", true), Vec::<String>::new());
+        assert_eq!(neighbour("na", false), ["ka"]);
+        assert_eq!(neighbour("ka", false), Vec::<String>::new());
+        // The starting point doesn't have to be a word
+        assert_eq!(neighbour("m", true), ["na"]);
+        assert_eq!(neighbour("m", false), ["ka"]);
+    }
+
+    #[test]
+    fn test_examples() {
+        let dir = TempDir::new("examples");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.dictionary_template = "{{ for w in words }}{w.romanization}\n\
+             {{ for e in w.examples }}  {e.conlang_text} = {e.gloss}\n{{ endfor }}{{ endfor }}"
+            .to_string();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        let add = |text: &str, gloss: &str| ExampleArgs {
+            command: ExampleCommand::Add {
+                language: "pro".to_string(),
+                word: "ana".to_string(),
+                text: text.to_string(),
+                gloss: gloss.to_string(),
+            },
+        };
+        wdb.example(add("ana ka", "fish water")).unwrap();
+        wdb.example(add("ana", "water!")).unwrap();
+
+        wdb.dump(DumpArgs::new("pro".to_string())).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("PRO.md")).unwrap(),
+            "ana\n  ana ka = fish water\n  ana = water!\nka\n"
+        );
+
+        let rm = |number| ExampleArgs {
+            command: ExampleCommand::Rm {
+                language: "pro".to_string(),
+                word: "ana".to_string(),
+                number,
+            },
+        };
+        assert!(wdb.example(rm(3)).is_err());
+        assert!(wdb.example(rm(0)).is_err());
+        wdb.example(rm(1)).unwrap();
+        let export = wdb.lang_export(wdb.get_lang("pro").unwrap()).unwrap();
+        assert_eq!(
+            export.words[0].examples,
+            [Example {
+                id: 2,
+                conlang_text: "ana".to_string(),
+                gloss: "water!".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_token_limit() {
+        assert!(check_token_limit(3, 3, false, &mut "".as_bytes()).is_ok());
+        let err = check_token_limit(4, 3, false, &mut "n\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().starts_with("Evolving cancelled"), "{}", err);
+        assert!(check_token_limit(4, 3, false, &mut "y\n".as_bytes()).is_ok());
+        assert!(check_token_limit(4, 3, true, &mut "".as_bytes()).is_ok());
+        let words = vec!["ka".to_string(); EVOLVE_TOKEN_LIMIT + 1];
+        let args = Cli::try_parse_from(
+            ["wdb", "evolve", "pro", "mid"]
+                .into_iter()
+                .chain(words.iter().map(|w| &w[..])),
+        )
+        .unwrap();
+        let Some(Command::Evolve(args)) = args.command else {
+            panic!("Expected an evolve command");
+        };
+        assert!(check_token_limit(
+            args.sentence.len(),
+            args.limit,
+            args.yes,
+            &mut "".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_apply_to_lexicon() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "dau", None);
+        add_word(&wdb, "dau", "ka", "fish", "n");
+        let lang = wdb.get_lang("dau").unwrap();
+        let evolved = [("ka", "ka"), ("ano", "ano"), ("bi", "pi"), ("ano", "ano")]
+            .map(|(r, p)| (r.to_string(), p.to_string()));
+
+        // `ka` is already known and isn't asked about, `bi` is declined
+        wdb.apply_to_lexicon(&lang, &evolved, &mut "y\nn\n".as_bytes())
+            .unwrap();
+        let mut args = search_args("");
+        args.language = Some("dau".to_string());
+        let words = wdb.search_words(&args).unwrap();
+        assert_eq!(romanizations(&words), vec!["ano", "ka"]);
+        assert_eq!(words[0].meaning, PLACEHOLDER_MEANING);
+        assert_eq!(words[0].ipa.as_deref(), Some("ano"));
+    }
+
+    #[test]
+    fn test_db_override() {
+        let dir = TempDir::new("db");
+        let db_file = dir.join("snapshot.db");
+
+        let mut cfg = Config::new(dir.to_path_buf(), test_settings(), true);
+        cfg.db_file = Some(db_file.clone());
+        let wdb = Wdb::new(cfg).unwrap();
+        wdb.db.execute_batch(SCHEMA).unwrap();
+        add_lang(&wdb, "pro", None);
+        drop(wdb);
+
+        // Neither `db_file` nor `db_dev_file` was touched
+        assert!(!dir.join("wdb_dev.db").exists());
+        let db = Connection::open(&db_file).unwrap();
+        let langs: u32 = db
+            .query_row("SELECT COUNT(*) FROM langs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(langs, 1);
+        drop(db);
+    }
+
+    #[test]
+    fn test_begin_retries() {
+        let dir = TempDir::new("busy");
+        let db_file = dir.join("busy.db");
+        let mut cfg = Config::new(dir.to_path_buf(), test_settings(), false);
+        cfg.db_file = Some(db_file.clone());
+        let mut wdb = Wdb::new(cfg).unwrap();
+        wdb.db.execute_batch(SCHEMA).unwrap();
+        add_lang(&wdb, "pro", None);
+        // Fail right away rather than waiting, so only the retries wait for the other connection
+        wdb.db.busy_timeout(time::Duration::ZERO).unwrap();
+
+        let other = Connection::open(&db_file).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        other
+            .execute("UPDATE langs SET name = 'Old' WHERE id = 'pro'", [])
+            .unwrap();
+        let holder = thread::spawn(move || {
+            thread::sleep(BUSY_BACKOFF * 3);
+            other.execute_batch("COMMIT").unwrap();
+        });
+        let tr = begin(&mut wdb.db).unwrap();
+        tr.execute("UPDATE langs SET name = 'New' WHERE id = 'pro'", [])
+            .unwrap();
+        tr.commit().unwrap();
+        holder.join().unwrap();
+        assert_eq!(wdb.get_lang("pro").unwrap().name, "New");
+
+        // Giving up eventually
+        let other = Connection::open(&db_file).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        let err = begin(&mut wdb.db).unwrap_err();
+        assert!(is_locked(&err), "{:?}", err);
+        other.execute_batch("ROLLBACK").unwrap();
+        drop(other);
+        drop(wdb);
+    }
+
+    #[test]
+    fn test_redump_if_changed() {
+        let dir = TempDir::new("watch");
+        let db_file = dir.join("watched.db");
+        let mut cfg = Config::new(dir.to_path_buf(), test_settings(), false);
+        cfg.db_file = Some(db_file.clone());
+        let mut wdb = Wdb::new(cfg).unwrap();
+        wdb.db.execute_batch(SCHEMA).unwrap();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        let dictionary = dir.join("PRO.md");
+
+        let mut last = None;
+        assert!(wdb.redump_if_changed("pro", &db_file, &mut last).unwrap());
+        assert_eq!(fs::read_to_string(&dictionary).unwrap(), "ka\n");
+        assert!(!wdb.redump_if_changed("pro", &db_file, &mut last).unwrap());
+
+        // Another process adds a word while holding the database locked
+        let other = Connection::open(&db_file).unwrap();
+        other.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        other
+            .execute(
+                "INSERT INTO words (lang, romanization, meaning, kind) VALUES ('pro', 'ta', 'tree', 'n')",
+                [],
+            )
+            .unwrap();
+        wdb.db.busy_timeout(time::Duration::ZERO).unwrap();
+        let bump = |secs| {
+            let f = fs::File::options().append(true).open(&db_file).unwrap();
+            f.set_modified(time::SystemTime::now() + time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        bump(10);
+        assert!(!wdb.redump_if_changed("pro", &db_file, &mut last).unwrap());
+        assert_eq!(fs::read_to_string(&dictionary).unwrap(), "ka\n");
+
+        other.execute_batch("COMMIT").unwrap();
+        bump(20);
+        assert!(wdb.redump_if_changed("pro", &db_file, &mut last).unwrap());
+        assert_eq!(fs::read_to_string(&dictionary).unwrap(), "ka\nta\n");
+        drop(other);
+        drop(wdb);
+    }
+
+    #[test]
+    fn test_graphemes() {
+        // `ã` and `ẽ` written with a combining tilde, two code points each
+        let a = "a\u{303}";
+        let e = "e\u{303}";
+        assert_eq!(grapheme_len(a), 1);
+        assert_eq!(grapheme_len(&format!("k{}n", a)), 3);
+        assert_eq!(pad_display(&format!("k{}", a), 4), format!("k{}  ", a));
+
+        assert_eq!(edit_distance(a, "b"), 1);
+        assert_eq!(edit_distance(a, e), 1);
+        assert_eq!(edit_distance(&format!("k{}na", a), "kana"), 1);
+        assert_eq!(edit_distance("", a), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_format_table() {
+        assert_eq!(display_width("kana"), 4);
+        assert_eq!(display_width("ka\u{303}na"), 4);
+        assert_eq!(display_width("仮名"), 4);
+
+        let rows = |first: &str| {
+            vec![
+                vec![first.to_string(), "fish".to_string(), "n".to_string()],
+                vec!["ta".to_string(), "tree".to_string()],
+            ]
+        };
+        // The columns line up the same whatever script the first column is in
+        for first in ["kana", "ka\u{303}na", "仮名"] {
+            let lines = format_table(&rows(first), "  ");
+            assert_eq!(lines[0], format!("{}  fish  n", first));
+            assert_eq!(lines[1], "ta    tree");
+            assert_eq!(
+                display_width(&lines[0]) - display_width("  n"),
+                display_width(&lines[1])
+            );
+        }
+        assert!(format_table(&[], " ").is_empty());
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka\u{303}na", "water", "n");
+        add_word(&wdb, "pro", "bo", "go", "v");
+        add_word(&wdb, "pro", "kanak", "child", "n");
+        let lang = wdb.get_lang("pro").unwrap();
+
+        let err = wdb.try_get_unique_word(&lang, "kana").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No matching words found. Did you mean:\n \
+             - kanak  child, n\n \
+             - ka\u{303}na   water, n\n"
+        );
+        let err = wdb.try_get_unique_word(&lang, "xyz").unwrap_err();
+        assert_eq!(err.to_string(), "No matching words found");
+    }
+
+    #[test]
+    fn test_phon_only() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ana", "water", "n");
+        add_word(&wdb, "pro", "bo", "go", "v");
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        wdb.db
+            .execute("UPDATE words SET ipa = NULL WHERE romanization = 'ka'", [])
+            .unwrap();
+
+        let mut args = PhonArgs {
+            language: Some("pro".to_string()),
+            force: false,
+            jobs: None,
+            only: vec![],
+            rule: None,
+            sample: None,
+            yes: false,
+        };
+        let batches = wdb.phon_batches(&args).unwrap();
+        assert_eq!(romanizations(&batches[0].1), vec!["ka"]);
+
+        // Words with an annotation already are picked too, but only once
+        args.only = ["bo", "ana", "bo"].map(String::from).to_vec();
+        let batches = wdb.phon_batches(&args).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(romanizations(&batches[0].1), vec!["bo", "ana"]);
+
+        args.only = vec!["nope".to_string()];
+        assert!(wdb.phon_batches(&args).is_err());
+    }
+
+    #[test]
+    fn test_anki_export() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        add_word(&wdb, "pro", "ana", "<water> & \"rain\"", "n");
+        wdb.db
+            .execute(
+                "UPDATE words SET ipa = NULL, note = 'line\tone\nline two' WHERE romanization = 'ka'",
+                [],
+            )
+            .unwrap();
+        let export = wdb.lang_export(wdb.get_lang("pro").unwrap()).unwrap();
+
+        let deck = anki_deck(
+            &export.words,
+            &[AnkiField::Romanization, AnkiField::Ipa],
+            &[AnkiField::Meaning],
+        );
+        assert_eq!(
+            deck,
+            "ana [ana]\t&lt;water&gt; &amp; &quot;rain&quot;\nka\tfish\n"
+        );
+
+        let deck = anki_deck(
+            &export.words,
+            &[AnkiField::Meaning],
+            &[AnkiField::Romanization, AnkiField::Kind, AnkiField::Note],
+        );
+        assert_eq!(
+            deck.lines().nth(1),
+            Some("fish\tka (n) line one<br>line two")
+        );
+
+        let cli = Cli::try_parse_from([
+            "wdb",
+            "export",
+            "pro",
+            "-f",
+            "anki",
+            "--back",
+            "meaning,kind",
+        ])
+        .unwrap();
+        let Some(Command::Export(args)) = cli.command else {
+            panic!("Expected an export command");
+        };
+        assert_eq!(args.front, [AnkiField::Romanization, AnkiField::Ipa]);
+        assert_eq!(args.back, [AnkiField::Meaning, AnkiField::Kind]);
+    }
+
+    #[test]
+    fn test_latex_export() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "k_a#", "50% & {more} ~$5^2\\", "n");
+        wdb.db.execute("UPDATE words SET ipa = NULL", []).unwrap();
+        add_word(&wdb, "pro", "ta", "say\nhi", "v");
+        let export = wdb.lang_export(wdb.get_lang("pro").unwrap()).unwrap();
+
+        let doc = latex_document(&export, "book", "\\usepackage{tipa}");
+        assert!(doc.starts_with(
+            "\\documentclass{book}\n\\usepackage{fontspec}\n\\usepackage{longtable}\n\
+             \\usepackage{tipa}\n\\begin{document}\n"
+        ));
+        assert!(doc.contains(
+            "\\textbf{k\\_a\\#} &  & \\textit{n} & 50\\% \\& \\{more\\} \
+             \\textasciitilde{}\\$5\\textasciicircum{}2\\textbackslash{} \\\\\n"
+        ));
+        assert!(doc.contains("\\textbf{ta} & [ta] & \\textit{v} & say hi \\\\\n"));
+        assert!(doc.ends_with("\\end{longtable}\n\\end{document}\n"));
+    }
+
+    #[test]
+    fn test_json_export() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish, eel", "n");
+        add_word(&wdb, "pro", "ta", "say \"hi\"", "v");
+        let dir = TempDir::new("json-export");
+        let out = dir.join("export.json");
+        let export = |wdb: &mut Wdb, args: &[&str]| {
+            let cli = Cli::try_parse_from(
+                ["wdb"]
+                    .iter()
+                    .chain(args)
+                    .chain(&["-o", out.to_str().unwrap()]),
+            )
+            .unwrap();
+            match cli.command {
+                Some(Command::Export(args)) => wdb.export(args).unwrap(),
+                Some(Command::ExportAll(args)) => wdb.export_all(args).unwrap(),
+                _ => panic!("Expected an export command"),
+            }
+            fs::read_to_string(&out).unwrap()
+        };
+        let expected =
+            serde_json::to_value(wdb.lang_export(wdb.get_lang("pro").unwrap()).unwrap()).unwrap();
+
+        let pretty = export(&mut wdb, &["export", "pro", "--pretty"]);
+        assert!(pretty.lines().count() > 1, "{}", pretty);
+        assert_eq!(export(&mut wdb, &["export", "pro"]), pretty);
+        let compact = export(&mut wdb, &["export", "pro", "--compact"]);
+        assert_eq!(compact.lines().count(), 1, "{}", compact);
+        for json in [pretty, compact] {
+            let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, expected);
+        }
+
+        let all = export(&mut wdb, &["export-all", "--compact"]);
+        let parsed: serde_json::Value = serde_json::from_str(&all).unwrap();
+        assert_eq!(parsed, serde_json::json!({ "pro": expected }));
+    }
+
+    #[test]
+    fn test_csv_export_append() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish, eel", "n");
+        add_word(&wdb, "pro", "ta", "say \"hi\"", "v");
+        let dir = TempDir::new("csv-append");
+        let out = dir.join("export.csv");
+        let export = |wdb: &mut Wdb, extra: &[&str]| {
+            let out = out.to_str().unwrap();
+            let cli = Cli::try_parse_from(
+                ["wdb", "export", "pro", "-f", "csv", "-o", out, "--append"]
+                    .iter()
+                    .chain(extra),
+            )
+            .unwrap();
+            let Some(Command::Export(args)) = cli.command else {
+                panic!("Expected an export command");
+            };
+            wdb.export(args).unwrap();
+            fs::read_to_string(out).unwrap()
+        };
+
+        let first = "romanization,meaning,kind,ipa,origin,note\n\
+                     ka,\"fish, eel\",n,ka,,\n\
+                     ta,\"say \"\"hi\"\"\",v,ta,,\n";
+        assert_eq!(export(&mut wdb, &[]), first);
+        // Nothing new, nothing appended
+        assert_eq!(export(&mut wdb, &[]), first);
+
+        add_word(&wdb, "pro", "an", "water", "n");
+        assert_eq!(export(&mut wdb, &[]), format!("{}an,water,n,an,,\n", first));
+
+        let full = export(&mut wdb, &["--full"]);
+        assert_eq!(full.lines().count(), 4);
+        assert!(full.lines().nth(1).unwrap().starts_with("an,"), "{}", full);
+        assert_eq!(export(&mut wdb, &[]), full);
+
+        assert!(Cli::try_parse_from(["wdb", "export", "pro", "-f", "csv", "--append"]).is_err());
+        let cli =
+            Cli::try_parse_from(["wdb", "export", "pro", "-o", "x.json", "--append"]).unwrap();
+        let Some(Command::Export(args)) = cli.command else {
+            panic!("Expected an export command");
+        };
+        assert!(wdb.export(args).is_err());
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+
+        wdb.cfg.settings.dictionary_template = "{{ for w in words }}{w.romanization}".to_string();
+        let err = format!(
+            "{:#}",
+            wdb.dump(DumpArgs::new("pro".to_string())).unwrap_err()
+        );
+        assert!(
+            err.starts_with("Invalid `dictionary_template` in `Wdb.toml`: "),
+            "{}",
+            err
+        );
+        assert!(err.contains("line 1"), "{}", err);
+
+        wdb.cfg.settings.dictionary_template =
+            "\n{{ for w in words }}{w.nope}{{ endfor }}".to_string();
+        let err = format!(
+            "{:#}",
+            wdb.dump(DumpArgs::new("pro".to_string())).unwrap_err()
+        );
+        assert!(
+            err.starts_with("Failed to render `dictionary_template` from `Wdb.toml`: "),
+            "{}",
+            err
+        );
+        assert!(err.contains("line 2"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let minimal = parse_settings("db_file = \"lexicon.db\"\n").unwrap();
+        assert_eq!(minimal.dev_db_file(), "lexicon_dev.db");
+        let dev_db_file = |db_file: &str| {
+            parse_settings(&format!("db_file = \"{}\"\n", db_file))
+                .unwrap()
+                .dev_db_file()
+        };
+        assert_eq!(dev_db_file("data.v2/lexicon"), "data.v2/lexicon_dev");
+        assert_eq!(dev_db_file("data.v2/lexicon.db"), "data.v2/lexicon_dev.db");
+        assert_eq!(dev_db_file(".lexicon"), ".lexicon_dev");
+        assert_eq!(minimal.word_list_folder, "words");
+        assert_eq!(minimal.rule_list_folder, "rules");
+        assert_eq!(minimal.dictionary_file_template, "{lang.name}.md");
+        assert!(!minimal.auto_dump);
+        assert_eq!(minimal.kinds, default_kinds());
+        assert_eq!(minimal.engine, Engine::Lexurgy);
+        // The default template has to compile
+        let mut tt = TinyTemplate::new();
+        add_template(&mut tt, "dictionary_template", &minimal.dictionary_template).unwrap();
+
+        let complete = parse_settings(&format!(
+            "{}kinds = [\"n\"]\nengine = \"native\"\nrequire_meaning = true\n",
+            TEST_SETTINGS.replace("auto_dump = false", "auto_dump = true")
+        ))
+        .unwrap();
+        assert_eq!(complete.dev_db_file(), "wdb_dev.db");
+        assert_eq!(
+            complete.dictionary_template,
+            test_settings().dictionary_template
+        );
+        assert!(complete.auto_dump && complete.require_meaning);
+        assert_eq!(complete.kinds, ["n"]);
+        assert_eq!(complete.engine, Engine::Native);
+
+        let Err(err) = parse_settings("auto_dump = true\n") else {
+            panic!("Expected a missing `db_file` error");
+        };
+        assert!(err.to_string().contains("`db_file`"), "{}", err);
+        assert!(parse_settings("db_file = 3\n").is_err());
+    }
+
+    #[test]
+    fn test_dictionary_encoding() {
+        let dir = TempDir::new("encoding");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "kã", "fish", "n");
+        let mut dump = |encoding| {
+            wdb.cfg.settings.dictionary_encoding = encoding;
+            wdb.dump(DumpArgs::new("pro".to_string())).unwrap();
+            fs::read(dir.join("PRO.md")).unwrap()
+        };
+
+        assert_eq!(dump(Encoding::Utf8), "kã\n".as_bytes());
+        assert_eq!(dump(Encoding::Utf8Bom), b"\xef\xbb\xbfk\xc3\xa3\n");
+        assert_eq!(dump(Encoding::Utf16le), b"\xff\xfek\0\xe3\0\n\0");
+        let settings: Settings = toml::from_str(&format!(
+            "{}dictionary_encoding = \"utf8-bom\"",
+            TEST_SETTINGS
+        ))
+        .unwrap();
+        assert_eq!(settings.dictionary_encoding, Encoding::Utf8Bom);
+        assert_eq!(test_settings().dictionary_encoding, Encoding::Utf8);
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = Flags::parse(" poetic,archaic\tpoetic,, old ");
+        assert_eq!(
+            flags.iter().collect::<Vec<_>>(),
+            ["archaic", "old", "poetic"]
+        );
+        assert!(flags.contains("old") && !flags.contains("arch"));
+        assert_eq!(flags.to_string(), "archaic, old, poetic");
+        assert_eq!(Flags::parse(&flags.to_string()), flags);
+        assert!(Flags::parse(" , ").is_empty());
+        assert_eq!(
+            serde_json::to_string(&flags).unwrap(),
+            "\"archaic, old, poetic\""
+        );
+
+        let wdb = test_wdb();
+        let round_trip = |flags: Option<&str>| {
+            wdb.db
+                .query_row("SELECT ?", [flags], |row| row.get::<_, Flags>(0))
+                .unwrap()
+        };
+        assert_eq!(round_trip(Some("old archaic")).to_string(), "archaic, old");
+        assert_eq!(round_trip(None), Flags::default());
+        let stored: String = wdb
+            .db
+            .query_row("SELECT ?", [&flags], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, "archaic, old, poetic");
+    }
+
+    #[test]
+    fn test_dump_tags() {
+        let dir = TempDir::new("tags");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.dictionary_template =
+            "{{ for w in words }}{w.romanization}:{w.flags}\n{{ endfor }}".to_string();
+        add_lang(&wdb, "pro", None);
+        for (rom, flags) in [
+            ("ka", ""),
+            ("ta", "archaic"),
+            ("na", "poetic, archaic"),
+            ("pa", "poetic"),
+        ] {
+            let id = add_word(&wdb, "pro", rom, rom, "n");
+            wdb.db
+                .execute(
+                    "UPDATE words SET flags = ? WHERE id = ?",
+                    params![flags, id],
+                )
+                .unwrap();
+        }
+        let mut dump = |exclude: &[&str], include: &[&str], strip_flags| {
+            let to_strings = |ts: &[&str]| ts.iter().map(|t| t.to_string()).collect();
+            wdb.dump(DumpArgs {
+                exclude_tag: to_strings(exclude),
+                include_tag: to_strings(include),
+                strip_flags,
+                ..DumpArgs::new("pro".to_string())
+            })
+            .unwrap();
+            fs::read_to_string(dir.join("PRO.md")).unwrap()
+        };
+
+        assert_eq!(
+            dump(&[], &[], false),
+            "ka:\nna:archaic, poetic\npa:poetic\nta:archaic\n"
+        );
+        assert_eq!(dump(&["archaic"], &[], false), "ka:\npa:poetic\n");
+        assert_eq!(
+            dump(&[], &["poetic"], false),
+            "na:archaic, poetic\npa:poetic\n"
+        );
+        assert_eq!(dump(&["archaic"], &["poetic"], true), "pa:\n");
+    }
+
+    #[test]
+    fn test_normalize_ipa() {
+        for ipa in ["tʃa", " tʃa ", "[tʃa]", "/tʃa/", "[ tʃa ]"] {
+            assert_eq!(normalize_ipa(ipa), "tʃa", "{:?}", ipa);
+        }
+        assert_eq!(normalize_ipa("[tʃa/"), "[tʃa/");
+        assert_eq!(normalize_ipa("[]"), "");
+
+        let dir = TempDir::new("ipa-delimiter");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.dictionary_template =
+            "{{ for w in words }}{w.romanization} {w.ipa}\n{{ endfor }}".to_string();
+        add_lang(&wdb, "pro", None);
+        for (rom, ipa) in [("ka", "[ka]"), ("ta", "/ta/"), ("na", "na")] {
+            let id = add_word(&wdb, "pro", rom, rom, "n");
+            wdb.db
+                .execute("UPDATE words SET ipa = ? WHERE id = ?", params![ipa, id])
+                .unwrap();
+        }
+        let mut dump = |delimiter| {
+            wdb.cfg.settings.ipa_delimiter = delimiter;
+            wdb.dump(DumpArgs::new("pro".to_string())).unwrap();
+            fs::read_to_string(dir.join("PRO.md")).unwrap()
+        };
+        assert_eq!(dump(IpaDelimiter::None), "ka ka\nna na\nta ta\n");
+        assert_eq!(dump(IpaDelimiter::Slashes), "ka /ka/\nna /na/\nta /ta/\n");
+        let settings: Settings =
+            toml::from_str(&format!("{}ipa_delimiter = \"brackets\"", TEST_SETTINGS)).unwrap();
+        assert_eq!(settings.ipa_delimiter, IpaDelimiter::Brackets);
+    }
+
+    #[test]
+    fn test_dump_dictionary_only() {
+        let mut wdb = test_wdb();
+        wdb.cfg.settings.dictionary_file_template = "{lang.name".to_string();
+        wdb.cfg.settings.reverse_dictionary_template = Some(
+            "{{ for m in meanings }}{m.meaning}: {{ for w in m.words }}{w.romanization}{{ endfor }}\n{{ endfor }}"
+                .to_string(),
+        );
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        add_word(&wdb, "pro", "ta", "tree", "n");
+
+        // A broken file name template only matters when writing the file
+        let err = wdb.dump(DumpArgs::new("pro".to_string())).unwrap_err();
+        assert!(
+            err.to_string().contains("dictionary_file_template"),
+            "{}",
+            err
+        );
+        let args = DumpArgs {
+            dictionary_only: true,
+            ..DumpArgs::new("pro".to_string())
+        };
+        let dict = wdb.render_dictionary(&args).unwrap();
+        assert_eq!(dict.file, None);
+        assert_eq!(dict.text, "ka\nta\n");
+
+        let reverse = DumpArgs {
+            reverse: true,
+            ..args
+        };
+        assert_eq!(
+            wdb.render_dictionary(&reverse).unwrap().text,
+            "fish: ka\ntree: ta\n"
+        );
+        let err = wdb
+            .dump(DumpArgs {
+                dictionary_only: false,
+                ..reverse
+            })
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("reverse_dictionary_file_template"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_dump_reverse() {
+        let dir = TempDir::new("reverse");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ta", "tree", "n");
+        add_word(&wdb, "pro", "na", "fish", "n");
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        let args = || DumpArgs {
+            reverse: true,
+            ..DumpArgs::new("pro".to_string())
+        };
+        let err = wdb.dump(args()).unwrap_err();
+        assert!(
+            err.to_string().contains("reverse_dictionary_template"),
+            "{}",
+            err
+        );
+
+        wdb.cfg.settings.reverse_dictionary_file_template =
+            Some("{lang.name} index.md".to_string());
+        wdb.cfg.settings.reverse_dictionary_template = Some(
+            "{{ for m in meanings }}{m.meaning}:{{ for w in m.words }} {w.romanization}{{ endfor }}\n{{ endfor }}"
+                .to_string(),
+        );
+        wdb.dump(args()).unwrap();
+        // Words sharing a meaning are listed together, by spelling
+        assert_eq!(
+            fs::read_to_string(dir.join("PRO index.md")).unwrap(),
+            "fish: ka na\ntree: ta\n"
+        );
+        assert!(!dir.join("PRO.md").exists());
+    }
+
+    #[test]
+    fn test_dump_group_by_kind() {
+        let dir = TempDir::new("groups");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.dictionary_template = "{{ for g in groups }}{g.name}:\
+             {{ for w in g.words }} {w.romanization}{{ endfor }}\n{{ endfor }}"
+            .to_string();
+        wdb.cfg.settings.kind_order = vec!["n".to_string(), "v".to_string()];
+        add_lang(&wdb, "pro", None);
+        for (rom, kind) in [
+            ("ka", "adj"),
+            ("ta", "v"),
+            ("na", "n"),
+            ("pa", "adv"),
+            ("sa", "n"),
+        ] {
+            add_word(&wdb, "pro", rom, rom, kind);
+        }
+        let mut dump = |group_by| {
+            wdb.dump(DumpArgs {
+                group_by,
+                ..DumpArgs::new("pro".to_string())
+            })
+            .unwrap();
+            fs::read_to_string(dir.join("PRO.md")).unwrap()
+        };
+        assert_eq!(
+            dump(Some(GroupBy::Kind)),
+            "n: na sa\nv: ta\nadj: ka\nadv: pa\n"
+        );
+        assert_eq!(dump(None), "");
+    }
+
+    #[test]
+    fn test_dump_group_by_letter_and_tag() {
+        let dir = TempDir::new("groups-tags");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.dictionary_template = "{{ for g in groups }}{g.name}:\
+             {{ for w in g.words }} {w.romanization}{{ endfor }}\n{{ endfor }}"
+            .to_string();
+        add_lang(&wdb, "pro", None);
+        for (rom, flags) in [
+            ("ka", "archaic"),
+            ("Kiu", ""),
+            ("ta", "poetic, archaic"),
+            ("tsa", "poetic"),
+            ("ana", ""),
+        ] {
+            let id = add_word(&wdb, "pro", rom, rom, "n");
+            wdb.db
+                .execute(
+                    "UPDATE words SET flags = ? WHERE id = ?",
+                    params![flags, id],
+                )
+                .unwrap();
+        }
+        let mut dump = |group_by, strip_flags| {
+            wdb.dump(DumpArgs {
+                group_by: Some(group_by),
+                strip_flags,
+                ..DumpArgs::new("pro".to_string())
+            })
+            .map(|_| fs::read_to_string(dir.join("PRO.md")).unwrap())
+        };
+        // Capitalized words go with the lowercase letter
+        assert_eq!(
+            dump(GroupBy::Letter, false).unwrap(),
+            "a: ana\nk: Kiu ka\nt: ta tsa\n"
+        );
+        assert_eq!(
+            dump(GroupBy::Tag, false).unwrap(),
+            "archaic: ka ta\npoetic: ta tsa\n: Kiu ana\n"
+        );
+        assert!(dump(GroupBy::Tag, true).is_err());
+    }
+
+    #[test]
+    fn test_gloss_legend() {
+        let dir = TempDir::new("glosses");
+        let mut wdb = test_wdb();
+        wdb.cfg.root = dir.to_path_buf();
+        wdb.cfg.settings.glosses = [("PST", "past"), ("PL", "plural"), ("NOM", "nominative")]
+            .iter()
+            .map(|(a, m)| (a.to_string(), m.to_string()))
+            .collect();
+        wdb.cfg.settings.dictionary_template =
+            "{{ for g in glosses }}{g.abbreviation}: {g.meaning}\n{{ endfor }}".to_string();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "go.PST", "v");
+        let id = add_word(&wdb, "pro", "ta", "fish", "n");
+        wdb.db
+            .execute("UPDATE words SET note = 'Irregular PL' WHERE id = ?", [id])
+            .unwrap();
+        add_word(&wdb, "pro", "na", "PAST, NOMINAL", "n");
+
+        wdb.dump(DumpArgs::new("pro".to_string())).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("PRO.md")).unwrap(),
+            "PL: plural\nPST: past\n"
+        );
+    }
+
+    #[test]
+    fn test_word_list_files() {
+        let dir = env::temp_dir();
+        let cfg = Config::new(dir.to_path_buf(), test_settings(), false);
+        let words = dir.join("words");
+        assert_eq!(cfg.word_list_file("pro_rom"), words.join("pro_rom.wli"));
+        assert_eq!(
+            cfg.lexurgy_output_file("pro_rom", LexurgyOutput::Phonetic),
+            words.join("out").join("pro_rom_ev.wli")
+        );
+        assert_eq!(
+            cfg.lexurgy_output_file("pro_rom", LexurgyOutput::Both),
+            words.join("out").join("pro_rom_ev.wlm")
+        );
+
+        let settings: Settings = toml::from_str(&format!(
+            "{}[word_lists]\nextension = \"txt\"\noutput_folder = \"evolved\"\noutput_suffix = \"-out\"\n",
+            TEST_SETTINGS
+        ))
+        .unwrap();
+        let cfg = Config::new(dir.to_path_buf(), settings, false);
+        assert_eq!(cfg.word_list_file("pro_rom"), words.join("pro_rom.txt"));
+        assert_eq!(cfg.lexurgy_output_folder(), words.join("evolved"));
+        assert_eq!(
+            cfg.lexurgy_output_file("pro_rom", LexurgyOutput::Romanized),
+            words.join("evolved").join("pro_rom-out.txt")
+        );
+        assert_eq!(
+            cfg.lexurgy_output_file("pro_rom", LexurgyOutput::Both),
+            words.join("evolved").join("pro_rom-out.wlm")
+        );
+    }
+
+    #[test]
+    fn test_missing_lexurgy_output() {
+        let dir = TempDir::new("lexurgy-out");
+        let expected = dir.join("pro_rom_ev.wli");
+
+        let err = open_lexurgy_output(&expected).unwrap_err().to_string();
+        assert!(err.contains("`pro_rom_ev.wli`"), "{}", err);
+        assert!(err.contains("found no files"), "{}", err);
+
+        fs::write(dir.join("pro_rom_evolved.wli"), "ka\n").unwrap();
+        fs::write(dir.join("pro_rom.wli"), "ka\n").unwrap();
+        let err = open_lexurgy_output(&expected).unwrap_err().to_string();
+        assert!(
+            err.contains("found only: pro_rom.wli, pro_rom_evolved.wli"),
+            "{}",
+            err
+        );
+
+        fs::write(&expected, "ka\n").unwrap();
+        assert!(open_lexurgy_output(&expected).is_ok());
+    }
+
+    /// Sets up a `pro` language and a `mid` one descending from it, with the given rules
+    fn test_family_with_rules(name: &str, rules: &str) -> (Wdb, TempDir) {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.join("rules")).unwrap();
+        fs::create_dir_all(dir.join("words")).unwrap();
+        fs::write(dir.join("rules").join("mid.lsc"), rules).unwrap();
+        let mut wdb = test_wdb();
+        wdb.cfg = Config::new(dir.to_path_buf(), test_settings(), false);
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        (wdb, dir)
+    }
+
+    #[test]
+    fn test_import_csv() {
+        assert_eq!(
+            parse_csv_line(r#"ka,"fish, or eel","say ""hi""",n"#, ',').unwrap(),
+            ["ka", "fish, or eel", "say \"hi\"", "n"]
+        );
+        assert!(parse_csv_line(r#"ka,"fish"#, ',').is_err());
+
+        let (mut wdb, dir) =
+            test_family_with_rules("import", "Deromanizer:\n    c => k\ninit:\n    unchanged\n");
+        wdb.cfg.native = true;
+        let csv = dir.join("words.csv");
+        fs::write(
+            &csv,
+            "meaning;rom;kind;ipa\nfish;ca;n;\ntree;ta;n;tʰa\n\"red; bright\";co;adj; \n",
+        )
+        .unwrap();
+        let args = ImportArgs {
+            language: "mid".to_string(),
+            file: csv.clone(),
+            header: true,
+            delimiter: ';',
+            rom_col: 2,
+            meaning_col: 1,
+            kind_col: 3,
+            ipa_col: Some(4),
+            disable_autorom: false,
+            homophone: false,
+            force_kind: false,
+            append: false,
+            yes: false,
+        };
+        let words =
+            parse_csv_words(fs::read_to_string(&args.file).unwrap().as_bytes(), &args).unwrap();
+        assert_eq!(
+            words[1],
+            NewWord {
+                romanization: "ta".to_string(),
+                meaning: "tree".to_string(),
+                kind: "n".to_string(),
+                ipa: Some("tʰa".to_string()),
+            }
+        );
+        wdb.import(args, &mut "".as_bytes()).unwrap();
+
+        let lang = wdb.get_lang("mid").unwrap();
+        let ipa = |rom| {
+            wdb.try_get_unique_word(&lang, rom)
+                .unwrap()
+                .unwrap()
+                .ipa
+                .unwrap()
+        };
+        // The provided IPA is kept as is, the rest deromanized
+        assert_eq!(ipa("ta"), "tʰa");
+        assert_eq!(ipa("ca"), "ka");
+        assert_eq!(ipa("co"), "ko");
+        assert_eq!(
+            wdb.try_get_unique_word(&lang, "co")
+                .unwrap()
+                .unwrap()
+                .meaning,
+            "red; bright"
+        );
+
+        // Importing into a language with words needs its id typed
+        fs::write(&csv, "su,sun,n\n").unwrap();
+        let args = |append| ImportArgs {
+            language: "mid".to_string(),
+            file: csv.clone(),
+            header: false,
+            delimiter: ',',
+            rom_col: 1,
+            meaning_col: 2,
+            kind_col: 3,
+            ipa_col: None,
+            disable_autorom: false,
+            homophone: false,
+            force_kind: false,
+            append,
+            yes: false,
+        };
+        assert!(wdb.import(args(false), &mut "y\n".as_bytes()).is_err());
+        assert!(wdb.homophones(&lang, "su").unwrap().is_empty());
+        wdb.import(args(false), &mut " mid\n".as_bytes()).unwrap();
+        fs::write(&csv, "si,see,v\n").unwrap();
+        wdb.import(args(true), &mut "".as_bytes()).unwrap();
+        assert_eq!(wdb.homophones(&lang, "si").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_letter_counts() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let lang = wdb.get_lang("pro").unwrap();
+        assert!(wdb.letter_counts(&lang, false).unwrap().is_empty());
+
+        add_word(&wdb, "pro", "kaŋ", "fish", "n");
+        add_word(&wdb, "pro", "a\u{303}ka", "tree", "n");
+        add_word(&wdb, "pro", "ka na", "stone", "n");
+        let counts = |graphemes| {
+            wdb.letter_counts(&lang, graphemes)
+                .unwrap()
+                .iter()
+                .map(|(l, n)| format!("{} {}", l, n))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(counts(false), ["a 5", "k 3", "n 1", "ŋ 1", "\u{303} 1"]);
+        assert_eq!(counts(true), ["a 4", "k 3", "a\u{303} 1", "n 1", "ŋ 1"]);
+        assert_eq!(
+            frequency_table(&[("a".to_string(), 3), ("ŋ".to_string(), 1)]),
+            ["a      3   75.0%", "ŋ      1   25.0%"]
+        );
+    }
+
+    #[test]
+    fn test_phoneme_counts() {
+        let (wdb, _dir) = test_family_with_rules("phonology", "Symbol tʃ, aː\n");
+        add_word(&wdb, "mid", "tʃaː", "fish", "n");
+        add_word(&wdb, "mid", "ta.tʃa", "tree", "n");
+        let id = add_word(&wdb, "mid", "ka", "stone", "n");
+        wdb.db
+            .execute("UPDATE words SET ipa = NULL WHERE id = ?", [id])
+            .unwrap();
+        // Delimited IPA is counted the same as bare IPA
+        let id = add_word(&wdb, "mid", "ta", "hand", "n");
+        wdb.db
+            .execute("UPDATE words SET ipa = '[ta]' WHERE id = ?", [id])
+            .unwrap();
+
+        let lang = wdb.get_lang("mid").unwrap();
+        let PhonemeCounts { counts, missing } = wdb.phoneme_counts(&lang).unwrap();
+        let counts = counts
+            .iter()
+            .map(|(s, n)| format!("{} {}", s, n))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, ["a 3", "t 2", "tʃ 2", "aː 1"]);
+        assert_eq!(missing, ["ka"]);
+    }
+
+    #[test]
+    fn test_lexicon_warnings() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        add_word(&wdb, "pro", "ka", "to see", "v");
+        add_word(&wdb, "pro", "tana", "tree", "n");
+        let pro = wdb.get_lang("pro").unwrap();
+        let tokens = ["ka", "tana", "ka#1", "ka#2", "tanu"].map(String::from);
+        let warnings = wdb.lexicon_warnings(&pro, &tokens).unwrap();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings[0].starts_with("`ka#2` is not a word in PRO (pro): Index `2`"));
+        assert!(warnings[1].starts_with("`tanu` is not a word in PRO (pro): No matching"));
+        assert!(warnings[1].contains("tana"), "{}", warnings[1]);
+    }
+
+    #[test]
+    fn test_evolve_json() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "evolve-json",
+            "Deromanizer:\n    c => k\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        fs::write(
+            dir.join("rules").join("new.lsc"),
+            "init:\n    unchanged\nrhotacism:\n    d => r\nRomanizer:\n    k => c\n",
+        )
+        .unwrap();
+        add_lang(&wdb, "new", Some("mid"));
+        let cli = |extra: &[&str]| {
+            let args = [
+                "wdb", "evolve", "pro", "new", "cat", "ta", "--format", "json",
+            ];
+            let cli = Cli::try_parse_from(args.iter().chain(extra)).unwrap();
+            let Some(Command::Evolve(args)) = cli.command else {
+                panic!("Expected an evolve command");
+            };
+            args
+        };
+        let langs = wdb.get_langs().unwrap();
+        let (pro, new) = (wdb.get_lang("pro").unwrap(), wdb.get_lang("new").unwrap());
+        let steps = evolution_steps(&langs, &pro, &new).unwrap();
+        let json = |args: EvolveArgs| {
+            let tokens = args.sentence.clone();
+            let evolution = wdb.run_evolution(&args, &steps, tokens.clone()).unwrap();
+            serde_json::to_value(evolved_tokens(&tokens, &evolution)).unwrap()
+        };
+
+        assert_eq!(
+            json(cli(&[])),
+            serde_json::json!([
+                {"input": "cat", "stages": [
+                    {"stage_id": "mid", "form": "kad"},
+                    {"stage_id": "new", "form": "car"},
+                ]},
+                {"input": "ta", "stages": [
+                    {"stage_id": "mid", "form": "da"},
+                    {"stage_id": "new", "form": "ra"},
+                ]},
+            ])
+        );
+        let with_phonetic = json(cli(&["-p"]));
+        assert_eq!(
+            with_phonetic[0]["stages"][1],
+            serde_json::json!({"stage_id": "new", "form": "car", "phonetic": "kar"})
+        );
+        assert_eq!(
+            with_phonetic[1]["stages"][0]["phonetic"],
+            serde_json::Value::Null
+        );
+        assert!(Cli::try_parse_from([
+            "wdb",
+            "evolve",
+            "pro",
+            "new",
+            "ta",
+            "--format",
+            "json",
+            "--compare"
+        ])
+        .is_err());
+
+        // Warnings about unknown words and rules the native engine skips go to STDERR, leaving only
+        // the JSON on STDOUT
+        let expected = json(cli(&[]));
+        fs::write(
+            dir.join("rules").join("new.lsc"),
+            "init:\n    unchanged\nrhotacism:\n    d => r\nskipped:\n    r => r\n    :rhotacism\n\
+             Romanizer:\n    k => c\n",
+        )
+        .unwrap();
+        let mut out = vec![];
+        wdb.evolve(cli(&["--check-lexicon"]), &mut out).unwrap();
+        let printed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(printed, expected);
+    }
+
+    #[test]
+    fn test_evolve_from_ipa() {
+        let (mut wdb, _dir) = test_family_with_rules(
+            "from-ipa",
+            "Deromanizer:\n    c => k\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        add_lang(&wdb, "new", Some("mid"));
+        let args = |from: &str, from_ipa| EvolveArgs {
+            show_phonetic: true,
+            from_ipa,
+            ..evolve_args(from, "mid", "cat")
+        };
+        let mid = wdb.get_lang("mid").unwrap();
+        let steps = [&mid];
+        let words = |from_ipa| {
+            let evolution = wdb
+                .run_evolution(&args("pro", from_ipa), &steps, vec!["cat".to_string()])
+                .unwrap();
+            evolution.stages[0].words.clone()
+        };
+        // Romanized input is deromanized first, IPA is evolved as is
+        assert_eq!(words(false), ["kad"]);
+        assert_eq!(words(true), ["cad"]);
+
+        let err = wdb
+            .evolve(
+                EvolveArgs {
+                    to_lang: "new".to_string(),
+                    ..args("mid", true)
+                },
+                &mut io::sink(),
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("isn't a proto-language"),
+            "{}",
+            err
+        );
     }
 
-    fn dump(&mut self, args: DumpArgs) -> Result<()> {
-        let lang = self.get_lang(&args.language).expect("Invalid language");
-        let mut stmt = self
-            .db
-            .prepare("SELECT * FROM words WHERE lang = ? ORDER BY romanization")?;
-        let entries = stmt
-            .query_map([&lang.id], WordEntry::from_row)?
-            .collect::<Result<_, _>>()?;
-        let mut tt = TinyTemplate::new();
-        tt.add_template(
-            "dictionary_file",
-            &self.cfg.settings.dictionary_file_template,
-        )?;
-        tt.add_template("dictionary", &self.cfg.settings.dictionary_template)?;
+    #[test]
+    fn test_format_comparison() {
+        let words = |ws: &[&str]| ws.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        let stages = [
+            ("pro", words(&["kata", "na", "ti"])),
+            ("mid", words(&["gada", "nā", "ʃi"])),
+        ];
+        assert_eq!(
+            format_comparison(&stages),
+            ["pro  → mid", "kata → gada", "na   → nā", "ti   → ʃi"]
+        );
 
-        #[derive(Serialize)]
-        struct DictionaryTemplateContext {
-            lang: LangEntry,
-            words: Vec<WordEntry>,
-        }
+        // A stage that merged two words
+        let stages = [
+            ("pro", words(&["a", "ka"])),
+            ("mid", words(&["aka"])),
+            ("new", words(&["ak"])),
+        ];
+        assert_eq!(
+            format_comparison(&stages),
+            ["pro → mid → new", "a   → aka → ak", "ka  → -   → -"]
+        );
+    }
 
-        let context = DictionaryTemplateContext {
-            lang,
-            words: entries,
+    #[test]
+    fn test_parse_lexurgy_output() {
+        let parse = |out: &str, format, expected| {
+            parse_lexurgy_output(out.as_bytes(), format, expected)
+                .unwrap()
+                .into_iter()
+                .map(|o| match o {
+                    WordOutput::PhonRom(p, r) => format!("{}|{}", p, r),
+                    o => o.get_value().unwrap(),
+                })
+                .collect::<Vec<_>>()
         };
+        assert_eq!(
+            parse("kaka  \r\n\n\n", LexurgyOutput::Romanized, 1),
+            ["kaka"]
+        );
+        // The second word was deleted by the rules, its empty line is kept
+        assert_eq!(
+            parse("ka\n\nta \n\n", LexurgyOutput::Phonetic, 3),
+            ["ka", "", "ta"]
+        );
+        assert_eq!(parse("ka\n\n", LexurgyOutput::Phonetic, 2), ["ka", ""]);
+        assert_eq!(
+            parse("caca => kaka => kaga  \n\n", LexurgyOutput::Both, 1),
+            ["kaka|kaga"]
+        );
+    }
 
-        let mut dict_file = self.cfg.root.to_path_buf();
-        dict_file.push(tt.render("dictionary_file", &context)?);
-        fs::write(&dict_file, tt.render("dictionary", &context)?)
-            .with_context(|| format!("Writing dictionary file: {:?}", &dict_file))?;
+    #[test]
+    fn test_parse_lexurgy_trace() {
+        let headed = "Tracing kata\nApplied voicing: kata -> kada\nApplied apocope: kada -> kad\n\
+                      Tracing kata\nApplied voicing: kata -> kada\nTracing pa\n";
+        assert_eq!(
+            parse_lexurgy_trace(headed, &["kata", "pa", "kata"]),
+            [vec!["voicing", "apocope"], vec![], vec!["voicing"]]
+        );
+        // Without headers the changes are followed from word to word
+        let plain = "Running rules...\nvoicing to kata: kata => kada\nlenition: pa => fa\n\
+                     apocope: kada => kad\n";
+        let trace = parse_lexurgy_trace(plain, &["kata", "pa"]);
+        assert_eq!(trace, [vec!["voicing", "apocope"], vec!["lenition"]]);
 
-        Ok(())
+        let tokens = ["kata".to_string(), "pa".to_string(), "ti".to_string()];
+        let trace = [trace[0].clone(), trace[1].clone(), vec![]];
+        assert_eq!(
+            format_trace(&tokens, &trace),
+            ["kata     pa        ti", "voicing  lenition  -", "apocope"]
+        );
     }
 
-    fn list(&mut self) -> Result<()> {
-        println!("Languages:");
-        for entry in self.get_langs()? {
-            let words: u32 = self.db.query_row(
-                "SELECT COUNT(id) FROM words WHERE lang = ?",
-                [&entry.id],
-                |row| row.get(0),
-            )?;
-            println!(" {}: {} ({} words)", entry.id, entry.name, words);
-        }
-        Ok(())
+    #[test]
+    fn test_validate_lexicon() {
+        let (wdb, dir) = test_family_with_rules(
+            "validate-lexicon",
+            "Symbol tʃ\nDeromanizer:\n    ch => tʃ\n    a => a\n",
+        );
+        add_word(&wdb, "mid", "cha", "tea", "n");
+        add_word(&wdb, "mid", "qax", "thing", "n");
+        // Without a rule file, the proto-language isn't checked
+        add_word(&wdb, "pro", "zzz", "sleep", "v");
+        assert_eq!(wdb.validate_lexicon().unwrap(), ["mid: qax: q, x"]);
+        assert!(wdb.validate().is_err());
+
+        fs::write(dir.join("rules").join("pro.lsc"), "Class cons {z}\n").unwrap();
+        fs::write(dir.join("rules").join("mid.lsc"), "init:\n    unchanged\n").unwrap();
+        assert!(wdb.validate_lexicon().unwrap().is_empty());
+        wdb.validate().unwrap();
     }
 
-    fn add(&mut self, args: AddArgs) -> Result<()> {
-        println!("{:?}", args);
-        let lang = self.get_lang(&args.language)?;
-        let rom = normalize_text(&args.word);
-        // Make sure there isn't already another word in the db if it's not supposed to be a homophone
-        if !args.homophone {
-            let mut stmt = self
+    #[test]
+    fn test_validate_rules() {
+        let (wdb, dir) = test_family_with_rules("validate-rules", "init:\n    unchanged\n");
+        let pro = dir.join("rules").join("pro.lsc");
+        assert_eq!(
+            wdb.validate_rules().unwrap(),
+            [format!("pro: Missing rule file {}", pro.display())]
+        );
+        assert!(wdb.check().is_err());
+
+        fs::write(&pro, "Deromanizer:\n    a =>\n").unwrap();
+        let problems = wdb.validate_rules().unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("pro: Failed to parse"),
+            "{}",
+            problems[0]
+        );
+
+        fs::write(&pro, "Deromanizer:\n    unchanged\n").unwrap();
+        assert!(wdb.validate_rules().unwrap().is_empty());
+        wdb.check().unwrap();
+    }
+
+    #[test]
+    fn test_pronunciations() {
+        let (mut wdb, _dir) = test_family_with_rules(
+            "pron",
+            "Deromanizer:\n    c => k\n    sh => ʃ\ninit:\n    unchanged\nvoicing:\n    k => g\n",
+        );
+        wdb.cfg.native = true;
+        let lang = wdb.get_lang("mid").unwrap();
+        let words = ["caca", "shac"].map(String::from);
+        assert_eq!(wdb.pronunciations(&lang, &words).unwrap(), ["kaka", "ʃak"]);
+        // Nothing's added
+        assert!(wdb.homophones(&lang, "caca").unwrap().is_empty());
+        assert!(Cli::try_parse_from(["wdb", "pron", "mid"]).is_err());
+    }
+
+    #[test]
+    fn test_sc() {
+        let (mut wdb, _dir) = test_family_with_rules(
+            "sc",
+            "Deromanizer:\n    c => k\n\
+             init:\n    unchanged\n\
+             voicing:\n    k => g / a _ a\n\
+             Romanizer:\n    g => gh\n",
+        );
+        wdb.cfg.native = true;
+        let args = |input: &str, output: &str| {
+            let cli = Cli::try_parse_from([
+                "wdb", "sc", "mid", "caca", "kac", "--input", input, "--output", output,
+            ])?;
+            let Some(Command::Sc(args)) = cli.command else {
+                panic!("Expected an sc command");
+            };
+            Ok::<_, clap::Error>(args)
+        };
+        let run = |input, output| wdb.sc_words(&args(input, output).unwrap()).unwrap();
+        assert_eq!(run("romanized", "romanized"), ["kagha", "kak"]);
+        assert_eq!(run("romanized", "both"), ["kaga => kagha", "kak => kak"]);
+        assert_eq!(run("phonetic", "phonetic"), ["caca", "kac"]);
+        assert!(args("ipa", "both").is_err());
+        assert!(args("phonetic", "rom").is_err());
+    }
+
+    #[test]
+    fn test_rule_override() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "rule-override",
+            "Deromanizer:\n    c => k\ninit:\n    unchanged\n",
+        );
+        wdb.cfg.native = true;
+        fs::write(
+            dir.join("rules").join("draft.lsc"),
+            "Deromanizer:\n    c => tʃ\ninit:\n    unchanged\n",
+        )
+        .unwrap();
+        let absolute = dir.join("absolute.lsc");
+        fs::write(
+            &absolute,
+            "Deromanizer:\n    c => s\ninit:\n    unchanged\n",
+        )
+        .unwrap();
+        let sc = |rule: Option<&str>| {
+            let mut cmd = vec!["wdb", "sc", "mid", "ca", "--input", "romanized"];
+            cmd.extend(["--output", "phonetic"]);
+            cmd.extend(rule.map(|r| ["--rule", r]).into_iter().flatten());
+            let Some(Command::Sc(args)) = Cli::try_parse_from(cmd).unwrap().command else {
+                panic!("Expected an sc command");
+            };
+            wdb.sc_words(&args).unwrap()
+        };
+        assert_eq!(sc(None), ["ka"]);
+        assert_eq!(sc(Some("draft")), ["tʃa"]);
+        assert_eq!(sc(Some("draft.lsc")), ["tʃa"]);
+        assert_eq!(sc(Some(absolute.to_str().unwrap())), ["sa"]);
+        assert_eq!(wdb.get_lang("mid").unwrap().rule, "mid");
+
+        add_word(&wdb, "mid", "cu", "dog", "n");
+        let args = PhonArgs {
+            language: Some("mid".to_string()),
+            force: true,
+            jobs: Some(1),
+            only: vec![],
+            rule: Some("draft".to_string()),
+            sample: None,
+            yes: false,
+        };
+        let batches = wdb.phon_batches(&args).unwrap();
+        assert_eq!(batches[0].0.rule, "draft");
+        wdb.deromanize(args, &mut io::empty()).unwrap();
+        let word = wdb.try_get_unique_word(&wdb.get_lang("mid").unwrap(), "cu");
+        assert_eq!(word.unwrap().unwrap().ipa.as_deref(), Some("tʃu"));
+        assert!(Cli::try_parse_from(["wdb", "phon", "--rule", "draft"]).is_err());
+    }
+
+    #[test]
+    fn test_run_parallel() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let items = (0..12).collect::<Vec<usize>>();
+        let max_running = |jobs| {
+            let (running, max) = (AtomicUsize::new(0), AtomicUsize::new(0));
+            let results = run_parallel(jobs, &items, |i| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                i * 2
+            });
+            // Whichever thread got to an item, the results keep the order of the items
+            assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+            max.into_inner()
+        };
+        assert_eq!(max_running(1), 1);
+        let max = max_running(3);
+        assert!((2..=3).contains(&max), "{}", max);
+        assert!(run_parallel(4, &[] as &[usize], |i| *i).is_empty());
+    }
+
+    #[test]
+    fn test_phon_jobs() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "phon-jobs",
+            "Deromanizer:\n    c => k\ninit:\n    unchanged\n",
+        );
+        fs::write(
+            dir.join("rules").join("pro.lsc"),
+            "Deromanizer:\n    c => s\ninit:\n    unchanged\n",
+        )
+        .unwrap();
+        wdb.cfg.native = true;
+        add_word(&wdb, "pro", "ca", "fish", "n");
+        add_word(&wdb, "mid", "ca", "fish", "n");
+        add_word(&wdb, "mid", "cu", "dog", "n");
+        let cli = Cli::try_parse_from(["wdb", "phon", "-f", "--jobs", "2"]).unwrap();
+        let Some(Command::Phon(args)) = cli.command else {
+            panic!("Expected a phon command");
+        };
+        assert_eq!(args.jobs, Some(2));
+        wdb.deromanize(args, &mut io::empty()).unwrap();
+        // Both languages ran at once, each with its own rules
+        let mut stmt = wdb
+            .db
+            .prepare("SELECT lang, ipa FROM words ORDER BY id")
+            .unwrap();
+        let ipas = stmt
+            .query_map([], |row| {
+                Ok(format!(
+                    "{} {}",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(ipas, ["pro sa", "mid ka", "mid ku"]);
+    }
+
+    #[test]
+    fn test_phon_sample() {
+        let (mut wdb, _dir) = test_family_with_rules(
+            "phon-sample",
+            "Deromanizer:\n    c => k\ninit:\n    unchanged\n",
+        );
+        wdb.cfg.native = true;
+        add_word(&wdb, "mid", "ca", "fish", "n");
+        add_word(&wdb, "mid", "cu", "dog", "n");
+        let phon = |wdb: &mut Wdb, extra: &[&str], answer: &str| {
+            let cmd = ["wdb", "phon", "-l", "mid", "-f", "--sample", "1"];
+            let cli = Cli::try_parse_from(cmd.iter().chain(extra)).unwrap();
+            let Some(Command::Phon(args)) = cli.command else {
+                panic!("Expected a phon command");
+            };
+            wdb.deromanize(args, &mut answer.as_bytes()).unwrap();
+            let mut stmt = wdb
                 .db
-                .prepare("SELECT * FROM words WHERE romanization = ? AND lang = ?")?;
-            let homophones: Vec<_> = stmt
-                .query_map([&rom, &lang.id], WordEntry::from_row)?
-                .collect::<Result<_, _>>()?;
-            if !homophones.is_empty() {
-                use std::fmt::Write;
-                let mut err_msg =
-                format!("Error adding word `{}` to language {}. \n\nThe following homophone(s) exist already:\n", &rom, lang);
-                for h in homophones {
-                    write!(
-                        &mut err_msg,
-                        " - {}: {}, {}\n",
-                        h.romanization, h.meaning, h.kind
-                    )?;
-                }
+                .prepare("SELECT ipa FROM words WHERE lang = 'mid' ORDER BY id")
+                .unwrap();
+            stmt.query_map([], |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        // Declining leaves every word as it was
+        assert_eq!(phon(&mut wdb, &[], "n\n"), ["ca", "cu"]);
+        assert_eq!(phon(&mut wdb, &[], ""), ["ca", "cu"]);
+        assert_eq!(phon(&mut wdb, &[], "y\n"), ["ka", "ku"]);
 
-                write!(
-                    &mut err_msg,
-                    "\nIf you want to add it as a homophone, use the -H flag."
-                )?;
-                bail!(err_msg);
-            }
-        }
+        wdb.db
+            .execute("UPDATE words SET ipa = romanization", [])
+            .unwrap();
+        assert_eq!(phon(&mut wdb, &["--yes"], ""), ["ka", "ku"]);
+        assert!(Cli::try_parse_from(["wdb", "phon", "--yes"]).is_err());
+    }
 
-        let mut phon: Option<String> = args.ipa;
+    #[test]
+    fn test_native_engine() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "native",
+            "Deromanizer:\n    c => k\n\
+             init:\n    unchanged\n\
+             voicing:\n    k => g / a _ a\n\
+             Romanizer:\n    g => gh\n",
+        );
+        let lang = wdb.get_lang("mid").unwrap();
+        let run = |wdb: &Wdb, cmd: LexurgyCmd| {
+            cmd.run(&wdb.cfg, ["caca", "kac"].into_iter())
+                .unwrap()
+                .into_iter()
+                .map(|o| match o {
+                    WordOutput::PhonRom(p, r) => format!("{} {}", p, r),
+                    o => o.get_value().unwrap(),
+                })
+                .collect::<Vec<_>>()
+        };
+        let runs = |wdb: &Wdb| {
+            [
+                run(wdb, LexurgyCmd::deromanize(&lang)),
+                run(
+                    wdb,
+                    LexurgyCmd::evolve(&lang, LexurgyInput::Phonetic, LexurgyOutput::Phonetic),
+                ),
+                run(
+                    wdb,
+                    LexurgyCmd::evolve(&lang, LexurgyInput::Romanized, LexurgyOutput::Romanized),
+                ),
+            ]
+        };
 
-        if phon.is_none() && !args.disable_autorom {
-            println!("Reromanization...");
-            let mut phons =
-                LexurgyCmd::deromanize(&lang).run(&self.cfg, std::iter::once(&rom[..]))?;
-            if phons.len() != 1 {
-                bail!("expected a single word back, got {}", phons.len());
-            }
-            let p = phons.remove(0).get_phon()?;
-            println!("  {} => {}", &rom, &p);
-            phon = Some(p);
-        }
+        wdb.cfg.native = true;
+        let native = runs(&wdb);
+        assert_eq!(
+            native,
+            [
+                vec!["kaka", "kak"],
+                vec!["caca", "kac"],
+                vec!["kagha", "kak"]
+            ]
+        );
+        let mut both = LexurgyCmd::evolve(&lang, LexurgyInput::Phonetic, LexurgyOutput::Both);
+        both.start_at = Some("voicing".to_string());
+        assert_eq!(run(&wdb, both), ["caca caca", "kac kac"]);
 
-        let _ = self.db.execute(
-            "INSERT INTO words
-               (lang, romanization, ipa, meaning, kind, note, origin, flags)
-               VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-            params![
-                &lang.id,
-                &rom,
-                &phon,
-                &args.meaning,
-                &normalize_text(&args.kind),
-                &args.note.unwrap_or(String::new()),
-                &args.origin.unwrap_or(String::new()),
-                "",
-            ],
-        )?;
-        println!("Added `{}` to {}", &args.word, lang);
-        if self.cfg.settings.auto_dump {
-            self.dump(DumpArgs {
-                language: args.language,
-            })?;
+        let settings: Settings =
+            toml::from_str(&format!("{}engine = \"native\"\n", TEST_SETTINGS)).unwrap();
+        assert!(Config::new(dir.to_path_buf(), settings, false).native);
+
+        // Only comparable where Lexurgy is installed
+        wdb.cfg.native = false;
+        if process::Command::new("lexurgy").output().is_ok() {
+            assert_eq!(runs(&wdb), native);
         }
-        Ok(())
     }
 
-    fn try_get_unique_word(&self, lang: &LangEntry, mut rom: &str) -> Result<Option<WordEntry>> {
-        use std::fmt::Write;
-        let mut index = None;
+    #[test]
+    fn test_random_words() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_word(&wdb, "pro", "ka", "fish", "n");
+        let lang = wdb.get_lang("pro").unwrap();
+        let ast = sc::parser::lsc::lsc_file("Syllables:\n    {k, t} :: a\n").unwrap();
+        let gen = sc::generate::WordGenerator::from_ast(&ast).unwrap();
 
-        if let Some((r, i)) = rom.split_once('#') {
-            index = Some(i.parse::<usize>()?);
-            rom = r;
+        let words = wdb.random_words(&lang, &gen, 100, &mut Rng(12345)).unwrap();
+        // Every word of up to three syllables gets found, except for the existing one
+        assert_eq!(words.len(), 2 + 4 + 8 - 1, "{:?}", words);
+        assert!(!words.contains(&"ka".to_string()));
+        for (i, w) in words.iter().enumerate() {
+            assert!(!words[i + 1..].contains(w), "{:?}", words);
+            assert!(w.len() % 2 == 0 && w.len() <= 6, "{:?}", words);
         }
+    }
 
-        let mut stmt = self.db.prepare(
-            "SELECT * FROM words WHERE lang = ? AND romanization = ? ORDER BY romanization",
-        )?;
-        let mut words = stmt
-            .query_map(params!(&lang.id, &rom), WordEntry::from_row)?
-            .collect::<Result<Vec<_>, _>>()?;
+    #[test]
+    fn test_random_seed() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let lang = wdb.get_lang("pro").unwrap();
+        let ast =
+            sc::parser::lsc::lsc_file("Syllables:\n    {p, t, k, m, n} :: {a, i, u}\n").unwrap();
+        let gen = sc::generate::WordGenerator::from_ast(&ast).unwrap();
+        let run = |seed| {
+            wdb.random_words(&lang, &gen, 10, &mut Rng::from_seed(seed))
+                .unwrap()
+        };
 
-        if words.is_empty() {
-            bail!("No matching words found");
-        }
+        assert_eq!(run(7), run(7));
+        assert_eq!(run(0), run(0));
+        assert_ne!(run(7), run(8));
+    }
 
-        if let Some(index) = index {
-            if index >= words.len() {
-                let mut err_msg = format!("Index `{}` out of bounds for homophone list:", index);
-                for (i, h) in words.iter().enumerate() {
-                    write!(
-                        &mut err_msg,
-                        " {}:  {}: {}, {}\n",
-                        i, h.romanization, h.meaning, h.kind
-                    )?;
-                }
-                bail!(err_msg);
-            }
-            return Ok(Some(words.swap_remove(index)));
-        }
+    #[test]
+    fn test_validate_kind() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let args = |word: &str, kind: &str, force_kind| AddArgs {
+            word: Some(word.to_string()),
+            meaning: Some("fish".to_string()),
+            kind: Some(kind.to_string()),
+            force_kind,
+            ..add_args("pro")
+        };
+        let edit = |kind: &str, force_kind| EditArgs {
+            kind: Some(kind.to_string()),
+            force_kind,
+            ..edit_args("pro", "ka")
+        };
+        let kind = |wdb: &Wdb| {
+            let lang = wdb.get_lang("pro").unwrap();
+            wdb.try_get_unique_word(&lang, "ka").unwrap().unwrap().kind
+        };
 
-        if words.len() == 1 {
-            return Ok(Some(words.swap_remove(0)));
-        }
+        wdb.add_word(args("ka", "n", false), None).unwrap();
+        let err = wdb.add_word(args("ta", "noun", false), None).unwrap_err();
+        assert!(
+            err.to_string().starts_with(
+                "Unknown kind `noun`, expected one of: v, n, adv, adj, inj, conj, adp"
+            ),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains("--force-kind"), "{}", err);
+        wdb.add_word(args("ta", "noun", true), None).unwrap();
 
-        let mut err_msg = format!("`{0}` has homophones, please specify by passing `{0}#N` where N is one of the following indices:\n", &rom);
-        for (i, h) in words.iter().enumerate() {
-            write!(
-                &mut err_msg,
-                " {}:  {}: {}, {}\n",
-                i, h.romanization, h.meaning, h.kind
-            )?;
+        wdb.edit(edit("v", false)).unwrap();
+        assert_eq!(kind(&wdb), "v");
+        assert!(wdb.edit(edit("verb", false)).is_err());
+        assert_eq!(kind(&wdb), "v");
+        wdb.edit(edit("verb", true)).unwrap();
+        assert_eq!(kind(&wdb), "verb");
+
+        let words = parse_word_lines("pa\tgo\tv\nna\tsee\tvv\n".as_bytes()).unwrap();
+        let err = wdb.add_batch(add_args("pro"), words).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Adding `na`: Unknown kind `vv`"));
+        assert!(wdb
+            .homophones(&wdb.get_lang("pro").unwrap(), "pa")
+            .unwrap()
+            .is_empty());
+
+        wdb.cfg.settings.kinds = vec!["noun".to_string()];
+        assert!(wdb.cfg.settings.validate_kind("noun", false).is_ok());
+        assert!(wdb.cfg.settings.validate_kind("n", false).is_err());
+    }
+
+    #[test]
+    fn test_require_meaning_and_kind() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "require",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\n",
+        );
+        wdb.cfg.native = true;
+        fs::copy(
+            dir.join("rules").join("mid.lsc"),
+            dir.join("rules").join("pro.lsc"),
+        )
+        .unwrap();
+        let args = |word: &str, meaning: &str, kind: &str| AddArgs {
+            word: Some(word.to_string()),
+            meaning: Some(meaning.to_string()),
+            kind: Some(kind.to_string()),
+            force_kind: true,
+            disable_autorom: false,
+            ..add_args("pro")
+        };
+        // Lenient by default
+        wdb.add_word(args("ka", " ", ""), None).unwrap();
+
+        wdb.cfg.settings.require_meaning = true;
+        let err = wdb.add_word(args("ta", " ", "n"), None).unwrap_err();
+        assert!(err.to_string().contains("require_meaning"), "{}", err);
+        wdb.cfg.settings.require_kind = true;
+        let err = wdb.add_word(args("ta", "fish", ""), None).unwrap_err();
+        assert!(err.to_string().contains("require_kind"), "{}", err);
+        wdb.add_word(args("ta", "fish", "n"), None).unwrap();
+
+        let words = parse_word_lines("pa\tgo\tv\nna\t\tv\n".as_bytes()).unwrap();
+        let err = wdb.add_batch(add_args("pro"), words).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Adding `na`: Words need a meaning"));
+
+        let inherit = |word: &str, meaning: Option<&str>| InheritArgs {
+            meaning: meaning.map(|m| m.to_string()),
+            kind: Some("n".to_string()),
+            ..inherit_args("mid", word)
+        };
+        let err = wdb.inherit(inherit("ka", None)).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Inheriting `ka`: Words need a meaning"));
+        assert!(wdb
+            .homophones(&wdb.get_lang("mid").unwrap(), "ka")
+            .unwrap()
+            .is_empty());
+        wdb.inherit(inherit("ka", Some("eel"))).unwrap();
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "rebuild",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        for rom in ["ka", "ta", "tu"] {
+            add_word(&wdb, "pro", rom, rom, "n");
         }
-        bail!(err_msg);
+        wdb.inherit(inherit_args("mid", "*")).unwrap();
+        let coined = add_word(&wdb, "mid", "ti", "tea", "n");
+        let mid = wdb.get_lang("mid").unwrap();
+        let da = wdb.try_get_unique_word(&mid, "da").unwrap().unwrap();
+        wdb.db
+            .execute("UPDATE words SET meaning = 'that' WHERE id = ?", [da.id])
+            .unwrap();
+        wdb.db
+            .execute(
+                "DELETE FROM words WHERE lang = 'pro' AND romanization = 'tu'",
+                [],
+            )
+            .unwrap();
+
+        fs::write(
+            dir.join("rules").join("mid.lsc"),
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nlenition:\n    t => s\n",
+        )
+        .unwrap();
+        wdb.rebuild(RebuildArgs {
+            language: "mid".to_string(),
+        })
+        .unwrap();
+
+        let mut stmt = wdb
+            .db
+            .prepare("SELECT * FROM words WHERE lang = 'mid' ORDER BY id")
+            .unwrap();
+        let words = stmt
+            .query_map([], WordEntry::from_row)
+            .unwrap()
+            .map(|w| {
+                let w = w.unwrap();
+                format!("{} {} {}", w.romanization, w.ipa.unwrap(), w.meaning)
+            })
+            .collect::<Vec<_>>();
+        // The word whose source is gone and the word that wasn't inherited stay the same
+        assert_eq!(words, ["ka ka ka", "sa sa that", "du du tu", "ti ti tea"]);
+        let fields = wdb
+            .word_history(da.id)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.field)
+            .collect::<Vec<_>>();
+        // Inheriting it logged its creation
+        assert_eq!(fields, ["romanization", "romanization", "ipa"]);
+        assert!(wdb.word_history(coined).unwrap().is_empty());
+        assert_eq!(
+            inherited_source("(inherited pro ka na 12) loan"),
+            Some(("pro", 12))
+        );
+        assert_eq!(inherited_source("(inherited pro ka)"), None);
     }
 
-    fn edit(&mut self, args: EditArgs) -> Result<()> {
-        use rusqlite::ToSql;
-        use std::fmt::Write;
-        let lang = self.get_lang(&args.language)?;
-        let rom = normalize_text(&args.word);
-        if let Some(entry) = self.try_get_unique_word(&lang, &rom)? {
-            let mut changed = format!("Changed the following for `{}`:\n", rom);
-            let mut query_str = "UPDATE words SET ".to_string();
-            let mut first = true;
-            let fields = &[
-                ("meaning", Some(&entry.meaning), &args.meaning),
-                ("kind", Some(&entry.kind), &args.kind),
-                ("origin", entry.origin.as_ref(), &args.origin),
-                ("note", entry.note.as_ref(), &args.note),
-            ];
-            for (fld, old, val) in fields {
-                if let Some(v) = val {
-                    if !first {
-                        query_str.push_str(", ");
-                    }
-                    write!(&mut query_str, "{} = ?", fld)?;
-                    write!(
-                        &mut changed,
-                        " {}: {} => {}\n",
-                        fld,
-                        old.unwrap_or(&"<unset>".to_string()),
-                        v
-                    )?;
-                }
-                first = false;
-            }
-            query_str.push_str(" WHERE id = ?");
-            let vs = fields
-                .iter()
-                .filter_map(|(_, _, val)| val.as_ref().map(|v| v.to_sql()))
-                .chain(iter::once(entry.id.to_sql()))
-                .collect::<Result<Vec<_>, _>>()?;
-            let _ = self
-                .db
-                .execute(&query_str[..], rusqlite::params_from_iter(vs.iter()))?;
-            println!("{}", changed);
-            if self.cfg.settings.auto_dump {
-                self.dump(DumpArgs {
-                    language: args.language,
-                })?;
-            }
+    #[test]
+    fn test_inherit_regular_only() {
+        let (mut wdb, _dir) = test_family_with_rules(
+            "inherit-regular",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        for rom in ["ta", "to", "tu"] {
+            add_word(&wdb, "pro", rom, rom, "n");
         }
-        Ok(())
+        wdb.db
+            .execute(
+                "UPDATE words SET flags = 'old, noinherit' WHERE romanization = 'to'",
+                [],
+            )
+            .unwrap();
+        let args = |word: &str| InheritArgs {
+            regular_only: true,
+            ..inherit_args("mid", word)
+        };
+        wdb.inherit(args("*")).unwrap();
+        let mid = wdb.get_lang("mid").unwrap();
+        assert_eq!(wdb.word_count("mid").unwrap(), 2);
+        assert!(wdb.homophones(&mid, "do").unwrap().is_empty());
+        assert_eq!(wdb.homophones(&mid, "du").unwrap().len(), 1);
+
+        // Naming the word doesn't get around the tag either
+        wdb.inherit(args("to")).unwrap();
+        assert_eq!(wdb.word_count("mid").unwrap(), 2);
+        wdb.inherit(inherit_args("mid", "to")).unwrap();
+        assert_eq!(wdb.homophones(&mid, "do").unwrap().len(), 1);
     }
 
-    fn del(&mut self, args: DelArgs) -> Result<()> {
-        let lang = self.get_lang(&args.language)?;
-        let rom = normalize_text(&args.word);
-        if let Some(entry) = self.try_get_unique_word(&lang, &rom)? {
-            let _ = self
-                .db
-                .execute("DELETE FROM words WHERE id = ?", [entry.id])?;
-            println!(
-                "Deleted: {}: {} ({})",
-                entry.romanization, entry.meaning, entry.kind
-            );
-            if self.cfg.settings.auto_dump {
-                self.dump(DumpArgs {
-                    language: args.language,
-                })?;
-            }
+    #[test]
+    fn test_inherit_map() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "inherit-map",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        for (rom, meaning, kind) in [("ka", "fish", "n"), ("ta", "go", "v"), ("na", "see", "v")] {
+            add_word(&wdb, "pro", rom, meaning, kind);
         }
-        Ok(())
+        let map = dir.join("map.toml");
+        fs::write(
+            &map,
+            "[ta]\nmeaning = \"walk\"\nnote = \"narrowed\"\n\n[ka]\nkind = \"adj\"\n\n[pa]\nmeaning = \"x\"\n",
+        )
+        .unwrap();
+        let args = InheritArgs {
+            map: Some(map.clone()),
+            ..inherit_args("mid", "*")
+        };
+        wdb.inherit(args).unwrap();
+        let mid = wdb.get_lang("mid").unwrap();
+        let word = |rom| {
+            let w = wdb.try_get_unique_word(&mid, rom).unwrap().unwrap();
+            (w.meaning, w.kind, w.note.unwrap_or_default())
+        };
+        let fields = |m: &str, k: &str, n: &str| (m.to_string(), k.to_string(), n.to_string());
+        assert_eq!(word("ka"), fields("fish", "adj", ""));
+        assert_eq!(word("da"), fields("walk", "v", "narrowed"));
+        assert_eq!(word("na"), fields("see", "v", ""));
+
+        fs::write(&map, "[ka]\nkind = \"noun\"\n").unwrap();
+        let err = wdb
+            .inherit(InheritArgs {
+                map: Some(map),
+                ..inherit_args("mid", "ka")
+            })
+            .unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Inheriting `ka`: Unknown kind `noun`"));
+    }
+
+    #[test]
+    fn test_inherit_from_ancestor() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "inherit-ancestor",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        fs::write(
+            dir.join("rules").join("new.lsc"),
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nrhotacism:\n    d => r\n",
+        )
+        .unwrap();
+        add_lang(&wdb, "new", Some("mid"));
+        let ta = add_word(&wdb, "pro", "ta", "that", "n");
+        let inherit = |from: Option<&str>| InheritArgs {
+            from: from.map(str::to_string),
+            show_intermediate: true,
+            ..inherit_args("new", "ta")
+        };
+        // Without `--from` only the parent is looked at
+        assert!(wdb.inherit(inherit(None)).is_err());
+        wdb.inherit(inherit(Some("pro"))).unwrap();
+
+        let new = wdb.get_lang("new").unwrap();
+        let word = wdb.try_get_unique_word(&new, "ra").unwrap().unwrap();
+        assert_eq!(word.ipa.as_deref(), Some("ra"));
+        assert_eq!(word.origin, Some(format!("(inherited pro ta {})", ta)));
+        assert!(wdb.inherit(inherit(Some("new"))).is_err());
+    }
+
+    #[test]
+    fn test_rebuild_from_ancestor() {
+        let (mut wdb, dir) = test_family_with_rules(
+            "rebuild-ancestor",
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nvoicing:\n    t => d\n",
+        );
+        wdb.cfg.native = true;
+        fs::write(
+            dir.join("rules").join("new.lsc"),
+            "Deromanizer:\n    unchanged\ninit:\n    unchanged\nrhotacism:\n    d => r\n",
+        )
+        .unwrap();
+        add_lang(&wdb, "new", Some("mid"));
+        let ta = add_word(&wdb, "pro", "ta", "that", "n");
+        wdb.inherit(InheritArgs {
+            from: Some("pro".to_string()),
+            ..inherit_args("new", "ta")
+        })
+        .unwrap();
+
+        wdb.db
+            .execute("UPDATE words SET ipa = 'tu' WHERE id = ?", [ta])
+            .unwrap();
+        wdb.rebuild(RebuildArgs {
+            language: "new".to_string(),
+        })
+        .unwrap();
+        // Evolved through `mid` again on the way
+        let new = wdb.get_lang("new").unwrap();
+        let word = wdb.try_get_unique_word(&new, "ru").unwrap().unwrap();
+        assert_eq!(word.ipa.as_deref(), Some("ru"));
+        assert_eq!(word.origin, Some(format!("(inherited pro ta {})", ta)));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let id = add_word(&wdb, "pro", "kana", "fish", "n");
+        let edit = |meaning: Option<&str>, note: Option<&str>| EditArgs {
+            meaning: meaning.map(String::from),
+            note: note.map(String::from),
+            ..edit_args("pro", "kana")
+        };
+        wdb.edit(edit(Some("river fish"), None)).unwrap();
+        wdb.edit(edit(Some("eel"), None)).unwrap();
+
+        let changes = wdb
+            .word_history(id)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.field, c.old, c.new))
+            .collect::<Vec<_>>();
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(
+            changes,
+            [
+                ("meaning".to_string(), some("fish"), some("river fish")),
+                ("meaning".to_string(), some("river fish"), some("eel")),
+            ]
+        );
+        assert!(wdb.word_history(id + 1).unwrap().is_empty());
     }
 
-    fn evolve(&mut self, args: EvolveArgs) -> Result<()> {
-        let langs = self.get_langs()?;
-        let from = langs
-            .iter()
-            .find(|l| l.id == args.from_lang)
-            .ok_or(anyhow!("No such 'from' language: `{}`", args.from_lang))?;
-        let to = langs
-            .iter()
-            .find(|l| l.id == args.to_lang)
-            .ok_or(anyhow!("No such 'to' language: `{}`", args.to_lang))?;
+    #[test]
+    fn test_edit_rename() {
+        let (mut wdb, _dir) =
+            test_family_with_rules("rename", "Deromanizer:\n    c => k\ninit:\n    unchanged\n");
+        wdb.cfg.native = true;
+        let id = add_word(&wdb, "mid", "kana", "fish", "n");
+        add_word(&wdb, "mid", "tana", "tree", "n");
+        let edit = |rename: &str| EditArgs {
+            meaning: Some("eel".to_string()),
+            rename: Some(rename.to_string()),
+            disable_autorom: false,
+            ..edit_args("mid", "kana")
+        };
+
+        // The homophone guard checks the new spelling, leaving the word untouched
+        let err = wdb.edit(edit("tana")).unwrap_err().to_string();
+        assert!(err.contains("use the -H flag"), "{}", err);
+        assert!(wdb.word_history(id).unwrap().is_empty());
 
-        if from.id == to.id {
-            bail!("'from' and 'to' language are the same. Nothing to evolve");
-        }
+        wdb.edit(edit("cana")).unwrap();
+        let lang = wdb.get_lang("mid").unwrap();
+        let word = wdb.try_get_unique_word(&lang, "cana").unwrap().unwrap();
+        assert_eq!(word.id, id);
+        assert_eq!(word.ipa.as_deref(), Some("kana"));
+        assert_eq!(word.meaning, "eel");
+        assert!(wdb.try_get_unique_word(&lang, "kana").is_err());
 
-        let mut steps = vec![];
-        let mut l = to;
-        while let Some(ref l_id) = l.origin {
-            steps.push(l);
+        let changes = wdb.word_history(id).unwrap();
+        let fields = changes.iter().map(|c| &c.field[..]).collect::<Vec<_>>();
+        assert_eq!(fields, ["romanization", "ipa", "meaning"]);
+        assert_eq!(changes[0].new.as_deref(), Some("cana"));
+        // Logged by the same edit
+        assert_eq!(changes[0].timestamp, changes[2].timestamp);
 
-            l = langs.iter().find(|l| &l.id == l_id).ok_or(anyhow!(
-                "Internal Error! Language {}({}) has an invalid origin language: `{}`",
-                l.name,
-                l.id,
-                l_id
-            ))?;
+        // Renaming to the current spelling leaves nothing to change
+        let mut same = edit("cana");
+        same.word = "cana".to_string();
+        same.meaning = None;
+        wdb.edit(same).unwrap();
+        assert_eq!(wdb.word_history(id).unwrap().len(), 3);
+    }
 
-            if l_id == &from.id {
-                break;
-            }
-        }
+    #[test]
+    fn test_swap() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let first = add_word(&wdb, "pro", "ka", "fish", "n");
+        let second = add_word(&wdb, "pro", "ka", "to see", "v");
+        wdb.db
+            .execute(
+                "UPDATE words SET note = 'scaly', origin = 'onomatopoeia' WHERE id = ?",
+                [first],
+            )
+            .unwrap();
+        let swap = |a, b| SwapArgs {
+            language: "pro".to_string(),
+            word: "ka".to_string(),
+            a,
+            b,
+        };
+        assert!(wdb.swap(swap(0, 0)).is_err());
+        assert!(wdb.swap(swap(0, 2)).is_err());
+        wdb.swap(swap(1, 0)).unwrap();
 
-        if l.id != from.id {
-            bail!(
-                "{}({}) is not a descendent of {}({})!",
-                to.name,
-                to.id,
-                from.name,
-                from.id
-            );
-        }
+        let word = |id| {
+            wdb.db
+                .query_row(
+                    "SELECT * FROM words WHERE id = ?",
+                    [id],
+                    WordEntry::from_row,
+                )
+                .unwrap()
+        };
+        let (a, b) = (word(first), word(second));
+        assert_eq!((&a.romanization[..], &b.romanization[..]), ("ka", "ka"));
+        assert_eq!((&a.meaning[..], &a.kind[..]), ("to see", "v"));
+        assert_eq!(
+            (a.note.as_deref(), a.origin.as_deref()),
+            (Some(""), Some(""))
+        );
+        assert_eq!((&b.meaning[..], &b.kind[..]), ("fish", "n"));
+        assert_eq!(b.note.as_deref(), Some("scaly"));
+        assert_eq!(b.origin.as_deref(), Some("onomatopoeia"));
+        let fields = wdb
+            .word_history(first)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.field)
+            .collect::<Vec<_>>();
+        assert_eq!(fields, ["meaning", "kind", "note", "origin"]);
+    }
 
-        let mut tokens = vec![];
-        for sentence_fragment in args.sentence {
-            tokens.extend(sentence_fragment.split(' ').map(|f| f.replace('-', " ")));
-        }
+    #[test]
+    fn test_edit_origin() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        let id = add_word(&wdb, "mid", "kana", "fish", "n");
+        wdb.db
+            .execute(
+                "UPDATE words SET origin = '(inherited pro kana 1)' WHERE id = ?",
+                [id],
+            )
+            .unwrap();
+        let edit = |f: &dyn Fn(&mut EditArgs)| {
+            let mut args = edit_args("mid", "kana");
+            f(&mut args);
+            args
+        };
+        let origin = |wdb: &Wdb| {
+            let lang = wdb.get_lang("mid").unwrap();
+            wdb.try_get_unique_word(&lang, "kana")
+                .unwrap()
+                .unwrap()
+                .origin
+        };
 
-        let mut first = true;
-        for step in steps.iter().rev() {
-            let last = step.id == to.id;
-            let mut cmd = LexurgyCmd::evolve(
-                step,
-                if first {
-                    LexurgyInput::Romanized
-                } else {
-                    LexurgyInput::Phonetic
-                },
-                if last {
-                    if args.show_phonetic {
-                        LexurgyOutput::Phonetic
-                    } else {
-                        LexurgyOutput::Romanized
-                    }
-                } else {
-                    LexurgyOutput::Phonetic
-                },
-            );
-            if last {
-                cmd.stop_before = args.stop_before.clone();
-            }
-            if first {
-                cmd.start_at = args.start_at.clone();
-            }
-            let new_tokens = cmd.run(&self.cfg, tokens.iter().map(|x| &x[..]))?;
-            tokens.clear();
-            for tok in new_tokens {
-                tokens.push(tok.get_value()?);
-            }
-            first = false;
-            if last || args.show_intermediate {
-                print!("{}: ", step.id);
-                for tok in &tokens {
-                    print!("{} ", tok);
-                }
-                println!();
-            }
-        }
+        let err = wdb
+            .edit(edit(&|a| a.origin = Some("borrowed".to_string())))
+            .unwrap_err();
+        assert!(err.to_string().contains("--force"), "{}", err);
+        assert!(wdb.edit(edit(&|a| a.origin_clear = true)).is_err());
+        assert_eq!(origin(&wdb).unwrap(), "(inherited pro kana 1)");
 
-        Ok(())
+        wdb.edit(edit(&|a| a.origin_note = Some(" via a loan ".to_string())))
+            .unwrap();
+        assert_eq!(origin(&wdb).unwrap(), "(inherited pro kana 1) via a loan");
+        wdb.edit(edit(&|a| {
+            a.origin_note = Some("semantic shift".to_string())
+        }))
+        .unwrap();
+        assert_eq!(
+            origin(&wdb).unwrap(),
+            "(inherited pro kana 1) semantic shift"
+        );
+
+        wdb.edit(edit(&|a| {
+            a.origin = Some("borrowed".to_string());
+            a.force = true;
+        }))
+        .unwrap();
+        assert_eq!(origin(&wdb).unwrap(), "borrowed");
+        // Only the origins recorded by `inherit` are protected
+        wdb.edit(edit(&|a| a.origin_clear = true)).unwrap();
+        let cleared: bool = wdb
+            .db
+            .query_row(
+                "SELECT origin IS NULL FROM words WHERE id = ?",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(cleared);
+        assert_eq!(wdb.word_history(id).unwrap().len(), 4);
+        assert_eq!(structured_origin("(inherited pro"), None);
     }
 
-    fn inherit(&mut self, args: InheritArgs) -> Result<()> {
-        let dest_lang = self.get_lang(&args.language)?;
-        if dest_lang.origin.is_none() {
-            bail!("There no parent language to inherit from!");
-        }
-        let src_lang = self.get_lang(dest_lang.origin.as_ref().unwrap())?;
-        let mut words: Vec<WordEntry> = Vec::new();
-        if args.word == "*" {
-            let mut stmt = self.db.prepare("SELECT * FROM words WHERE lang = ?")?;
-            words = stmt
-                .query_map(params![&src_lang.id], WordEntry::from_row)?
-                .collect::<Result<Vec<_>, _>>()?;
-        } else {
-            if let Some(word) = self.try_get_unique_word(&src_lang, &args.word)? {
-                words.push(word);
-            } else {
-                return Ok(());
-            }
-        }
+    #[test]
+    fn test_dedupe() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "dau", Some("pro"));
+        let first = add_word(&wdb, "pro", "ka", "fish", "n");
+        let dup = add_word(&wdb, "pro", "ka", "fish", "n");
+        // Homophones and words of other languages stay
+        add_word(&wdb, "pro", "ka", "fish", "v");
+        add_word(&wdb, "pro", "ka", "eel", "n");
+        add_word(&wdb, "dau", "ka", "fish", "n");
+        let dup2 = add_word(&wdb, "pro", "ka", "fish", "n");
+        let count = |wdb: &Wdb| -> u32 {
+            wdb.db
+                .query_row("SELECT COUNT(*) FROM words", [], |row| row.get(0))
+                .unwrap()
+        };
+        let ids = |words: Vec<WordEntry>| words.iter().map(|w| w.id).collect::<Vec<_>>();
 
-        let phon = words
-            .iter()
-            .map(|w| {
-                w.ipa.as_ref().map(|p| &p[..]).ok_or(anyhow!(
-                    "The inherited words must have a phonetic annotation"
-                ))
-            })
-            .collect::<Result<Vec<&str>>>()?;
-        println!("Applying sound changes..");
-        let evolved = LexurgyCmd::evolve(&dest_lang, LexurgyInput::Phonetic, LexurgyOutput::Both)
-            .run(&self.cfg, phon.into_iter())?;
-        if evolved.len() != words.len() {
-            bail!(
-                "Expected {} resulting word, got: {}",
-                words.len(),
-                evolved.len()
-            );
-        }
-        let tr = self.db.transaction()?;
-        for (word, output) in words.iter().zip(evolved.into_iter()) {
-            let (phon, rom) = output.get_phon_rom()?;
-            println!(
-                "  {} ({}) => {} ({})",
-                &word.romanization,
-                word.ipa.as_ref().unwrap(),
-                &rom,
-                &phon
-            );
-            let _ = tr.execute(
-                "INSERT INTO words
-                (lang, romanization, ipa, meaning, kind, note, origin, flags)
-                VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
-                params![
-                    dest_lang.id,
-                    rom,
-                    phon,
-                    args.meaning.as_ref().unwrap_or(&word.meaning),
-                    args.kind.as_ref().unwrap_or(&word.kind),
-                    args.note.as_ref().or(word.note.as_ref()),
-                    format!(
-                        "(inherited {} {} {})",
-                        src_lang.id, word.romanization, word.id
-                    ),
-                    word.flags
-                ],
-            )?;
-        }
-        let _ = tr.commit()?;
-        if self.cfg.settings.auto_dump {
-            self.dump(DumpArgs {
-                language: args.language,
-            })?;
-        }
-        Ok(())
+        assert_eq!(ids(wdb.duplicates(None).unwrap()), [dup, dup2]);
+        let dau = wdb.get_lang("dau").unwrap();
+        assert!(wdb.duplicates(Some(&dau)).unwrap().is_empty());
+
+        let args = |dry_run| DedupeArgs {
+            language: Some("pro".to_string()),
+            dry_run,
+        };
+        wdb.dedupe(args(true)).unwrap();
+        assert_eq!(count(&wdb), 6);
+        wdb.dedupe(args(false)).unwrap();
+        assert_eq!(count(&wdb), 4);
+        assert!(wdb.duplicates(None).unwrap().is_empty());
+        let pro = wdb.get_lang("pro").unwrap();
+        assert_eq!(wdb.homophones(&pro, "ka").unwrap()[0].id, first);
+        let deleted = wdb.word_history(dup).unwrap();
+        assert_eq!(
+            (deleted[0].old.as_deref(), deleted[0].new.as_deref()),
+            (Some("ka"), None)
+        );
+        assert!(wdb.word_history(first).unwrap().is_empty());
     }
 
-    fn check_missing_ipa(&mut self) -> Result<()> {
-        let mut stmt = self
-            .db
-            .prepare("SELECT * FROM words WHERE ipa IS NULL ORDER BY lang DESC, romanization")?;
-        let words = stmt
-            .query_map([], WordEntry::from_row)?
-            .collect::<rusqlite::Result<Vec<WordEntry>>>()?;
+    #[test]
+    fn test_ignore_case() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        let lang = wdb.get_lang("pro").unwrap();
+        let foo = add_word(&wdb, "pro", "foo", "fish", "n");
+        assert!(wdb.try_get_unique_word(&lang, "Foo").is_err());
+        assert!(wdb.homophones(&lang, "FOO").unwrap().is_empty());
 
-        if words.is_empty() {
-            return Ok(());
-        }
+        wdb.cfg.ignore_case = true;
+        let found = wdb.try_get_unique_word(&lang, "Foo").unwrap().unwrap();
+        assert_eq!(found.id, foo);
+        assert_eq!(wdb.homophones(&lang, "FOO").unwrap().len(), 1);
 
-        println!("\nNOTE: The following words are missing phonetic annotation:");
-        for lang_words in words.chunk_by(|w0, w1| w0.lang == w1.lang) {
-            println!("{}", self.get_lang(&lang_words[0].lang)?);
-            for word in lang_words {
-                println!("- {}: {} ({})", word.romanization, word.meaning, word.kind);
-            }
-        }
-        println!("\nRun `wdb phon` to generate phonetic annotations based on their romanization.");
-        Ok(())
+        // Words differing only in case are homophones then, listed and indexed together
+        let cap = add_word(&wdb, "pro", "Foo", "name", "n");
+        let err = wdb
+            .try_get_unique_word(&lang, "fOO")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(" 0:  foo: fish, n"), "{}", err);
+        assert!(err.contains(" 1:  Foo: name, n"), "{}", err);
+        let id = |rom| wdb.try_get_unique_word(&lang, rom).unwrap().unwrap().id;
+        // The word that was there first keeps its index
+        assert_eq!(id("FOO#0"), foo);
+        assert_eq!(id("foo#1"), cap);
+        assert!(wdb.try_get_unique_word(&lang, "foo#2").is_err());
+
+        let settings = parse_settings("db_file = \"wdb.db\"\ncase_insensitive_lookup = true\n");
+        let cfg = Config::new(env::temp_dir(), settings.unwrap(), false);
+        assert!(cfg.ignore_case);
+        let cli = Cli::try_parse_from(["wdb", "del", "pro", "Foo", "--ignore-case"]).unwrap();
+        assert!(cli.ignore_case);
     }
 
-    fn deromanize(&mut self, args: PhonArgs) -> Result<()> {
-        let languages = args
-            .language
-            .as_ref()
-            .map(|l| self.get_lang(l).map(|x| vec![x]))
-            .unwrap_or_else(|| self.get_langs())?;
-        let mut any_change = false;
-        for lang in languages {
-            let words: Vec<WordEntry> = {
-                let mut stmt = self.db.prepare(&format!(
-                    "SELECT * FROM words WHERE lang = ? {}",
-                    if args.force { "" } else { "AND ipa IS NULL" }
-                ))?;
-                let ws = stmt
-                    .query_map([&lang.id], WordEntry::from_row)?
-                    .collect::<Result<_, _>>()?;
-                ws
-            };
+    #[test]
+    fn test_working_lang() {
+        let dir = TempDir::new("working-lang");
+        let mut wdb = test_wdb();
+        wdb.cfg = Config::new(dir.to_path_buf(), test_settings(), false);
+        add_lang(&wdb, "eng", None);
+        add_lang(&wdb, "old", None);
+        let use_lang = |language: Option<&str>, clear| UseArgs {
+            language: language.map(str::to_string),
+            clear,
+        };
+        assert!(wdb.use_lang(use_lang(Some("nope"), false)).is_err());
+        wdb.use_lang(use_lang(Some("en"), false)).unwrap();
+        assert_eq!(working_lang(&dir).as_deref(), Some("eng"));
 
-            if words.is_empty() {
-                continue;
-            }
+        let langs = wdb.get_langs().unwrap();
+        let line = |line: &str| {
+            let mut args = line.split(' ').map(str::to_string).collect::<Vec<_>>();
+            let inserted = insert_working_lang(&mut args, "eng", &langs);
+            (args.join(" "), inserted)
+        };
+        assert_eq!(
+            line("wdb add foo fish n"),
+            ("wdb add eng foo fish n".to_string(), true)
+        );
+        assert!(!line("wdb add old foo fish n").1);
+        // A unique prefix picks its language like everywhere else
+        assert!(!line("wdb add ol kaz fish n").1);
+        // Even when it was meant as the word, so that needs the language spelled out
+        assert_eq!(
+            line("wdb add e fish n"),
+            ("wdb add e fish n".to_string(), false)
+        );
+        assert!(!line("wdb add eng e fish n").1);
+        assert_eq!(line("wdb --db x.db del foo").0, "wdb --db x.db del eng foo");
+        assert_eq!(
+            line("wdb add -o old foo fish n").0,
+            "wdb add -o old eng foo fish n"
+        );
+        assert_eq!(line("wdb add -H foo fish n").0, "wdb add -H eng foo fish n");
+        assert_eq!(line("wdb dump").0, "wdb dump eng");
+        assert_eq!(
+            line("wdb dump --dictionary-only").0,
+            "wdb dump --dictionary-only eng"
+        );
+        // Commands without a language, or with it as an option, are left alone
+        assert!(!line("wdb tree").1);
+        assert!(!line("wdb phon foo").1);
+        assert!(!line("wdb use old").1);
 
-            any_change = true;
+        let (args, _) = line("wdb add foo fish n -D");
+        let Some(Command::Add(args)) = Cli::try_parse_from(args.split(' ')).unwrap().command else {
+            panic!("Expected an add command");
+        };
+        wdb.add_word(args, None).unwrap();
+        let eng = wdb.get_lang("eng").unwrap();
+        assert_eq!(wdb.homophones(&eng, "foo").unwrap()[0].meaning, "fish");
 
-            let lexurgy = LexurgyCmd::deromanize(&lang);
-            println!("Running `{}` deromanization rule...", &lang.rule);
-            let phons = lexurgy
-                .run(&self.cfg, words.iter().map(|w| &w.romanization[..]))?
-                .into_iter()
-                .map(|o| o.get_phon())
-                .collect::<Result<Vec<_>>>()?;
-            if phons.len() != words.len() {
-                println!(
-                    "Number of words out ({}) doesn't match number of words in({})!'",
-                    phons.len(),
-                    words.len()
-                );
-            }
-            for (word, phon) in words.iter().zip(phons.iter()) {
-                println!(" {} => {}", &word.romanization, phon);
-            }
+        wdb.use_lang(use_lang(None, true)).unwrap();
+        assert_eq!(working_lang(&dir), None);
+    }
 
-            let mut write_phons = || {
-                let tr = self.db.transaction()?;
-                for (word, phon) in words.iter().zip(phons.iter()) {
-                    tr.execute(
-                        "UPDATE words SET ipa = ? WHERE id = ?",
-                        params![phon, word.id],
-                    )?;
-                }
-                tr.commit()
-            };
+    #[test]
+    fn test_prune() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        add_lang(&wdb, "late", Some("mid"));
+        add_lang(&wdb, "stub", Some("pro"));
+        add_lang(&wdb, "iso", None);
+        add_lang(&wdb, "full", Some("pro"));
+        add_word(&wdb, "late", "ka", "fish", "n");
+        add_word(&wdb, "full", "ta", "stone", "n");
+        let ids = |langs: Vec<LangEntry>| langs.into_iter().map(|l| l.id).collect::<Vec<_>>();
 
-            if let Err(err) = write_phons() {
-                bail!(
-                    "failed to update phonetic annotation. No words changed.\n{}",
-                    err
-                );
-            } else {
-                println!("Updated {} word entries", words.len());
-                if words.len() != 0 {
-                    if self.cfg.settings.auto_dump {
-                        self.dump(DumpArgs { language: lang.id })?;
-                    }
-                }
-            }
-        }
+        // `pro` and `mid` have no words, but `late` descends from them
+        assert_eq!(ids(wdb.prunable_langs().unwrap()), ["iso", "stub"]);
+        wdb.prune(PruneArgs { dry_run: true }).unwrap();
+        assert_eq!(ids(wdb.get_langs().unwrap()).len(), 6);
 
-        if !any_change {
-            println!("No word updated, every word present has a phonetic annotation.\nIf you want to update all anyway, use the -f flag.");
-        }
-        Ok(())
+        wdb.prune(PruneArgs { dry_run: false }).unwrap();
+        let mut left = ids(wdb.get_langs().unwrap());
+        left.sort();
+        assert_eq!(left, ["full", "late", "mid", "pro"]);
+        assert!(wdb.get_lang("stub").is_err());
+        assert!(wdb.prunable_langs().unwrap().is_empty());
     }
-}
 
-fn find_obsidian_root() -> Result<PathBuf> {
-    let cur = env::current_dir()?;
-    let mut obsidian = PathBuf::new();
-    for root in cur.ancestors() {
-        obsidian.clear();
-        obsidian.push(root);
-        obsidian.push(".obsidian");
-        if obsidian.exists() {
-            return Ok(root.into());
+    #[test]
+    fn test_suggest_ranking() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        for rom in ["tana", "kanak", "kana", "kan", "mana", "bo", "kanaka"] {
+            add_word(&wdb, "pro", rom, rom, "n");
         }
+        let lang = wdb.get_lang("pro").unwrap();
+        let suggest = |query: &str, max_distance: usize, limit: usize| {
+            wdb.suggest(&lang, query, max_distance, limit)
+                .unwrap()
+                .into_iter()
+                .map(|(w, d)| format!("{}:{}", w.romanization, d))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            suggest("kana", 2, 10),
+            ["kana:0", "kan:1", "kanak:1", "mana:1", "tana:1", "kanaka:2"]
+        );
+        assert_eq!(suggest("kana", 1, 3), ["kana:0", "kan:1", "kanak:1"]);
+        assert!(suggest("xyz", 1, 10).is_empty());
     }
-    bail!("you must run this command from inside of an Obsidian vault!");
-}
 
-fn load_settings(root: &path::Path) -> Result<Settings> {
-    Ok(toml::from_str(
-        &fs::read_to_string(root.join("Wdb.toml")).expect("No `Wdb.toml` settings file present"),
-    )?)
-}
+    #[test]
+    fn test_error_report() {
+        let wdb = test_wdb();
+        let err = wdb
+            .resolve_lang("xyz")
+            .context("Dumping `xyz`")
+            .unwrap_err();
+        let json = serde_json::to_string(&ErrorReport::new(&err)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"error":"Dumping `xyz`","causes":["No such language: `xyz`"]}"#
+        );
+    }
 
-fn main() -> Result<()> {
-    let mut cli = Cli::parse();
-    let root = find_obsidian_root()?;
-    let settings = load_settings(&root)?;
-    let cfg = Config::new(root, settings, cli.debug_mode | cfg!(debug_assertions));
-    if cfg.debug_mode {
-        println!(
-            "NOTE: Running in debug, changes are done to the `{}` instead of `{}`\n",
-            &cfg.settings.db_dev_file, &cfg.settings.db_file,
+    #[test]
+    fn test_resolve_lang() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "en", None);
+        add_lang(&wdb, "eng", Some("en"));
+        add_lang(&wdb, "eo", Some("en"));
+        add_lang(&wdb, "deu", Some("en"));
+
+        // An exact match wins even though it's also a prefix of `eng`
+        assert_eq!(wdb.resolve_lang("en").unwrap().id, "en");
+        assert_eq!(wdb.resolve_lang("eng").unwrap().id, "eng");
+        assert_eq!(wdb.resolve_lang("d").unwrap().id, "deu");
+        assert_eq!(
+            wdb.resolve_lang("e").unwrap_err().to_string(),
+            "`e` is ambiguous, it could be any of: en, eng, eo"
+        );
+        assert_eq!(
+            wdb.resolve_lang("x").unwrap_err().to_string(),
+            "No such language: `x`"
         );
     }
 
-    let mut wdb = Wdb::new(cfg)?;
-    let mut cmd = cli.command;
-    let interactive = if let Some(Command::Interactive) = cmd {
-        true
-    } else {
-        false
-    };
-    let mut buf = String::new();
+    #[test]
+    fn test_lang_info() {
+        let wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "mid", Some("pro"));
+        add_lang(&wdb, "b", Some("mid"));
+        add_lang(&wdb, "a", Some("mid"));
+        add_lang(&wdb, "late", Some("a"));
+        add_word(&wdb, "mid", "ka", "fish", "n");
+        add_word(&wdb, "mid", "ta", "tree", "n");
+        let info = |parents, children| {
+            wdb.lang_info(&LangInfoArgs {
+                language: "mid".to_string(),
+                parents,
+                children,
+            })
+            .unwrap()
+        };
+        let rule_file = wdb.cfg.rule_file(&wdb.get_lang("mid").unwrap());
 
-    loop {
-        match cmd {
-            Some(Command::Dump(args)) => wdb.dump(args)?,
-            Some(Command::List) => wdb.list()?,
-            Some(Command::Add(args)) => wdb.add(args)?,
-            Some(Command::Edit(args)) => wdb.edit(args)?,
-            Some(Command::Del(args)) => wdb.del(args)?,
-            Some(Command::Evolve(args)) => wdb.evolve(args)?,
-            Some(Command::Inherit(args)) => wdb.inherit(args)?,
-            Some(Command::Phon(args)) => {
-                cli.disable_checks = args.language.is_none();
-                wdb.deromanize(args)?
-            }
-            _ => {}
-        }
-        if !interactive {
-            break;
-        }
-        loop {
-            std::io::stdin().read_line(&mut buf)?;
-            match Cli::try_parse_from(buf.split(' ')) {
-                Err(err) => {
-                    println!("Failed to parse command: {:?}", err)
-                }
-                Ok(Cli { command: c, .. }) => {
-                    cmd = c;
-                    break;
-                }
-            }
-        }
+        assert_eq!(
+            info(false, false),
+            format!(
+                "MID (mid)\n Rule file: {}\n Words: 2\n Parent: PRO (pro)\n Children: A (a), B (b)\n",
+                rule_file.display()
+            )
+        );
+        let info = info(true, true);
+        let lines = info.lines().skip(3).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                " Ancestors: PRO (pro)",
+                " Descendants: A (a), B (b), LATE (late)"
+            ]
+        );
+
+        let late = wdb
+            .lang_info(&LangInfoArgs {
+                language: "late".to_string(),
+                parents: true,
+                children: false,
+            })
+            .unwrap();
+        assert!(late.ends_with(" Ancestors: A (a) <- MID (mid) <- PRO (pro)\n Children: <none>\n"));
     }
 
-    if !cli.disable_checks {
-        wdb.check_missing_ipa()?;
+    #[test]
+    fn test_cyclic_origins() {
+        let mut wdb = test_wdb();
+        add_lang(&wdb, "pro", None);
+        add_lang(&wdb, "a", Some("b"));
+        add_lang(&wdb, "b", Some("a"));
+
+        let err = validate_family(&wdb.get_langs().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("a -> b -> a"), "{}", err);
+
+        let err = wdb
+            .evolve(evolve_args("pro", "a", "ana"), &mut io::sink())
+            .unwrap_err();
+        assert!(err.to_string().contains("cyclic origin"), "{}", err);
     }
-    Ok(())
 }